@@ -0,0 +1,771 @@
+//! The AST module
+
+use std::fmt;
+
+/// The type of a variable or expression.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Type {
+    Float,
+    Int,
+    Str,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Float => write!(f, "float"),
+            Type::Int => write!(f, "int"),
+            Type::Str => write!(f, "string"),
+        }
+    }
+}
+
+/// A position in the source, for reporting errors found after parsing.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+/// The stretch of source a node was parsed from, from the start of its first
+/// token to just past the end of its last.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos,
+}
+
+impl Span {
+    pub fn new(start: Pos, end: Pos) -> Self {
+        Span { start, end }
+    }
+
+    /// The smallest span covering both spans, e.g. that of a binary
+    /// expression from the spans of its operands.
+    pub fn merge(self, other: Span) -> Span {
+        let key = |pos: Pos| (pos.line, pos.col);
+
+        Span {
+            start: if key(other.start) < key(self.start) {
+                other.start
+            } else {
+                self.start
+            },
+            end: if key(other.end) > key(self.end) {
+                other.end
+            } else {
+                self.end
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UnaryOp {
+    Plus,
+    Minus,
+    Not,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Eq,
+    NotEq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+    And,
+    Or,
+}
+
+impl UnaryOp {
+    /// The source spelling of the operator.
+    pub fn spelling(&self) -> &'static str {
+        match self {
+            UnaryOp::Plus => "+",
+            UnaryOp::Minus => "-",
+            UnaryOp::Not => "NOT",
+        }
+    }
+}
+
+impl BinaryOp {
+    /// The source (and C) spelling of the operator.
+    pub fn spelling(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Pow => "^",
+            BinaryOp::Eq => "==",
+            BinaryOp::NotEq => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Lte => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Gte => ">=",
+            BinaryOp::And => "AND",
+            BinaryOp::Or => "OR",
+        }
+    }
+
+    /// Whether the operator compares its operands, e.g. `<`.
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Eq
+                | BinaryOp::NotEq
+                | BinaryOp::Lt
+                | BinaryOp::Lte
+                | BinaryOp::Gt
+                | BinaryOp::Gte
+        )
+    }
+}
+
+/// A function built into the language, called as `NAME(args)`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Builtin {
+    /// `RANDOM(lo, hi)`, a random integer between `lo` and `hi` inclusive.
+    Random,
+    Sqrt,
+    Abs,
+    Sin,
+    Cos,
+    Floor,
+    /// `LEN(s)`, the number of characters in a string.
+    Len,
+    /// `ORD(s)`, the code of the first character of a string, or 0 for an
+    /// empty one.
+    Ord,
+    /// `CHR(n)`, the one-character string with the ASCII code `n`.
+    Chr,
+}
+
+impl Builtin {
+    /// The builtin with the given source spelling, if any.
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        match name {
+            "RANDOM" => Some(Builtin::Random),
+            "SQRT" => Some(Builtin::Sqrt),
+            "ABS" => Some(Builtin::Abs),
+            "SIN" => Some(Builtin::Sin),
+            "COS" => Some(Builtin::Cos),
+            "FLOOR" => Some(Builtin::Floor),
+            "LEN" => Some(Builtin::Len),
+            "ORD" => Some(Builtin::Ord),
+            "CHR" => Some(Builtin::Chr),
+            _ => None,
+        }
+    }
+
+    /// The source spelling of the builtin.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Random => "RANDOM",
+            Builtin::Sqrt => "SQRT",
+            Builtin::Abs => "ABS",
+            Builtin::Sin => "SIN",
+            Builtin::Cos => "COS",
+            Builtin::Floor => "FLOOR",
+            Builtin::Len => "LEN",
+            Builtin::Ord => "ORD",
+            Builtin::Chr => "CHR",
+        }
+    }
+
+    /// How many arguments the builtin takes.
+    pub fn arity(&self) -> usize {
+        match self {
+            Builtin::Random => 2,
+            _ => 1,
+        }
+    }
+
+    /// Whether the builtin takes a string rather than numbers.
+    pub fn takes_string(&self) -> bool {
+        matches!(self, Builtin::Len | Builtin::Ord)
+    }
+
+    /// The type of the builtin's result.
+    pub fn result_type(&self) -> Type {
+        match self {
+            Builtin::Random | Builtin::Len | Builtin::Ord => Type::Int,
+            Builtin::Chr => Type::Str,
+            Builtin::Sqrt | Builtin::Abs | Builtin::Sin | Builtin::Cos | Builtin::Floor => {
+                Type::Float
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Expr {
+    Number(String, Span),
+    Str(String, Span),
+    Var(String, Span),
+    Unary {
+        op: UnaryOp,
+        /// The position of the operator.
+        pos: Pos,
+        span: Span,
+        expr: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        /// The position of the operator.
+        pos: Pos,
+        span: Span,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    Call {
+        func: Builtin,
+        args: Vec<Expr>,
+        span: Span,
+    },
+}
+
+impl Expr {
+    /// Where the expression is in the source, including any parentheses
+    /// around it.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Number(_, span) | Expr::Str(_, span) | Expr::Var(_, span) => *span,
+            Expr::Unary { span, .. } | Expr::Binary { span, .. } | Expr::Call { span, .. } => *span,
+        }
+    }
+
+    /// Whether the expression is a condition, i.e. a comparison or a logical
+    /// operation, whose value is 1 if it holds and 0 if it doesn't.
+    pub fn is_condition(&self) -> bool {
+        match self {
+            Expr::Unary {
+                op: UnaryOp::Not, ..
+            } => true,
+            Expr::Binary { op, .. } => {
+                op.is_comparison() || matches!(op, BinaryOp::And | BinaryOp::Or)
+            }
+            _ => false,
+        }
+    }
+
+    /// The binary operations down the left of the expression, from the
+    /// innermost out, after the operand they start from: for `a - b + c`,
+    /// `a`, then `a - b` and `a - b + c`.
+    ///
+    /// The parser builds a flat chain such as `1 + 1 + ... + 1` without
+    /// limiting its length, so it nests as deeply as it's long. Passes walk
+    /// these chains in a loop rather than recursing into each `lhs`; anything
+    /// else in an expression only nests as deeply as the parser allows.
+    pub fn left_chain(&self) -> (&Expr, Vec<Link<'_>>) {
+        let mut links = Vec::new();
+        let mut expr = self;
+        while let Expr::Binary {
+            op, pos, lhs, rhs, ..
+        } = expr
+        {
+            links.push(Link {
+                expr,
+                op: *op,
+                pos: *pos,
+                rhs,
+            });
+            expr = lhs;
+        }
+        links.reverse();
+        (expr, links)
+    }
+
+    /// An expression to leave behind when taking another apart, which owns
+    /// nothing.
+    pub fn placeholder() -> Expr {
+        Expr::Number(String::new(), Span::default())
+    }
+}
+
+/// A binary operation in a chain of them down the left of an expression, as
+/// given by `Expr::left_chain`.
+pub struct Link<'a> {
+    /// The whole operation, including the operations to its left.
+    pub expr: &'a Expr,
+    pub op: BinaryOp,
+    pub pos: Pos,
+    pub rhs: &'a Expr,
+}
+
+/// Cloned a link at a time down a chain of binary operations, as the derived
+/// `Clone` would recurse once for each link.
+impl Clone for Expr {
+    fn clone(&self) -> Self {
+        let (first, links) = self.left_chain();
+        let mut clone = match first {
+            Expr::Number(value, span) => Expr::Number(value.clone(), *span),
+            Expr::Str(value, span) => Expr::Str(value.clone(), *span),
+            Expr::Var(name, span) => Expr::Var(name.clone(), *span),
+            Expr::Unary {
+                op,
+                pos,
+                span,
+                expr,
+            } => Expr::Unary {
+                op: *op,
+                pos: *pos,
+                span: *span,
+                expr: expr.clone(),
+            },
+            Expr::Call { func, args, span } => Expr::Call {
+                func: *func,
+                args: args.clone(),
+                span: *span,
+            },
+            Expr::Binary { .. } => unreachable!("the chain starts after any binary operation"),
+        };
+
+        for link in links {
+            clone = Expr::Binary {
+                op: link.op,
+                pos: link.pos,
+                span: link.expr.span(),
+                lhs: Box::new(clone),
+                rhs: Box::new(link.rhs.clone()),
+            };
+        }
+        clone
+    }
+}
+
+/// Dropped a link at a time down a chain of binary operations, for the same
+/// reason.
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let Expr::Binary { lhs, .. } = self else {
+            return;
+        };
+
+        let mut next = std::mem::replace(&mut **lhs, Expr::placeholder());
+        while let Expr::Binary { lhs, .. } = &mut next {
+            // `next` is dropped with nothing left to its left
+            next = std::mem::replace(&mut **lhs, Expr::placeholder());
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stmt {
+    /// The items to print, separated by spaces on a single line.
+    Print(Vec<Expr>, Span),
+    /// Like `Print`, but without the trailing newline.
+    Write(Vec<Expr>, Span),
+    /// Stops the program with an error if the condition doesn't hold.
+    Assert(Expr, Span),
+    /// Ends the program, with the value as its exit status.
+    Return(Expr, Span),
+    /// Ends the program wherever it's run from, with the value as its exit
+    /// status, or 0 without one.
+    Exit(Option<Expr>, Span),
+    If {
+        /// The `IF` branch followed by any `ELSEIF` branches, in order.
+        branches: Vec<(Expr, Vec<Stmt>)>,
+        else_body: Option<Vec<Stmt>>,
+        span: Span,
+    },
+    While {
+        cond: Expr,
+        body: Vec<Stmt>,
+        span: Span,
+    },
+    /// A loop that tests its condition after each run of the body, so the
+    /// body always runs at least once.
+    DoWhile {
+        body: Vec<Stmt>,
+        cond: Expr,
+        span: Span,
+    },
+    Label(String, Span),
+    Goto(String, Span),
+    /// A line of C, passed through to the generated code as it is.
+    Emit(String, Span),
+    /// Exchanges the values of two numeric variables of the same type.
+    Swap(String, String, Span),
+    /// Leaves the innermost loop.
+    Break(Span),
+    /// Skips to the next iteration of the innermost loop.
+    Continue(Span),
+    Let {
+        name: String,
+        value: Expr,
+        /// The comment lines directly above the statement, if any.
+        doc: Option<String>,
+        span: Span,
+    },
+    Int {
+        name: String,
+        value: Expr,
+        span: Span,
+    },
+    /// A named value that can't be reassigned. Its value only uses numbers
+    /// and other constants.
+    Const {
+        name: String,
+        value: Expr,
+        span: Span,
+    },
+    /// Reassignment of an already declared variable, without `LET`.
+    Assign {
+        name: String,
+        value: Expr,
+        span: Span,
+    },
+    /// `name op= value`, as in `x += 1`, which stores `name op value` back in
+    /// an already declared variable.
+    CompoundAssign {
+        name: String,
+        op: BinaryOp,
+        /// The position of the operator.
+        pos: Pos,
+        value: Expr,
+        span: Span,
+    },
+    Input {
+        prompt: Option<String>,
+        name: String,
+        /// The comment lines directly above the statement, if any.
+        doc: Option<String>,
+        span: Span,
+    },
+    Comment(String, Span),
+}
+
+impl Stmt {
+    /// The value a `CompoundAssign` stores, i.e. `name op value` for
+    /// `name op= value`.
+    pub fn compound_value(name: &str, op: BinaryOp, pos: Pos, value: &Expr) -> Expr {
+        let span = value.span();
+        Expr::Binary {
+            op,
+            pos,
+            span,
+            lhs: Box::new(Expr::Var(name.to_string(), span)),
+            rhs: Box::new(value.clone()),
+        }
+    }
+
+    /// Where the statement is in the source, up to but not including the
+    /// newline that ends it. A block statement runs to the end of its closing
+    /// keyword.
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Print(_, span)
+            | Stmt::Write(_, span)
+            | Stmt::Assert(_, span)
+            | Stmt::Return(_, span)
+            | Stmt::Exit(_, span)
+            | Stmt::Label(_, span)
+            | Stmt::Goto(_, span)
+            | Stmt::Emit(_, span)
+            | Stmt::Swap(_, _, span)
+            | Stmt::Break(span)
+            | Stmt::Continue(span)
+            | Stmt::Comment(_, span) => *span,
+            Stmt::If { span, .. }
+            | Stmt::While { span, .. }
+            | Stmt::DoWhile { span, .. }
+            | Stmt::Let { span, .. }
+            | Stmt::Int { span, .. }
+            | Stmt::Const { span, .. }
+            | Stmt::Assign { span, .. }
+            | Stmt::CompoundAssign { span, .. }
+            | Stmt::Input { span, .. } => *span,
+        }
+    }
+}
+
+/// Pretty-prints a program as an indented tree, one node per line with its
+/// children below it, for `--dump-ast`.
+pub fn dump(program: &[Stmt]) -> String {
+    let mut out = String::new();
+    dump_block(&mut out, program, 0);
+    out
+}
+
+/// Resets every position and span in a program to the default, so that the
+/// trees parsed from differently laid out source compare equal.
+pub fn clear_positions(program: &mut [Stmt]) {
+    for stmt in program {
+        clear_stmt(stmt);
+    }
+}
+
+fn clear_stmt(stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Print(items, span) | Stmt::Write(items, span) => {
+            items.iter_mut().for_each(clear_expr);
+            *span = Span::default();
+        }
+
+        Stmt::Assert(value, span) | Stmt::Return(value, span) => {
+            clear_expr(value);
+            *span = Span::default();
+        }
+
+        Stmt::Exit(value, span) => {
+            value.iter_mut().for_each(clear_expr);
+            *span = Span::default();
+        }
+
+        Stmt::If {
+            branches,
+            else_body,
+            span,
+        } => {
+            for (cond, body) in branches {
+                clear_expr(cond);
+                clear_positions(body);
+            }
+            if let Some(body) = else_body {
+                clear_positions(body);
+            }
+            *span = Span::default();
+        }
+
+        Stmt::While { cond, body, span } | Stmt::DoWhile { body, cond, span } => {
+            clear_expr(cond);
+            clear_positions(body);
+            *span = Span::default();
+        }
+
+        Stmt::Let { value, span, .. }
+        | Stmt::Int { value, span, .. }
+        | Stmt::Const { value, span, .. }
+        | Stmt::Assign { value, span, .. } => {
+            clear_expr(value);
+            *span = Span::default();
+        }
+
+        Stmt::CompoundAssign {
+            pos, value, span, ..
+        } => {
+            clear_expr(value);
+            *pos = Pos::default();
+            *span = Span::default();
+        }
+
+        Stmt::Label(_, span)
+        | Stmt::Goto(_, span)
+        | Stmt::Emit(_, span)
+        | Stmt::Swap(_, _, span)
+        | Stmt::Break(span)
+        | Stmt::Continue(span)
+        | Stmt::Input { span, .. }
+        | Stmt::Comment(_, span) => *span = Span::default(),
+    }
+}
+
+/// Clears an expression's positions, going down a chain of binary operations
+/// in a loop, as passes do.
+fn clear_expr(mut expr: &mut Expr) {
+    loop {
+        match expr {
+            Expr::Number(_, span) | Expr::Str(_, span) | Expr::Var(_, span) => {
+                *span = Span::default();
+                return;
+            }
+
+            Expr::Unary {
+                pos, span, expr, ..
+            } => {
+                *pos = Pos::default();
+                *span = Span::default();
+                clear_expr(expr);
+                return;
+            }
+
+            Expr::Call { args, span, .. } => {
+                args.iter_mut().for_each(clear_expr);
+                *span = Span::default();
+                return;
+            }
+
+            Expr::Binary {
+                pos,
+                span,
+                lhs,
+                rhs,
+                ..
+            } => {
+                *pos = Pos::default();
+                *span = Span::default();
+                clear_expr(rhs);
+                expr = lhs;
+            }
+        }
+    }
+}
+
+fn dump_line(out: &mut String, depth: usize, text: &str) {
+    out.push_str(&"  ".repeat(depth));
+    out.push_str(text);
+    out.push('\n');
+}
+
+fn dump_block(out: &mut String, stmts: &[Stmt], depth: usize) {
+    for stmt in stmts {
+        dump_stmt(out, stmt, depth);
+    }
+}
+
+fn dump_stmt(out: &mut String, stmt: &Stmt, depth: usize) {
+    match stmt {
+        Stmt::Print(items, _) | Stmt::Write(items, _) => {
+            let kind = if matches!(stmt, Stmt::Print(..)) {
+                "Print"
+            } else {
+                "Write"
+            };
+            dump_line(out, depth, kind);
+            for item in items {
+                dump_expr(out, item, depth + 1);
+            }
+        }
+
+        Stmt::Assert(cond, _) => {
+            dump_line(out, depth, "Assert");
+            dump_expr(out, cond, depth + 1);
+        }
+
+        Stmt::Return(value, _) => {
+            dump_line(out, depth, "Return");
+            dump_expr(out, value, depth + 1);
+        }
+
+        Stmt::Exit(value, _) => {
+            dump_line(out, depth, "Exit");
+            if let Some(value) = value {
+                dump_expr(out, value, depth + 1);
+            }
+        }
+
+        Stmt::If {
+            branches,
+            else_body,
+            ..
+        } => {
+            dump_line(out, depth, "If");
+            for (cond, body) in branches {
+                dump_line(out, depth + 1, "Cond");
+                dump_expr(out, cond, depth + 2);
+                dump_line(out, depth + 1, "Then");
+                dump_block(out, body, depth + 2);
+            }
+            if let Some(body) = else_body {
+                dump_line(out, depth + 1, "Else");
+                dump_block(out, body, depth + 2);
+            }
+        }
+
+        Stmt::While { cond, body, .. } => {
+            dump_line(out, depth, "While");
+            dump_line(out, depth + 1, "Cond");
+            dump_expr(out, cond, depth + 2);
+            dump_line(out, depth + 1, "Body");
+            dump_block(out, body, depth + 2);
+        }
+
+        Stmt::DoWhile { body, cond, .. } => {
+            dump_line(out, depth, "DoWhile");
+            dump_line(out, depth + 1, "Body");
+            dump_block(out, body, depth + 2);
+            dump_line(out, depth + 1, "Cond");
+            dump_expr(out, cond, depth + 2);
+        }
+
+        Stmt::Label(name, _) => dump_line(out, depth, &format!("Label {}", name)),
+
+        Stmt::Goto(name, _) => dump_line(out, depth, &format!("Goto {}", name)),
+
+        Stmt::Emit(code, _) => dump_line(out, depth, &format!("Emit {:?}", code)),
+
+        Stmt::Swap(first, second, _) => {
+            dump_line(out, depth, &format!("Swap {} {}", first, second))
+        }
+
+        Stmt::Break(_) => dump_line(out, depth, "Break"),
+
+        Stmt::Continue(_) => dump_line(out, depth, "Continue"),
+
+        Stmt::Let { name, value, .. }
+        | Stmt::Int { name, value, .. }
+        | Stmt::Const { name, value, .. }
+        | Stmt::Assign { name, value, .. } => {
+            let kind = match stmt {
+                Stmt::Let { .. } => "Let",
+                Stmt::Int { .. } => "Int",
+                Stmt::Const { .. } => "Const",
+                _ => "Assign",
+            };
+            dump_line(out, depth, &format!("{} {}", kind, name));
+            dump_expr(out, value, depth + 1);
+        }
+
+        Stmt::CompoundAssign {
+            name, op, value, ..
+        } => {
+            dump_line(
+                out,
+                depth,
+                &format!("CompoundAssign {} {}=", name, op.spelling()),
+            );
+            dump_expr(out, value, depth + 1);
+        }
+
+        Stmt::Input { prompt, name, .. } => match prompt {
+            Some(prompt) => dump_line(out, depth, &format!("Input {} {:?}", name, prompt)),
+            None => dump_line(out, depth, &format!("Input {}", name)),
+        },
+
+        Stmt::Comment(text, _) => dump_line(out, depth, &format!("Comment {:?}", text)),
+    }
+}
+
+fn dump_expr(out: &mut String, expr: &Expr, depth: usize) {
+    match expr {
+        Expr::Number(value, _) => dump_line(out, depth, &format!("Number {}", value)),
+
+        Expr::Str(value, _) => dump_line(out, depth, &format!("Str {:?}", value)),
+
+        Expr::Var(name, _) => dump_line(out, depth, &format!("Var {}", name)),
+
+        Expr::Unary { op, expr, .. } => {
+            dump_line(out, depth, &format!("Unary {}", op.spelling()));
+            dump_expr(out, expr, depth + 1);
+        }
+
+        // each operation in the chain is the left operand of the next one out
+        Expr::Binary { .. } => {
+            let (first, links) = expr.left_chain();
+            for (i, link) in links.iter().rev().enumerate() {
+                dump_line(out, depth + i, &format!("Binary {}", link.op.spelling()));
+            }
+            dump_expr(out, first, depth + links.len());
+            for (i, link) in links.iter().enumerate() {
+                dump_expr(out, link.rhs, depth + links.len() - i);
+            }
+        }
+
+        Expr::Call { func, args, .. } => {
+            dump_line(out, depth, &format!("Call {}", func.name()));
+            for arg in args {
+                dump_expr(out, arg, depth + 1);
+            }
+        }
+    }
+}
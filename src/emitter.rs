@@ -1,43 +1,1711 @@
 //! The Emitter module
 
+use crate::ast::{BinaryOp, Builtin, Expr, Pos, Stmt, Type, UnaryOp};
+use crate::symbols::{Symbol, SymbolTable};
+use crate::typecheck;
 use crate::GenResult;
+use std::collections::BTreeSet;
 use std::fs::File;
 use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-pub struct Emitter {
-    outfile: &'static str,
+/// Encodes a decoded string literal so that it can be placed verbatim inside
+/// a C string literal. Other control characters are written as three-digit
+/// octal escapes, which unlike `\x` can't run on into a following digit.
+pub fn escape_string(value: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in value.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            c if c.is_ascii_control() => escaped.push_str(&format!("\\{:03o}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Encodes a decoded string literal so that it can be placed verbatim inside
+/// the format string of a C `printf` call.
+pub fn escape_format_string(value: &str) -> String {
+    escape_string(value).replace('%', "%%")
+}
+
+/// Spells a Teeny Tiny identifier as a valid C identifier. ASCII names are
+/// kept as they are, and every other character becomes `_u<hex>_`, which
+/// can't clash with another name since Teeny Tiny identifiers have no `_`.
+pub fn c_identifier(name: &str) -> String {
+    let mut mangled = String::new();
+
+    for c in name.chars() {
+        if c.is_ascii() {
+            mangled.push(c);
+        } else {
+            mangled.push_str(&format!("_u{:x}_", c as u32));
+        }
+    }
+
+    mangled
+}
+
+/// The lines of a `/** ... */` comment documenting a declaration, with one
+/// line per line of `doc`.
+pub fn doc_comment(doc: &str) -> Vec<String> {
+    let doc = doc.replace("*/", "* /");
+    let lines = doc.lines().collect::<Vec<_>>();
+
+    match lines[..] {
+        [line] => vec![format!("/** {} */", line)],
+        _ => std::iter::once("/**".to_string())
+            .chain(
+                lines
+                    .iter()
+                    .map(|line| format!(" * {}", line).trim_end().to_string()),
+            )
+            .chain(std::iter::once(" */".to_string()))
+            .collect(),
+    }
+}
+
+/// The binding strength of a binary operator in C, used to decide where the
+/// generated code needs parentheses.
+fn precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Or => 1,
+        BinaryOp::And => 2,
+        BinaryOp::Eq | BinaryOp::NotEq => 3,
+        BinaryOp::Lt | BinaryOp::Lte | BinaryOp::Gt | BinaryOp::Gte => 4,
+        BinaryOp::Add | BinaryOp::Sub => 5,
+        BinaryOp::Mul | BinaryOp::Div => 6,
+        // lowered to calls to fmod and pow(f), so never need parentheses
+        BinaryOp::Mod | BinaryOp::Pow => 7,
+    }
+}
+
+/// A code generator for one target language. The program handed to
+/// `emit_program` has already been parsed and type checked.
+pub trait Emitter {
+    /// Appends raw code, without a newline or indentation.
+    fn emit(&mut self, code: &str);
+
+    /// Emits a line of code at the current indentation.
+    fn emit_line(&mut self, code: &str);
+
+    /// Emits a line ahead of the code, e.g. a declaration.
+    fn header_line(&mut self, code: &str);
+
+    /// Generates code for a whole program. Fails if the program uses a
+    /// construct the target cannot express.
+    fn emit_program(&mut self, program: &[Stmt]) -> GenResult<()>;
+
+    /// The code generated so far.
+    fn output(&self) -> String;
+
+    /// Consumes the emitter, returning the generated code.
+    fn finish(self) -> String
+    where
+        Self: Sized,
+    {
+        self.output()
+    }
+}
+
+/// The default number of spaces per level of indentation in the generated C.
+pub const DEFAULT_INDENT_WIDTH: usize = 4;
+
+/// The name of the function the generated C wraps the program in.
+pub const DEFAULT_FUNCTION_NAME: &str = "main";
+
+/// The C standard the generated code is written for.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Standard {
+    /// ANSI C, for toolchains without C99 support. Float math goes through
+    /// the `double` functions, as `sqrtf` and the like are C99 additions.
+    C89,
+    C99,
+}
+
+/// The longest internal identifier C89 guarantees is significant; longer
+/// names may be cut short, and so clash, under a C89 compiler.
+const C89_IDENTIFIER_LIMIT: usize = 31;
+
+/// The names of the table of strings `CHR` gives, and the counter that fills
+/// it in. Teeny Tiny identifiers can't contain `_`, so neither can clash with
+/// a variable.
+const CHR_TABLE: &str = "chr_table";
+const CHR_INDEX: &str = "chr_index";
+
+/// Generates C.
+pub struct CEmitter {
+    /// Spaces per level of indentation. Zero leaves every line flush left,
+    /// for compact output.
+    pub indent_width: usize,
+    /// Decimal places for floats printed by `PRINT` and `WRITE`. Without
+    /// one, they're printed with `%g`, to six significant digits and without
+    /// trailing zeros.
+    pub float_precision: Option<usize>,
+    /// Turns off buffering of stdout at the start of `main`, so that output
+    /// shows up straight away in programs that mix `PRINT` and `INPUT`.
+    pub unbuffered_stdout: bool,
+    /// The function the program is wrapped in. Any name but `main` gives an
+    /// `int name(void)` function, for embedding the program in other code.
+    pub function_name: String,
+    /// Declares numeric variables and constants as `double` rather than
+    /// `float`, for programs that need the precision.
+    pub double: bool,
+    /// The standard to write for. Comments are always `/* */` comments and
+    /// declarations always come first in their block, which suits either.
+    pub standard: Standard,
+    /// The source file to name in a `#line` directive ahead of each
+    /// statement, so that the C compiler's errors and a debugger point at the
+    /// Teeny Tiny source rather than the generated C.
+    pub line_directives: Option<String>,
+    /// The first construct found that `standard` can't express, reported
+    /// once the whole program has been emitted.
+    unsupported: Option<String>,
+    /// Whether the program calls `RANDOM`, and so seeds `rand` on entry.
+    seeds_random: bool,
+    /// Whether the program calls `CHR`, and so fills in the table of
+    /// one-character strings it gives on entry.
+    uses_chr: bool,
+    depth: usize,
+    outfile: PathBuf,
+    includes: BTreeSet<String>,
     header: String,
     code: String,
+    declared: SymbolTable,
+    /// The declarations for each block being emitted, innermost last, which
+    /// go at the top of the block once the rest of it is done.
+    locals: Vec<String>,
 }
 
-impl Emitter {
-    pub fn new(outfile: &'static str) -> Self {
-        Emitter {
-            outfile: outfile,
+impl CEmitter {
+    /// Creates an emitter for a program whose C `write_file` writes to
+    /// `outfile`, which can be any path, e.g. one given on the command line.
+    pub fn new(outfile: impl Into<PathBuf>) -> Self {
+        CEmitter {
+            indent_width: DEFAULT_INDENT_WIDTH,
+            float_precision: None,
+            unbuffered_stdout: false,
+            function_name: DEFAULT_FUNCTION_NAME.to_string(),
+            double: false,
+            standard: Standard::C99,
+            line_directives: None,
+            unsupported: None,
+            seeds_random: false,
+            uses_chr: false,
+            depth: 0,
+            outfile: outfile.into(),
+            includes: BTreeSet::new(),
             header: String::new(),
             code: String::new(),
+            declared: SymbolTable::new(),
+            locals: Vec::new(),
         }
     }
 
-    pub fn header_line(&mut self, code: &str) {
-        self.header.push_str(code);
-        self.header.push('\n');
+    /// Requests `#include <header>` in the generated file. Each header is
+    /// included at most once, in sorted order ahead of all other header lines,
+    /// however many features ask for it.
+    pub fn include(&mut self, header: &str) {
+        self.includes.insert(header.to_string());
     }
 
-    pub fn emit_line(&mut self, code: &str) {
-        self.code.push_str(code);
-        self.code.push('\n');
+    /// The C type of non-integer numbers.
+    fn float_type(&self) -> &'static str {
+        if self.double {
+            "double"
+        } else {
+            "float"
+        }
     }
 
-    pub fn emit(&mut self, code: &str) {
-        self.code.push_str(code);
+    /// The name of a `<math.h>` function for non-integer numbers, given the
+    /// name of its `double` version, e.g. `sqrtf` for `sqrt` with floats.
+    fn math_function(&mut self, name: &str) -> String {
+        self.include("math.h");
+        if self.double || self.standard == Standard::C89 {
+            name.to_string()
+        } else {
+            format!("{}f", name)
+        }
+    }
+
+    /// Spells a name as a C identifier, noting it as unsupported if it's too
+    /// long for the standard.
+    fn identifier(&mut self, name: &str) -> String {
+        let ident = c_identifier(name);
+        if self.standard == Standard::C89
+            && ident.len() > C89_IDENTIFIER_LIMIT
+            && self.unsupported.is_none()
+        {
+            self.unsupported = Some(format!(
+                "identifier {:?} is longer than the {} characters significant in C89",
+                name, C89_IDENTIFIER_LIMIT
+            ));
+        }
+        ident
+    }
+
+    fn indentation(&self, depth: usize) -> String {
+        " ".repeat(depth * self.indent_width)
+    }
+
+    /// Declares a variable at the top of the block it is first assigned in,
+    /// or of `main` outside of any block, with its doc comment above it.
+    /// Later assignments keep the type it was first declared with, and their
+    /// doc comments stay where they are.
+    fn declare(&mut self, name: &str, ty: Type, doc: Option<&str>) {
+        if self.declared.contains(name) {
+            for line in doc.map(doc_comment).unwrap_or_default() {
+                self.emit_line(&line);
+            }
+            return;
+        }
+        self.declared.declare(name, Symbol::new(ty, Pos::default()));
+
+        for line in doc.map(doc_comment).unwrap_or_default() {
+            self.declaration_line(&line);
+        }
+
+        let name = self.identifier(name);
+        match ty {
+            Type::Float => self.declaration_line(&format!("{} {};", self.float_type(), name)),
+            Type::Int => self.declaration_line(&format!("int {};", name)),
+            Type::Str => self.declaration_line(&format!("char *{};", name)),
+        }
+    }
+
+    /// Adds a line to the declarations at the top of the current block.
+    fn declaration_line(&mut self, code: &str) {
+        let indent = self.indentation(self.depth);
+        match self.locals.last_mut() {
+            Some(locals) => {
+                locals.push_str(&indent);
+                locals.push_str(code);
+                locals.push('\n');
+            }
+            None => self.header_line(&format!("{}{}", self.indentation(1), code)),
+        }
+    }
+
+    fn var_type(&self, name: &str) -> Type {
+        self.declared.type_of(name).unwrap_or(Type::Float)
+    }
+
+    /// The type of an expression, as worked out by the type checker. The
+    /// program has been type checked before it gets here, so an ill-typed
+    /// expression is never actually seen.
+    fn type_of(&self, expr: &Expr) -> Type {
+        typecheck::infer(expr, &self.declared).unwrap_or(Type::Float)
+    }
+
+    /// Prints the items on one line, separated by spaces and followed by
+    /// `end`, which is already escaped for the format string.
+    fn emit_print(&mut self, items: &[Expr], end: &str) {
+        let mut format = String::new();
+        let mut args = String::new();
+
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                format.push(' ');
+            }
+
+            match item {
+                Expr::Str(value, _) => format.push_str(&escape_format_string(value)),
+                expr => {
+                    format.push_str(&match self.type_of(expr) {
+                        Type::Float => match self.float_precision {
+                            Some(precision) => format!("%.{}f", precision),
+                            None => "%g".to_string(),
+                        },
+                        Type::Int => "%d".to_string(),
+                        Type::Str => "%s".to_string(),
+                    });
+                    args.push_str(&format!(", {}", self.expression(expr)));
+                }
+            }
+        }
+
+        self.emit_line(&format!("printf(\"{}{}\"{});", format, end, args));
+    }
+
+    /// Emits the body of an `IF`, `ELSE` or loop, after the opening `{`, in a
+    /// scope of its own.
+    fn emit_block(&mut self, stmts: &[Stmt]) {
+        self.declared.push_scope();
+        self.locals.push(String::new());
+        let outer = std::mem::take(&mut self.code);
+
+        for stmt in stmts {
+            self.emit_statement(stmt);
+        }
+
+        let body = std::mem::replace(&mut self.code, outer);
+        self.code.push_str(&self.locals.pop().unwrap());
+        self.code.push_str(&body);
+        self.declared.pop_scope();
+    }
+
+    fn emit_statement(&mut self, stmt: &Stmt) {
+        if let Some(source) = &self.line_directives {
+            if !matches!(stmt, Stmt::Comment(..)) {
+                let directive = format!(
+                    "#line {} \"{}\"\n",
+                    stmt.span().start.line,
+                    escape_string(source)
+                );
+                self.emit(&directive);
+            }
+        }
+
+        match stmt {
+            Stmt::Print(items, _) => self.emit_print(items, "\\n"),
+
+            Stmt::Write(items, _) => self.emit_print(items, ""),
+
+            Stmt::If {
+                branches,
+                else_body,
+                ..
+            } => {
+                for (i, (cond, body)) in branches.iter().enumerate() {
+                    let cond = self.expression(cond);
+                    if i == 0 {
+                        self.emit_line(&format!("if ({}) {{", cond));
+                    } else {
+                        self.emit_line(&format!("}} else if ({}) {{", cond));
+                    }
+                    self.emit_block(body);
+                }
+
+                if let Some(body) = else_body {
+                    self.emit_line("} else {");
+                    self.emit_block(body);
+                }
+                self.emit_line("}");
+            }
+
+            Stmt::While { cond, body, .. } => {
+                let cond = self.expression(cond);
+                self.emit_line(&format!("while ({}) {{", cond));
+                self.emit_block(body);
+                self.emit_line("}");
+            }
+
+            Stmt::Assert(cond, span) => {
+                let cond = self.expression(cond);
+                self.emit_line(&format!("if (!({})) {{", cond));
+                self.emit_line(&format!(
+                    "fprintf(stderr, \"assertion failed at line {}\\n\");",
+                    span.start.line
+                ));
+                self.emit_line("return 1;");
+                self.emit_line("}");
+            }
+
+            Stmt::Return(value, _) => {
+                let value = self.expression(value);
+                self.emit_line(&format!("return (int)({});", value));
+            }
+
+            Stmt::Exit(value, _) => {
+                self.include("stdlib.h");
+                let status = match value {
+                    Some(value) => format!("(int)({})", self.expression(value)),
+                    None => "0".to_string(),
+                };
+                self.emit_line(&format!("exit({});", status));
+            }
+
+            Stmt::DoWhile { body, cond, .. } => {
+                self.emit_line("do {");
+                self.emit_block(body);
+                let cond = self.expression(cond);
+                self.emit_line(&format!("}} while ({});", cond));
+            }
+
+            Stmt::Label(name, _) => {
+                let name = self.identifier(name);
+                self.emit_line(&format!("{}:", name));
+            }
+
+            Stmt::Goto(name, _) => self.emit_line(&format!("goto {};", c_identifier(name))),
+
+            // indented, but otherwise as it is, so that braces in it don't
+            // change the indentation of what follows
+            Stmt::Emit(code, _) => {
+                for line in code.lines() {
+                    let indent = self.indentation(self.depth);
+                    self.emit(&format!("{}{}\n", indent, line));
+                }
+            }
+
+            Stmt::Swap(first, second, _) => {
+                let ty = match self.var_type(first) {
+                    Type::Int => "int",
+                    _ => self.float_type(),
+                };
+                let (first, second) = (c_identifier(first), c_identifier(second));
+                self.emit_line("{");
+                self.emit_line(&format!("{} _t = {};", ty, first));
+                self.emit_line(&format!("{} = {};", first, second));
+                self.emit_line(&format!("{} = _t;", second));
+                self.emit_line("}");
+            }
+
+            Stmt::Break(_) => self.emit_line("break;"),
+
+            Stmt::Continue(_) => self.emit_line("continue;"),
+
+            Stmt::Let {
+                name, value, doc, ..
+            } => {
+                let ty = typecheck::let_type(value, &self.declared);
+                self.declare(name, ty, doc.as_deref());
+                let value = self.expression(value);
+                self.emit_line(&format!("{} = {};", c_identifier(name), value));
+            }
+
+            Stmt::Int { name, value, .. } => {
+                self.declare(name, Type::Int, None);
+                let value = self.expression(value);
+                self.emit_line(&format!("{} = {};", c_identifier(name), value));
+            }
+
+            Stmt::Const { name, value, .. } => {
+                self.declared.declare(
+                    name,
+                    Symbol {
+                        constant: true,
+                        ..Symbol::new(Type::Float, Pos::default())
+                    },
+                );
+                let name = self.identifier(name);
+                let value = self.expression(value);
+                self.declaration_line(&format!(
+                    "const {} {} = {};",
+                    self.float_type(),
+                    name,
+                    value
+                ));
+            }
+
+            Stmt::CompoundAssign {
+                name, op, value, ..
+            } => {
+                let value = self.expression(value);
+                self.emit_line(&format!(
+                    "{} {}= {};",
+                    c_identifier(name),
+                    op.spelling(),
+                    value
+                ));
+            }
+
+            Stmt::Assign { name, value, .. } => {
+                let value = self.expression(value);
+                self.emit_line(&format!("{} = {};", c_identifier(name), value));
+            }
+
+            Stmt::Input {
+                prompt, name, doc, ..
+            } => {
+                self.declare(name, Type::Float, doc.as_deref());
+                if let Some(prompt) = prompt {
+                    self.emit_line(&format!("printf(\"{}\");", escape_format_string(prompt)));
+                    self.emit_line("fflush(stdout);");
+                }
+
+                // unlike printf's, scanf's arguments aren't promoted to double
+                let format = match self.var_type(name) {
+                    Type::Float if self.double => "%lf",
+                    Type::Float => "%f",
+                    Type::Int => "%d",
+                    Type::Str => unreachable!("the parser rejects INPUT into a string"),
+                };
+                let name = c_identifier(name);
+                self.emit_line(&format!("if (0 == scanf(\"{}\", &{})) {{", format, name));
+                self.emit_line(&format!("{} = 0;", name));
+                self.emit_line("scanf(\"%*s\");");
+                self.emit_line("}");
+            }
+
+            Stmt::Comment(text, _) => {
+                self.emit_line(&format!("/* {} */", text.replace("*/", "* /")));
+            }
+        }
+    }
+
+    /// Generates C for an expression.
+    fn expression(&mut self, expr: &Expr) -> String {
+        match expr {
+            // C99 has no binary literals, so those are written out in decimal
+            Expr::Number(value, _) => match value.strip_prefix("0b").or(value.strip_prefix("0B")) {
+                Some(digits) => u64::from_str_radix(digits, 2).unwrap().to_string(),
+                None => value.clone(),
+            },
+
+            Expr::Str(value, _) => format!("\"{}\"", escape_string(value)),
+
+            Expr::Var(name, _) => c_identifier(name),
+
+            Expr::Unary {
+                op: UnaryOp::Not,
+                expr,
+                ..
+            } => format!("!({})", self.expression(expr)),
+
+            Expr::Unary { op, expr, .. } => {
+                let sign = if *op == UnaryOp::Minus { "-" } else { "+" };
+                // `- -x` must not run together into a decrement
+                match **expr {
+                    Expr::Unary { .. } => format!("{}({})", sign, self.expression(expr)),
+                    _ => format!("{}{}", sign, self.operand(expr, u8::MAX, false)),
+                }
+            }
+
+            Expr::Binary { .. } => {
+                let (first, links) = expr.left_chain();
+                let mut lhs = first;
+                let mut code = self.expression(first);
+                for link in links {
+                    code = self.binary(link.op, lhs, code, link.rhs);
+                    lhs = link.expr;
+                }
+                code
+            }
+
+            Expr::Call {
+                func: Builtin::Random,
+                args,
+                ..
+            } => {
+                self.include("stdlib.h");
+                self.include("time.h");
+                self.seeds_random = true;
+                let lo = self.expression(&args[0]);
+                let hi = self.expression(&args[1]);
+                format!(
+                    "((int)({0}) + rand() % ((int)({1}) - (int)({0}) + 1))",
+                    lo, hi
+                )
+            }
+
+            Expr::Call {
+                func: Builtin::Len,
+                args,
+                ..
+            } => {
+                self.include("string.h");
+                format!("(int)strlen({})", self.expression(&args[0]))
+            }
+
+            Expr::Call {
+                func: Builtin::Ord,
+                args,
+                ..
+            } => format!("(int)(unsigned char){}[0]", self.expression(&args[0])),
+
+            // a table of every one-character string, so that the strings
+            // CHR gives are never overwritten by a later call
+            Expr::Call {
+                func: Builtin::Chr,
+                args,
+                ..
+            } => {
+                self.uses_chr = true;
+                format!("{}[(int)({}) & 127]", CHR_TABLE, self.expression(&args[0]))
+            }
+
+            Expr::Call { func, args, .. } => {
+                let name = self.math_function(match func {
+                    Builtin::Sqrt => "sqrt",
+                    Builtin::Abs => "fabs",
+                    Builtin::Sin => "sin",
+                    Builtin::Cos => "cos",
+                    Builtin::Floor => "floor",
+                    Builtin::Random | Builtin::Len | Builtin::Ord | Builtin::Chr => {
+                        unreachable!("handled above")
+                    }
+                });
+                format!("{}({})", name, self.expression(&args[0]))
+            }
+        }
+    }
+
+    /// Generates C for a binary operation, given the C already generated for
+    /// its left operand.
+    fn binary(&mut self, op: BinaryOp, lhs: &Expr, lhs_code: String, rhs: &Expr) -> String {
+        match op {
+            // operands may be floats, so the remainder has to go through fmod
+            BinaryOp::Mod => {
+                self.include("math.h");
+                format!("fmod({}, {})", lhs_code, self.expression(rhs))
+            }
+
+            BinaryOp::Pow => {
+                let pow = self.math_function("pow");
+                format!("{}({}, {})", pow, lhs_code, self.expression(rhs))
+            }
+
+            _ => {
+                let mut code = self.parenthesize(lhs, lhs_code, precedence(op), false);
+                let rhs = self.operand(rhs, precedence(op), true);
+                match op {
+                    BinaryOp::And => code.push_str(" && "),
+                    BinaryOp::Or => code.push_str(" || "),
+                    // `x - -1` must not run together into a decrement
+                    BinaryOp::Add | BinaryOp::Sub if rhs.starts_with(op.spelling()) => {
+                        code.push_str(&format!(" {} ", op.spelling()))
+                    }
+                    _ => code.push_str(op.spelling()),
+                }
+                code.push_str(&rhs);
+                code
+            }
+        }
+    }
+
+    /// Generates C for the operand of an operator with the given precedence,
+    /// parenthesizing it if C would otherwise group it differently. Operators
+    /// are left-associative, so a right operand of equal precedence is
+    /// parenthesized as well.
+    fn operand(&mut self, expr: &Expr, parent: u8, is_rhs: bool) -> String {
+        let code = self.expression(expr);
+        self.parenthesize(expr, code, parent, is_rhs)
+    }
+
+    /// Parenthesizes the C generated for an operand, as `operand` does.
+    fn parenthesize(&self, expr: &Expr, code: String, parent: u8, is_rhs: bool) -> String {
+        match expr {
+            Expr::Binary { op, .. }
+                if precedence(*op) < parent || (is_rhs && precedence(*op) == parent) =>
+            {
+                format!("({})", code)
+            }
+            // a condition used as a number gets parentheses even where C
+            // doesn't need them, as `a==b<c` reads like a chained comparison
+            Expr::Binary { .. } if expr.is_condition() && parent > precedence(BinaryOp::And) => {
+                format!("({})", code)
+            }
+            _ => code,
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Writes the generated C to any sink, e.g. a `Vec<u8>` or stdout.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> GenResult<()> {
+        writer.write_all(self.output().as_bytes())?;
+        writer.flush()?;
+
+        Ok(())
     }
 
     pub fn write_file(&mut self) -> GenResult<()> {
-        let mut writer = BufWriter::new(File::create(self.outfile)?);
-        writer.write_all(self.header.as_bytes())?;
-        writer.write_all(self.code.as_bytes())?;
+        let mut writer = BufWriter::new(File::create(&self.outfile)?);
+        self.write_to(&mut writer)
+    }
+
+    /// Writes the generated C split in two, for building into other code: a
+    /// header at `h_path` with the includes and a prototype of the function
+    /// the program is wrapped in, and its definition at `c_path`, which
+    /// includes the header.
+    pub fn write_files(&self, c_path: impl AsRef<Path>, h_path: impl AsRef<Path>) -> GenResult<()> {
+        let h_path = h_path.as_ref();
+        let h_name = match h_path.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return Err(format!("not a header file: {}", h_path.display()).into()),
+        };
+
+        let guard = format!("{}_H", self.function_name.to_uppercase());
+        let mut header = format!("#ifndef {}\n#define {}\n\n", guard, guard);
+        for include in &self.includes {
+            header.push_str(&format!("#include <{}>\n", include));
+        }
+        header.push_str(&format!("\n{};\n\n#endif\n", self.signature()));
+
+        let source = format!("#include \"{}\"\n{}{}", h_name, self.header, self.code);
+
+        std::fs::write(h_path, header)?;
+        std::fs::write(c_path, source)?;
 
         Ok(())
     }
+
+    /// The C declaration of the function the program is wrapped in.
+    fn signature(&self) -> String {
+        if self.function_name == DEFAULT_FUNCTION_NAME {
+            "int main(int argc, char *argv[])".to_string()
+        } else {
+            format!("int {}(void)", self.function_name)
+        }
+    }
+}
+
+impl Emitter for CEmitter {
+    fn emit(&mut self, code: &str) {
+        self.code.push_str(code);
+    }
+
+    /// Emits a line of code, indenting it to the current depth. A line that
+    /// opens a block with `{` indents the lines that follow, and one starting
+    /// with `}` closes the block.
+    fn emit_line(&mut self, code: &str) {
+        if code.starts_with('}') {
+            self.depth = self.depth.saturating_sub(1);
+        }
+
+        self.code.push_str(&self.indentation(self.depth));
+        self.code.push_str(code);
+        self.code.push('\n');
+
+        if code.ends_with('{') {
+            self.depth += 1;
+        }
+    }
+
+    fn header_line(&mut self, code: &str) {
+        self.header.push_str(code);
+        self.header.push('\n');
+    }
+
+    /// Generates C for a whole program, wrapping it in `main` or the
+    /// function named by `function_name`.
+    fn emit_program(&mut self, program: &[Stmt]) -> GenResult<()> {
+        self.include("stdio.h");
+        self.header_line(&format!("{} {{", self.signature()));
+        self.depth = 1;
+
+        if self.unbuffered_stdout {
+            self.emit_line("setvbuf(stdout, NULL, _IONBF, 0);");
+        }
+
+        for stmt in program {
+            self.emit_statement(stmt);
+        }
+
+        // a program that ends by returning doesn't need another return
+        if !matches!(program.last(), Some(Stmt::Return(..) | Stmt::Exit(..))) {
+            self.emit_line("return 0;");
+        }
+        self.emit_line("}");
+
+        // after the declarations, so that these are the first statements run
+        if self.uses_chr {
+            let indent = self.indentation(1);
+            self.header_line(&format!("{}static char {}[128][2];", indent, CHR_TABLE));
+            self.header_line(&format!("{}int {};", indent, CHR_INDEX));
+        }
+        if self.seeds_random {
+            self.header_line(&format!("{}srand(time(NULL));", self.indentation(1)));
+        }
+        if self.uses_chr {
+            let indent = self.indentation(1);
+            self.header_line(&format!(
+                "{0}for ({1} = 0; {1} < 128; {1}++) {{",
+                indent, CHR_INDEX
+            ));
+            self.header_line(&format!(
+                "{}{}[{2}][0] = (char){2};",
+                self.indentation(2),
+                CHR_TABLE,
+                CHR_INDEX
+            ));
+            self.header_line(&format!("{}}}", indent));
+        }
+
+        match self.unsupported.take() {
+            Some(message) => Err(message.into()),
+            None => Ok(()),
+        }
+    }
+
+    fn output(&self) -> String {
+        let mut output = String::new();
+        for header in &self.includes {
+            output.push_str(&format!("#include <{}>\n", header));
+        }
+        output.push_str(&self.header);
+        output.push_str(&self.code);
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::emitter::{
+        escape_format_string, escape_string, CEmitter, Emitter, Standard, DEFAULT_INDENT_WIDTH,
+    };
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::GenResult;
+
+    fn emit(source: &str) -> CEmitter {
+        emit_with(source, |_| {}).unwrap()
+    }
+
+    /// Like `emit`, with the emitter set up by `configure` first, and failing
+    /// if it can't emit the program.
+    fn emit_with(source: &str, configure: impl FnOnce(&mut CEmitter)) -> GenResult<CEmitter> {
+        let program = Parser::new(Lexer::new(source)).unwrap().parse().unwrap();
+        let mut emitter = CEmitter::new("dummy.c");
+        emitter.indent_width = 0;
+        configure(&mut emitter);
+        emitter.emit_program(&program)?;
+        Ok(emitter)
+    }
+
+    fn emit_condition(condition: &str) -> String {
+        let input = format!(
+            "LET a = 1\nLET b = 2\nIF {} THEN\nPRINT a\nENDIF\n",
+            condition
+        );
+        let emitter = emit(&input);
+
+        let code = emitter.code();
+        let start = code.find("if (").unwrap() + 4;
+        let end = code.find(") {").unwrap();
+        code[start..end].to_string()
+    }
+
+    #[test]
+    fn test_escape_format_string() {
+        assert_eq!(escape_format_string("plain"), "plain");
+        assert_eq!(escape_format_string("a\tb\n"), r"a\tb\n");
+        assert_eq!(
+            escape_format_string(r#"say "hi" \ 5%"#),
+            r#"say \"hi\" \\ 5%%"#
+        );
+        assert_eq!(
+            escape_string("\u{1b}[1m\u{0}12\u{7f}"),
+            r"\033[1m\00012\177"
+        );
+    }
+
+    #[test]
+    fn test_write_to_buffer() {
+        let mut emitter = CEmitter::new("dummy.c");
+        emitter.include("stdio.h");
+        emitter.header_line("int main(int argc, char *argv[]) {");
+        emitter.emit_line("return 0;");
+        emitter.emit_line("}");
+
+        let mut buffer = Vec::new();
+        emitter.write_to(&mut buffer).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "#include <stdio.h>\nint main(int argc, char *argv[]) {\nreturn 0;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_write_file() {
+        let outfile = std::env::temp_dir().join(format!("ttc-rs-{}-emitted.c", std::process::id()));
+        let program = Parser::new(Lexer::new("PRINT 1\n"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut emitter = CEmitter::new(&outfile);
+        emitter.emit_program(&program).unwrap();
+        emitter.write_file().unwrap();
+
+        let written = std::fs::read_to_string(&outfile).unwrap();
+        std::fs::remove_file(&outfile).unwrap();
+        assert_eq!(written, emitter.output());
+    }
+
+    #[test]
+    fn test_write_files() {
+        let dir = std::env::temp_dir();
+        let c_path = dir.join(format!("ttc-rs-{}-run.c", std::process::id()));
+        let h_path = dir.join(format!("ttc-rs-{}-run.h", std::process::id()));
+        let emitter = emit_with("PRINT SQRT(2)\n", |emitter| {
+            emitter.function_name = "run".to_string();
+        })
+        .unwrap();
+        emitter.write_files(&c_path, &h_path).unwrap();
+
+        let header = std::fs::read_to_string(&h_path).unwrap();
+        let source = std::fs::read_to_string(&c_path).unwrap();
+        std::fs::remove_file(&h_path).unwrap();
+        std::fs::remove_file(&c_path).unwrap();
+
+        assert_eq!(
+            header,
+            "#ifndef RUN_H\n#define RUN_H\n\n#include <math.h>\n#include <stdio.h>\n\nint run(void);\n\n#endif\n"
+        );
+        assert!(source.starts_with(&format!(
+            "#include \"ttc-rs-{}-run.h\"\nint run(void) {{\n",
+            std::process::id()
+        )));
+        assert!(!source.contains("<stdio.h>"));
+    }
+
+    #[test]
+    fn test_includes_are_deduplicated() {
+        let mut emitter = CEmitter::new("dummy.c");
+        emitter.include("stdio.h");
+        emitter.header_line("int main(int argc, char *argv[]) {");
+        emitter.include("math.h");
+        emitter.include("stdio.h");
+        assert_eq!(
+            emitter.output(),
+            "#include <math.h>\n#include <stdio.h>\nint main(int argc, char *argv[]) {\n"
+        );
+    }
+
+    #[test]
+    fn test_math_include_is_emitted_once() {
+        let emitter = emit("LET x = 7 % 3\nLET y = 2 ^ x\nPRINT x % 2, y ^ 2");
+        let output = emitter.output();
+        assert_eq!(output.matches("#include <math.h>").count(), 1);
+        assert!(output.starts_with("#include <math.h>\n#include <stdio.h>\nint main("));
+    }
+
+    #[test]
+    fn test_emit_program() {
+        let emitter = emit("INPUT n\nLET x = n * 2\nPRINT x\nLET x = 1");
+        assert_eq!(
+            emitter.output(),
+            "#include <stdio.h>
+int main(int argc, char *argv[]) {
+float n;
+float x;
+if (0 == scanf(\"%f\", &n)) {
+n = 0;
+scanf(\"%*s\");
+}
+x = n*2;
+printf(\"%g\\n\", x);
+x = 1;
+return 0;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_emit_label_loop() {
+        let emitter = emit("LABEL loop\nPRINT \"hello, world\"\nGOTO loop");
+        assert_eq!(
+            emitter.code(),
+            "loop:\nprintf(\"hello, world\\n\");\ngoto loop;\nreturn 0;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_emit_break_continue() {
+        let emitter = emit("INT i = 0\nWHILE i < 5 REPEAT\ni = i + 1\nIF i == 2 THEN\nCONTINUE\nENDIF\nBREAK\nENDWHILE");
+        assert!(emitter
+            .code()
+            .contains("while (i<5) {\ni = i+1;\nif (i==2) {\ncontinue;\n}\nbreak;\n}\n"));
+    }
+
+    #[test]
+    fn test_emit_float_precision() {
+        // not cut short to two decimal places
+        assert!(emit("PRINT 3.14159, 2\n")
+            .code()
+            .contains("printf(\"%g %d\\n\", 3.14159, 2);\n"));
+
+        let emitter = emit_with("PRINT 3.14159, 2\nLET x = 1\nWRITE x", |emitter| {
+            emitter.float_precision = Some(5);
+        })
+        .unwrap();
+        assert!(emitter
+            .code()
+            .contains("printf(\"%.5f %d\\n\", 3.14159, 2);\nx = 1;\nprintf(\"%.5f\", x);\n"));
+    }
+
+    #[test]
+    fn test_emit_double() {
+        let source = "LET x = 2\nINPUT y\nCONST C = 3\nSWAP x, y\nPRINT SQRT(x) + y ^ 2, C\n";
+        let output = emit_with(source, |emitter| emitter.double = true)
+            .unwrap()
+            .output();
+        assert!(output.contains("{\ndouble x;\ndouble y;\nconst double C = 3;\nx = 2;\n"));
+        assert!(output.contains("if (0 == scanf(\"%lf\", &y)) {\n"));
+        assert!(output.contains("{\ndouble _t = x;\nx = y;\ny = _t;\n}\n"));
+        assert!(output.contains("printf(\"%g %g\\n\", sqrt(x)+pow(y, 2), C);\n"));
+        assert!(!output.contains("float"));
+
+        let output = emit(source).output();
+        assert!(output.contains("float x;\n"));
+        assert!(output.contains("scanf(\"%f\", &y)"));
+        assert!(output.contains("sqrtf(x)+powf(y, 2)"));
+    }
+
+    #[test]
+    fn test_emit_unbuffered_stdout() {
+        let emitter = emit_with("INPUT n\nPRINT n", |emitter| {
+            emitter.unbuffered_stdout = true;
+        })
+        .unwrap();
+        assert!(emitter.output().contains(
+            "int main(int argc, char *argv[]) {\nfloat n;\nsetvbuf(stdout, NULL, _IONBF, 0);\nif (0 == scanf"
+        ));
+
+        assert!(!emit("INPUT n\nPRINT n").output().contains("setvbuf"));
+    }
+
+    #[test]
+    fn test_emit_function_name() {
+        let output = emit_with("PRINT 1", |emitter| {
+            emitter.function_name = "run".to_string();
+        })
+        .unwrap()
+        .output();
+        assert!(output.contains("int run(void) {\nprintf(\"%d\\n\", 1);\nreturn 0;\n}\n"));
+        assert!(!output.contains("main"));
+    }
+
+    #[test]
+    fn test_emit_assert() {
+        let emitter = emit("INT i = 2\n\nASSERT i * 2 == 4 AND NOT i < 0\n");
+        assert!(emitter.code().contains(
+            "if (!(i*2==4 && !(i<0))) {\nfprintf(stderr, \"assertion failed at line 3\\n\");\nreturn 1;\n}\n"
+        ));
+    }
+
+    #[test]
+    fn test_emit_compound_assignment() {
+        let emitter = emit("LET x = 1\nx += 1\nx -= 2 * x\nLET x *= 3\nx /= (x + 1)\n");
+        assert!(emitter
+            .code()
+            .ends_with("x += 1;\nx -= 2*x;\nx *= 3;\nx /= x+1;\nreturn 0;\n}\n"));
+    }
+
+    #[test]
+    fn test_emit_swap() {
+        let emitter = emit("LET x = 1\nLET y = 2\nSWAP x, y\nINT i = 1\nINT j = 2\nSWAP i, j\n");
+        assert!(emitter
+            .code()
+            .contains("{\nfloat _t = x;\nx = y;\ny = _t;\n}\n"));
+        assert!(emitter
+            .code()
+            .contains("{\nint _t = i;\ni = j;\nj = _t;\n}\n"));
+    }
+
+    #[test]
+    fn test_emit_random() {
+        let emitter = emit("INT i = 1\nPRINT RANDOM(i, 6) * 2\n");
+        assert!(emitter
+            .code()
+            .contains("printf(\"%d\\n\", ((int)(i) + rand() % ((int)(6) - (int)(i) + 1))*2);"));
+        assert_eq!(emitter.output().matches("srand(time(NULL));").count(), 1);
+        assert!(emitter
+            .output()
+            .starts_with("#include <stdio.h>\n#include <stdlib.h>\n#include <time.h>\n"));
+
+        let emitter = emit("PRINT RANDOM(1, 2)\nPRINT RANDOM(3, 4)\n");
+        assert!(emitter
+            .output()
+            .contains("int main(int argc, char *argv[]) {\nsrand(time(NULL));\nprintf("));
+        assert_eq!(emitter.output().matches("srand").count(), 1);
+        assert!(!emit("PRINT 1").output().contains("srand"));
+    }
+
+    #[test]
+    fn test_emit_math_builtins() {
+        for (call, code) in [
+            ("SQRT(x)", "sqrtf(x)"),
+            ("ABS(x)", "fabsf(x)"),
+            ("SIN(x)", "sinf(x)"),
+            ("COS(x)", "cosf(x)"),
+            ("FLOOR(x)", "floorf(x)"),
+        ] {
+            let emitter = emit(&format!("LET x = 2\nPRINT {} * 2\n", call));
+            assert!(emitter
+                .code()
+                .contains(&format!("printf(\"%g\\n\", {}*2);", code)));
+            assert!(emitter.output().starts_with("#include <math.h>\n"));
+        }
+        assert!(emit("LET x = 2\nPRINT SQRT(ABS(x - 4))\n")
+            .code()
+            .contains("sqrtf(fabsf(x-4))"));
+    }
+
+    #[test]
+    fn test_emit_raw() {
+        let mut parser =
+            Parser::from_source("IF 1 > 0 THEN\nEMIT \"if (x) {\\\"\"\nENDIF\nPRINT 1\n").unwrap();
+        parser.allow_raw = true;
+        let program = parser.parse().unwrap();
+        let mut emitter = CEmitter::new("dummy.c");
+        emitter.emit_program(&program).unwrap();
+
+        assert!(emitter
+            .code()
+            .contains("    if (1>0) {\n        if (x) {\"\n    }\n    printf(\"%d\\n\", 1);\n"));
+    }
+
+    #[test]
+    fn test_emit_string_builtins() {
+        let emitter = emit("LET s = \"abc\"\nPRINT LEN(s), ORD(s)\nLET c = CHR(ORD(s) + 1)\n");
+        assert!(emitter
+            .code()
+            .contains("printf(\"%d %d\\n\", (int)strlen(s), (int)(unsigned char)s[0]);\n"));
+        assert!(emitter
+            .code()
+            .contains("c = chr_table[(int)((int)(unsigned char)s[0]+1) & 127];\n"));
+        assert!(emitter.output().contains("#include <string.h>\n"));
+        assert!(emitter.output().contains(
+            "char *c;\nstatic char chr_table[128][2];\nint chr_index;\nfor (chr_index = 0; chr_index < 128; chr_index++) {\nchr_table[chr_index][0] = (char)chr_index;\n}\n"
+        ));
+        assert!(!emit("PRINT LEN(\"a\")").output().contains("chr_table"));
+    }
+
+    #[test]
+    fn test_emit_c89() {
+        let emit_for = |source: &str, standard: Standard| {
+            emit_with(source, |emitter| emitter.standard = standard).map(|emitter| emitter.output())
+        };
+
+        let source = "LET x = 2\nIF x > 1 THEN\nPRINT x\nLET y = SQRT(x) + x ^ 2\nENDIF\n";
+        let c89 = emit_for(source, Standard::C89).unwrap();
+        let c99 = emit_for(source, Standard::C99).unwrap();
+        assert!(c89.contains("y = sqrt(x)+pow(x, 2);"));
+        assert!(c99.contains("y = sqrtf(x)+powf(x, 2);"));
+        assert_eq!(c89.replace("sqrt(", "sqrtf(").replace("pow(", "powf("), c99);
+        // C89 wants declarations ahead of the statements of their block.
+        assert!(c89.contains("if (x>1) {\nfloat y;\nprintf("));
+
+        let name = "abcdefghijklmnopqrstuvwxyzABCDE";
+        assert_eq!(name.len(), 31);
+        assert!(emit_for(&format!("LET {} = 1\n", name), Standard::C89).is_ok());
+        let source = format!("LET {}F = 1\n", name);
+        assert!(emit_for(&source, Standard::C99).is_ok());
+        assert_eq!(
+            emit_for(&source, Standard::C89).unwrap_err().to_string(),
+            "identifier \"abcdefghijklmnopqrstuvwxyzABCDEF\" is longer than the 31 characters significant in C89"
+        );
+    }
+
+    #[test]
+    fn test_emit_return() {
+        let emitter = emit("INT x = 3\nIF x > 2 THEN\nRETURN 1\nENDIF\nRETURN x * 2\n");
+        assert!(emitter
+            .code()
+            .ends_with("if (x>2) {\nreturn (int)(1);\n}\nreturn (int)(x*2);\n}\n"));
+        assert!(!emitter.code().contains("return 0;"));
+
+        let emitter = emit("INT x = 3\nIF x > 2 THEN\nRETURN 1\nENDIF\n");
+        assert!(emitter
+            .code()
+            .ends_with("return (int)(1);\n}\nreturn 0;\n}\n"));
+    }
+
+    #[test]
+    fn test_emit_line_directives() {
+        let source = "INT x = 3\n\n# check x\nIF x > 2 THEN\n    PRINT x\nENDIF\nPRINT \"done\"\n";
+        let emitter = emit_with(source, |emitter| {
+            emitter.indent_width = DEFAULT_INDENT_WIDTH;
+            emitter.line_directives = Some("dir\\prog.teeny".to_string());
+        })
+        .unwrap();
+
+        assert_eq!(
+            emitter.code(),
+            "\
+#line 1 \"dir\\\\prog.teeny\"
+    x = 3;
+    /* check x */
+#line 4 \"dir\\\\prog.teeny\"
+    if (x>2) {
+#line 5 \"dir\\\\prog.teeny\"
+        printf(\"%d\\n\", x);
+    }
+#line 7 \"dir\\\\prog.teeny\"
+    printf(\"done\\n\");
+    return 0;
+}
+"
+        );
+        assert!(!emit("PRINT 1\n").code().contains("#line"));
+    }
+
+    #[test]
+    fn test_emit_comparison_values() {
+        let emitter =
+            emit("LET x = 3\nLET flag = x > 0\nLET off = 1 > 2\nPRINT flag, 1 == (x < 1)\n");
+        assert!(emitter.output().contains("int flag;\nint off;\n"));
+        assert!(emitter
+            .code()
+            .contains("flag = x>0;\noff = 1>2;\nprintf(\"%d %d\\n\", flag, 1==(x<1));\n"));
+    }
+
+    #[test]
+    fn test_emit_exit() {
+        let emitter = emit("INT x = 3\nIF x > 2 THEN\nEXIT x - 1\nENDIF\nEXIT\n");
+        assert!(emitter
+            .output()
+            .starts_with("#include <stdio.h>\n#include <stdlib.h>\n"));
+        assert!(emitter
+            .code()
+            .ends_with("if (x>2) {\nexit((int)(x-1));\n}\nexit(0);\n}\n"));
+        assert!(!emitter.code().contains("return 0;"));
+    }
+
+    #[test]
+    fn test_emit_do_while() {
+        let emitter = emit("INT i = 5\nDO\nPRINT i\ni = i + 1\nWHILE i < 3\n");
+        assert!(emitter
+            .code()
+            .contains("do {\nprintf(\"%d\\n\", i);\ni = i+1;\n} while (i<3);\n"));
+    }
+
+    #[test]
+    fn test_emit_block_scope() {
+        let emitter = emit(
+            "LET a = 1
+IF a > 0 THEN
+PRINT a
+LET s = \"x\"
+IF a > 1 THEN
+INPUT n
+ENDIF
+ELSE
+LET s = 2
+a = s
+ENDIF
+",
+        );
+        assert_eq!(
+            emitter.output(),
+            "#include <stdio.h>
+int main(int argc, char *argv[]) {
+float a;
+a = 1;
+if (a>0) {
+char *s;
+printf(\"%g\\n\", a);
+s = \"x\";
+if (a>1) {
+float n;
+if (0 == scanf(\"%f\", &n)) {
+n = 0;
+scanf(\"%*s\");
+}
+}
+} else {
+float s;
+s = 2;
+a = s;
+}
+return 0;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_emit_input_prompt() {
+        let emitter = emit("INPUT \"Enter your age: \", age\nPRINT age");
+        assert!(emitter.code().starts_with(
+            "printf(\"Enter your age: \");\nfflush(stdout);\nif (0 == scanf(\"%f\", &age)) {\n"
+        ));
+    }
+
+    #[test]
+    fn test_emit_input_without_prompt() {
+        let emitter = emit("INPUT age\nPRINT age");
+        assert!(emitter
+            .code()
+            .starts_with("if (0 == scanf(\"%f\", &age)) {\n"));
+        assert!(!emitter.code().contains("fflush"));
+    }
+
+    #[test]
+    fn test_emit_integer_input() {
+        let emitter = emit("INT n = 0\nINPUT n\nINPUT x\nPRINT n, x");
+        assert_eq!(emitter.output().matches("int n;").count(), 1);
+        assert!(emitter.code().contains("if (0 == scanf(\"%d\", &n)) {\n"));
+        assert!(emitter.code().contains("if (0 == scanf(\"%f\", &x)) {\n"));
+        assert!(emitter.code().contains("printf(\"%d %g\\n\", n, x);"));
+    }
+
+    #[test]
+    fn test_emit_print_escapes() {
+        let emitter = emit(r#"PRINT "a\tb \"quoted\" 100\%""#);
+        assert!(emitter
+            .code()
+            .starts_with(r#"printf("a\tb \"quoted\" 100%%\n");"#));
+
+        assert_eq!(
+            emit(r#"PRINT "100%""#).code(),
+            "printf(\"100%%\\n\");\nreturn 0;\n}\n"
+        );
+        assert!(emit("INPUT \"% done?\", n\nLET s = \"%d\"\nPRINT s\n")
+            .code()
+            .contains("printf(\"%% done?\");"));
+    }
+
+    #[test]
+    fn test_emit_print_items() {
+        let emitter =
+            emit("LET x = 1.5\nINT y = 2\nPRINT x, \" \", y\nPRINT \"x =\", x, \"100\\%\"");
+        assert!(emitter.code().contains("printf(\"%g   %d\\n\", x, y);\n"));
+        assert!(emitter.code().contains("printf(\"x = %g 100%%\\n\", x);\n"));
+    }
+
+    #[test]
+    fn test_emit_write() {
+        let emitter = emit("WRITE \"a\"\nWRITE \"b\"\nLET x = 2\nWRITE \"x:\", x\nPRINT \"\"");
+        assert_eq!(
+            emitter.code(),
+            "printf(\"a\");
+printf(\"b\");
+x = 2;
+printf(\"x: %g\", x);
+printf(\"\\n\");
+return 0;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_emit_string_variables() {
+        let input =
+            "LET s = \"100\\% \\\"done\\\"\"\nPRINT s\nLET t = s\ns = \"bye\"\nPRINT t, s, 1\n";
+        let emitter = emit(input);
+        assert!(emitter.output().contains("char *s;\nchar *t;\n"));
+        assert_eq!(
+            emitter.code(),
+            "s = \"100% \\\"done\\\"\";
+printf(\"%s\\n\", s);
+t = s;
+s = \"bye\";
+printf(\"%s %s %d\\n\", t, s, 1);
+return 0;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_emit_modulo() {
+        let emitter = emit("LET r = 7 % 3\nLET s = r * 2 % 4 + 1");
+        assert!(emitter
+            .output()
+            .starts_with("#include <math.h>\n#include <stdio.h>\n"));
+        assert!(emitter.code().contains("r = fmod(7, 3);\n"));
+        assert!(emitter.code().contains("s = fmod(r*2, 4)+1;\n"));
+    }
+
+    #[test]
+    fn test_emit_power() {
+        let emitter = emit("LET x = 2 ^ 3 ^ 2\nLET y = 2 * x ^ 2 + 1\nLET z = -x ^ 2");
+        assert!(emitter.output().contains("#include <math.h>\n"));
+        assert!(emitter.code().contains("x = powf(2, powf(3, 2));\n"));
+        assert!(emitter.code().contains("y = 2*powf(x, 2)+1;\n"));
+        assert!(emitter.code().contains("z = powf(-x, 2);\n"));
+    }
+
+    #[test]
+    fn test_emit_grouping() {
+        let emitter = emit("LET a = 1\nLET b = 2\nLET c = 3\nLET x = (a + b) * c\nLET y = a + b * c\nLET z = a % (b + c) ^ 2");
+        assert!(emitter.code().contains("x = (a+b)*c;\n"));
+        assert!(emitter.code().contains("y = a+b*c;\n"));
+        assert!(emitter.code().contains("z = fmod(a, powf(b+c, 2));\n"));
+    }
+
+    #[test]
+    fn test_emit_precedence_matrix() {
+        let ops = [("+", 1), ("-", 1), ("*", 2), ("/", 2)];
+
+        for (op1, prec1) in ops {
+            for (op2, prec2) in ops {
+                let cases = [
+                    (
+                        format!("a {} b {} c", op1, op2),
+                        format!("a{}b{}c", op1, op2),
+                    ),
+                    (
+                        format!("a {} (b {} c)", op1, op2),
+                        if prec2 > prec1 {
+                            format!("a{}b{}c", op1, op2)
+                        } else {
+                            format!("a{}(b{}c)", op1, op2)
+                        },
+                    ),
+                    (
+                        format!("(a {} b) {} c", op1, op2),
+                        if prec1 < prec2 {
+                            format!("(a{}b){}c", op1, op2)
+                        } else {
+                            format!("a{}b{}c", op1, op2)
+                        },
+                    ),
+                ];
+
+                for (source, code) in cases {
+                    let emitter = emit(&format!(
+                        "LET a = 1\nLET b = 2\nLET c = 3\nLET x = {}",
+                        source
+                    ));
+                    assert!(
+                        emitter.code().contains(&format!("x = {};\n", code)),
+                        "{}",
+                        source
+                    );
+                }
+            }
+        }
+
+        for (cond, code) in [
+            ("a < b AND b < c OR c < a", "a<b && b<c || c<a"),
+            ("a < b OR b < c AND c < a", "a<b || b<c && c<a"),
+            ("NOT a < b AND b < c", "!(a<b) && b<c"),
+            ("NOT (a + b) * c == a - b", "!((a+b)*c==a-b)"),
+            ("a + b < c * 2 ^ 2", "a+b<c*powf(2, 2)"),
+        ] {
+            let emitter = emit(&format!(
+                "LET a = 1\nLET b = 2\nLET c = 3\nIF {} THEN\nPRINT a\nENDIF",
+                cond
+            ));
+            assert!(
+                emitter.code().contains(&format!("if ({}) {{", code)),
+                "{}",
+                cond
+            );
+        }
+    }
+
+    #[test]
+    fn test_emit_if_else() {
+        let emitter = emit("LET a = 1\nIF a > 0 THEN\nPRINT \"yes\"\nELSE\nPRINT \"no\"\nENDIF\n");
+        assert!(emitter
+            .code()
+            .contains("if (a>0) {\nprintf(\"yes\\n\");\n} else {\nprintf(\"no\\n\");\n}\n"));
+    }
+
+    #[test]
+    fn test_emit_inline_if() {
+        let emitter = emit("LET a = 1\nIF a > 0 THEN PRINT \"yes\"\nPRINT a\n");
+        assert!(emitter
+            .code()
+            .contains("if (a>0) {\nprintf(\"yes\\n\");\n}\nprintf(\"%g\\n\", a);\n"));
+    }
+
+    #[test]
+    fn test_emit_if_without_else() {
+        let emitter = emit("LET a = 1\nIF a > 0 THEN\nPRINT \"yes\"\nENDIF\n");
+        assert!(emitter
+            .code()
+            .contains("if (a>0) {\nprintf(\"yes\\n\");\n}\n"));
+        assert!(!emitter.code().contains("else"));
+    }
+
+    #[test]
+    fn test_emit_nested_if_else() {
+        let emitter = emit("LET a = 1\nIF a > 0 THEN\nIF a > 1 THEN\nPRINT 2\nELSE\nPRINT 1\nENDIF\nELSE\nPRINT 0\nENDIF\n");
+        assert_eq!(emitter.code().matches("} else {").count(), 2);
+    }
+
+    #[test]
+    fn test_emit_elseif_grades() {
+        let input = "INPUT score
+IF score >= 90 THEN
+    PRINT \"A\"
+ELSEIF score >= 80 THEN
+    PRINT \"B\"
+ELSE
+    PRINT \"C\"
+ENDIF
+";
+        assert!(emit(input).code().contains(
+            "if (score>=90) {
+printf(\"A\\n\");
+} else if (score>=80) {
+printf(\"B\\n\");
+} else {
+printf(\"C\\n\");
+}
+"
+        ));
+    }
+
+    #[test]
+    fn test_emit_parens() {
+        let emitter = emit("LET a = 1\nLET b = 2\nLET x = ((a + b) * (a - b)) / 2\nLET y = a - (b - 1)\nLET z = -(a + b)");
+        assert!(emitter.code().contains("x = (a+b)*(a-b)/2;\n"));
+        assert!(emitter.code().contains("y = a-(b-1);\n"));
+        assert!(emitter.code().contains("z = -(a+b);\n"));
+    }
+
+    #[test]
+    fn test_emit_logical_operators() {
+        assert_eq!(emit_condition("a > 0 AND b > 0"), "a>0 && b>0");
+        assert_eq!(emit_condition("a > 0 OR b > 0"), "a>0 || b>0");
+        assert_eq!(emit_condition("NOT a > 0"), "!(a>0)");
+        assert_eq!(
+            emit_condition("a > 0 OR b > 0 AND NOT a == b"),
+            "a>0 || b>0 && !(a==b)"
+        );
+        assert_eq!(emit_condition("NOT NOT a < b"), "!(!(a<b))");
+    }
+
+    #[test]
+    fn test_emit_comments() {
+        let input = "# leading
+
+LET a = 1 # trailing
+IF a > 0 THEN
+    # inside */ the block
+    PRINT a
+    # before ENDIF
+ENDIF
+# at end of file";
+        assert_eq!(
+            emit(input).code(),
+            "/* leading */
+a = 1;
+/* trailing */
+if (a>0) {
+/* inside * / the block */
+printf(\"%g\\n\", a);
+/* before ENDIF */
+}
+/* at end of file */
+return 0;
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_emit_doc_comments() {
+        let emitter = emit(
+            "# the count\nLET x = 1\n# first line\n# second */ line\nINPUT y\n# again\nLET x = 2\n",
+        );
+        assert!(emitter.output().contains(
+            "int main(int argc, char *argv[]) {\n/** the count */\nfloat x;\n/**\n * first line\n * second * / line\n */\nfloat y;\n"
+        ));
+        assert!(emitter.code().contains("/** again */\nx = 2;\n"));
+    }
+
+    #[test]
+    fn test_emit_indented_comment_lines() {
+        let emitter = emit("  # a\n  # b\nPRINT 1\n    # c\n\t# d\nPRINT 2\n");
+        assert!(emitter
+            .code()
+            .starts_with("/* a */\n/* b */\nprintf(\"%d\\n\", 1);\n/* c */\n/* d */\n"));
+    }
+
+    #[test]
+    fn test_emit_unicode_identifiers() {
+        let emitter = emit("LET número = 1\nCONST π = 3.14\nPRINT número * π\n");
+        assert!(emitter
+            .output()
+            .contains("float n_ufa_mero;\nconst float _u3c0_ = 3.14;\n"));
+        assert!(emitter
+            .code()
+            .contains("n_ufa_mero = 1;\nprintf(\"%g\\n\", n_ufa_mero*_u3c0_);\n"));
+    }
+
+    #[test]
+    fn test_emit_const() {
+        let emitter = emit("LET x = 1\nCONST PI = 3.14159\nCONST TAU = 2 * PI\nPRINT PI * x");
+        assert!(emitter.output().contains(
+            "int main(int argc, char *argv[]) {\nfloat x;\nconst float PI = 3.14159;\nconst float TAU = 2*PI;\n"
+        ));
+        assert_eq!(
+            emitter.code(),
+            "x = 1;\nprintf(\"%g\\n\", PI*x);\nreturn 0;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_emit_int_loop_counter() {
+        let input = "INT i = 0\nWHILE i < 3 REPEAT\nPRINT i\nLET i = i + 1\nENDWHILE\n";
+        let emitter = emit(input);
+        assert!(emitter.output().contains("int i;\n"));
+        assert!(!emitter.output().contains("float i;"));
+        assert_eq!(
+            emitter.code(),
+            "i = 0;\nwhile (i<3) {\nprintf(\"%d\\n\", i);\ni = i+1;\n}\nreturn 0;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_emit_assignment() {
+        let emitter = emit("LET x = 1\nx = x * 2\n");
+        assert_eq!(emitter.output().matches("float x;").count(), 1);
+        assert!(emitter.code().starts_with("x = 1;\nx = x*2;\n"));
+    }
+
+    #[test]
+    fn test_emit_hex_and_binary_literals() {
+        let emitter = emit("INT mask = 0xFF\nINT flags = 0b1010\n");
+        assert!(emitter.code().starts_with("mask = 0xFF;\nflags = 10;\n"));
+    }
+
+    #[test]
+    fn test_emit_int_print_formats() {
+        let input =
+            "INT n = 4\nLET x = 1.5\nPRINT n * 2\nPRINT n * x\nPRINT n / 2.5\nPRINT 3\nINPUT n\n";
+        let code = emit(input).code().to_string();
+        assert!(code.contains("printf(\"%d\\n\", n*2);\n"));
+        assert!(code.contains("printf(\"%g\\n\", n*x);\n"));
+        assert!(code.contains("printf(\"%g\\n\", n/2.5);\n"));
+        assert!(code.contains("printf(\"%d\\n\", 3);\n"));
+        assert!(code.contains("scanf(\"%d\", &n)"));
+    }
+
+    #[test]
+    fn test_emit_exponent_literals_are_floats() {
+        let code = emit("INT n = 4\nPRINT n * 1e3\nPRINT n + 0xE\n")
+            .code()
+            .to_string();
+        assert!(code.contains("printf(\"%g\\n\", n*1e3);\n"));
+        assert!(code.contains("printf(\"%d\\n\", n+0xE);\n"));
+    }
+
+    #[test]
+    fn test_emit_indented() {
+        let input = "INT i = 0
+WHILE i < 3 REPEAT
+    IF i == 1 THEN
+        PRINT \"one\"
+    ELSE
+        PRINT i
+    ENDIF
+    i = i + 1
+ENDWHILE
+";
+        let emitter =
+            emit_with(input, |emitter| emitter.indent_width = DEFAULT_INDENT_WIDTH).unwrap();
+        assert_eq!(
+            emitter.output(),
+            "#include <stdio.h>
+int main(int argc, char *argv[]) {
+    int i;
+    i = 0;
+    while (i<3) {
+        if (i==1) {
+            printf(\"one\\n\");
+        } else {
+            printf(\"%d\\n\", i);
+        }
+        i = i+1;
+    }
+    return 0;
+}
+"
+        );
+
+        let emitter = emit_with(input, |emitter| emitter.indent_width = 2).unwrap();
+        assert!(emitter
+            .output()
+            .contains("\n  while (i<3) {\n    if (i==1) {\n      printf("));
+    }
 }
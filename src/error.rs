@@ -0,0 +1,205 @@
+//! The error module
+
+use crate::lexer::normalize_line_endings;
+use std::error::Error;
+use std::fmt;
+
+/// An error that points at a position in the source.
+pub trait Diagnostic: fmt::Display {
+    /// The line and column the error was found at, counting from 1.
+    fn position(&self) -> (usize, usize);
+
+    /// The error followed by the line of source it was found on, with a `^`
+    /// under its column, as in:
+    ///
+    /// ```text
+    /// error at 2:11: Undeclared variable: "b"
+    ///   |
+    /// 2 | PRINT a + b
+    ///   |           ^
+    /// ```
+    ///
+    /// Just the error if the position is past the end of the source.
+    fn render(&self, source: &str) -> String {
+        let (line, col) = self.position();
+        let source = normalize_line_endings(source);
+        let text = match source.lines().nth(line.wrapping_sub(1)) {
+            Some(text) => text,
+            None => return self.to_string(),
+        };
+
+        // tabs are kept, so that the caret lines up however wide they are
+        let marker = text
+            .chars()
+            .take(col.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let gutter = " ".repeat(line.to_string().len());
+
+        format!(
+            "{}\n{} |\n{} | {}\n{} | {}^",
+            self, gutter, line, text, gutter, marker
+        )
+    }
+}
+
+/// An error in the source program, along with the position it was found at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub message: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl CompileError {
+    pub fn new(message: &str, line: usize, col: usize) -> Self {
+        CompileError {
+            message: message.to_string(),
+            line,
+            col,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error at {}:{}: {}", self.line, self.col, self.message)
+    }
+}
+
+impl Error for CompileError {}
+
+impl Diagnostic for CompileError {
+    fn position(&self) -> (usize, usize) {
+        (self.line, self.col)
+    }
+}
+
+/// All of the errors found in a source program, in the order they were found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileErrors(pub Vec<CompileError>);
+
+impl fmt::Display for CompileErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, err) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", err)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for CompileErrors {}
+
+/// The first errors found in a source program, when there were too many to
+/// report them all.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TooManyErrors(pub Vec<CompileError>);
+
+impl fmt::Display for TooManyErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for err in &self.0 {
+            writeln!(f, "{}", err)?;
+        }
+        write!(f, "too many errors; aborting")
+    }
+}
+
+impl Error for TooManyErrors {}
+
+/// Something in the source program that is allowed but probably not what
+/// was meant, e.g. code that can never run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub message: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Warning {
+    pub fn new(message: &str, line: usize, col: usize) -> Self {
+        Warning {
+            message: message.to_string(),
+            line,
+            col,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "warning at {}:{}: {}", self.line, self.col, self.message)
+    }
+}
+
+impl Diagnostic for Warning {
+    fn position(&self) -> (usize, usize) {
+        (self.line, self.col)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::{CompileError, CompileErrors, Diagnostic, TooManyErrors, Warning};
+
+    #[test]
+    fn test_display() {
+        let err = CompileError::new("Undeclared variable: \"x\"", 3, 7);
+        assert_eq!(err.to_string(), "error at 3:7: Undeclared variable: \"x\"");
+    }
+
+    #[test]
+    fn test_render() {
+        let err = CompileError::new("Undeclared variable: \"b\"", 2, 11);
+        assert_eq!(
+            err.render("LET a = 1\nPRINT a + b\n"),
+            "error at 2:11: Undeclared variable: \"b\"\n  |\n2 | PRINT a + b\n  |           ^"
+        );
+
+        let err = CompileError::new("Unexpected token: \")\"", 10, 3);
+        let source = "\n".repeat(9) + "\tx = )\r\n";
+        assert_eq!(
+            err.render(&source),
+            "error at 10:3: Unexpected token: \")\"\n   |\n10 | \tx = )\n   | \t ^"
+        );
+    }
+
+    #[test]
+    fn test_render_warning() {
+        let warning = Warning::new("condition is always false", 1, 4);
+        assert_eq!(
+            warning.render("IF 0 > 1 THEN\n"),
+            "warning at 1:4: condition is always false\n  |\n1 | IF 0 > 1 THEN\n  |    ^"
+        );
+    }
+
+    #[test]
+    fn test_render_past_the_end() {
+        let err = CompileError::new("unterminated IF block started at line 1", 3, 1);
+        assert_eq!(err.render("IF 1 > 0 THEN\n"), err.to_string());
+    }
+
+    #[test]
+    fn test_display_multiple() {
+        let errs = CompileErrors(vec![
+            CompileError::new("Undeclared variable: \"x\"", 3, 7),
+            CompileError::new("Duplicate label: \"top\"", 5, 7),
+        ]);
+        assert_eq!(
+            errs.to_string(),
+            "error at 3:7: Undeclared variable: \"x\"\nerror at 5:7: Duplicate label: \"top\""
+        );
+    }
+
+    #[test]
+    fn test_display_too_many() {
+        let errs = TooManyErrors(vec![CompileError::new("Undeclared variable: \"x\"", 3, 7)]);
+        assert_eq!(
+            errs.to_string(),
+            "error at 3:7: Undeclared variable: \"x\"\ntoo many errors; aborting"
+        );
+    }
+}
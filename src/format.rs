@@ -0,0 +1,497 @@
+//! The format module, a pretty-printer that rewrites Teeny Tiny source in a
+//! canonical layout
+
+use crate::ast::{BinaryOp, Expr, Span, Stmt, UnaryOp};
+use crate::lexer::normalize_line_endings;
+use crate::parser::Parser;
+use crate::GenResult;
+
+/// Spaces per level of indentation in formatted source.
+const INDENT_WIDTH: usize = 4;
+
+/// The binding strength of a binary operator in Teeny Tiny, used to decide
+/// where the formatted source needs parentheses.
+fn precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Or => 1,
+        BinaryOp::And => 2,
+        BinaryOp::Eq
+        | BinaryOp::NotEq
+        | BinaryOp::Lt
+        | BinaryOp::Lte
+        | BinaryOp::Gt
+        | BinaryOp::Gte => 3,
+        BinaryOp::Add | BinaryOp::Sub => 4,
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 5,
+        BinaryOp::Pow => 6,
+    }
+}
+
+/// Encodes a decoded string literal back into Teeny Tiny source, the reverse
+/// of what the lexer does.
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in value.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\0' => escaped.push_str("\\0"),
+            c if c.is_ascii_control() => escaped.push_str(&format!("\\x{:02X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Formats Teeny Tiny source: one statement per line, bodies indented by
+/// four spaces, single spaces around binary operators and after commas, and
+/// only the parentheses that are needed. Comments are kept, as are single
+/// blank lines between statements. Formatting is idempotent.
+pub fn format_source(source: &str) -> GenResult<String> {
+    // formatting never generates the raw C, so it's always allowed
+    let mut parser = Parser::from_source(source)?;
+    parser.allow_raw = true;
+    let program = parser.parse()?;
+
+    let mut formatter = Formatter {
+        source: normalize_line_endings(source)
+            .lines()
+            .map(|line| line.chars().collect())
+            .collect(),
+        output: String::new(),
+        depth: 0,
+    };
+    formatter.block(&program);
+
+    Ok(formatter.output)
+}
+
+struct Formatter {
+    /// The source, by line, to copy number literals from as written.
+    source: Vec<Vec<char>>,
+    output: String,
+    depth: usize,
+}
+
+impl Formatter {
+    fn line(&mut self, text: &str) {
+        self.output.push_str(&" ".repeat(self.depth * INDENT_WIDTH));
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+
+    /// The source text of a span on a single line.
+    fn text(&self, span: Span) -> String {
+        self.source[span.start.line - 1][span.start.col - 1..span.end.col - 1]
+            .iter()
+            .collect()
+    }
+
+    fn indented(&mut self, stmts: &[Stmt]) {
+        self.depth += 1;
+        self.block(stmts);
+        self.depth -= 1;
+    }
+
+    /// Formats a list of statements. A comment on the same line as the
+    /// statement before it stays there, and a gap of blank lines between two
+    /// statements becomes a single blank line.
+    fn block(&mut self, stmts: &[Stmt]) {
+        let mut last_line = None;
+
+        for stmt in stmts {
+            let span = stmt.span();
+            let doc = match stmt {
+                Stmt::Let { doc, .. } | Stmt::Input { doc, .. } => doc.as_deref(),
+                _ => None,
+            };
+            let first_line = span.start.line - doc.map_or(0, |doc| doc.lines().count());
+
+            if let (Stmt::Comment(text, _), Some(line)) = (stmt, last_line) {
+                if span.start.line == line {
+                    self.output.pop();
+                    self.output.push_str(&format!(" {}\n", comment(text)));
+                    continue;
+                }
+            }
+            if last_line.is_some_and(|line| first_line > line + 1) {
+                self.output.push('\n');
+            }
+
+            for line in doc.map(str::lines).into_iter().flatten() {
+                self.line(&comment(line));
+            }
+
+            self.statement(stmt);
+            last_line = Some(span.end.line);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Print(items, _) => {
+                let items = self.items(items);
+                self.line(&format!("PRINT {}", items));
+            }
+
+            Stmt::Write(items, _) => {
+                let items = self.items(items);
+                self.line(&format!("WRITE {}", items));
+            }
+
+            // an inline IF, the only kind to fit on one line
+            Stmt::If {
+                branches,
+                else_body: None,
+                span,
+            } if span.start.line == span.end.line => {
+                let (cond, body) = &branches[0];
+                let cond = self.expression(cond);
+
+                let (depth, mark) = (self.depth, self.output.len());
+                self.depth = 0;
+                self.statement(&body[0]);
+                let body = self.output.split_off(mark);
+                self.depth = depth;
+
+                self.line(&format!("IF {} THEN {}", cond, body.trim_end()));
+            }
+
+            Stmt::If {
+                branches,
+                else_body,
+                ..
+            } => {
+                for (i, (cond, body)) in branches.iter().enumerate() {
+                    let keyword = if i == 0 { "IF" } else { "ELSEIF" };
+                    let cond = self.expression(cond);
+                    self.line(&format!("{} {} THEN", keyword, cond));
+                    self.indented(body);
+                }
+
+                if let Some(body) = else_body {
+                    self.line("ELSE");
+                    self.indented(body);
+                }
+                self.line("ENDIF");
+            }
+
+            Stmt::While { cond, body, .. } => {
+                let cond = self.expression(cond);
+                self.line(&format!("WHILE {} REPEAT", cond));
+                self.indented(body);
+                self.line("ENDWHILE");
+            }
+
+            Stmt::Assert(cond, _) => {
+                let cond = self.expression(cond);
+                self.line(&format!("ASSERT {}", cond));
+            }
+
+            Stmt::Return(value, _) => {
+                let value = self.expression(value);
+                self.line(&format!("RETURN {}", value));
+            }
+
+            Stmt::Exit(Some(value), _) => {
+                let value = self.expression(value);
+                self.line(&format!("EXIT {}", value));
+            }
+
+            Stmt::Exit(None, _) => self.line("EXIT"),
+
+            Stmt::DoWhile { body, cond, .. } => {
+                self.line("DO");
+                self.indented(body);
+                let cond = self.expression(cond);
+                self.line(&format!("WHILE {}", cond));
+            }
+
+            Stmt::Label(name, _) => self.line(&format!("LABEL {}", name)),
+
+            Stmt::Goto(name, _) => self.line(&format!("GOTO {}", name)),
+
+            Stmt::Emit(code, _) => self.line(&format!("EMIT \"{}\"", escape(code))),
+
+            Stmt::Swap(first, second, _) => self.line(&format!("SWAP {}, {}", first, second)),
+
+            Stmt::Break(_) => self.line("BREAK"),
+
+            Stmt::Continue(_) => self.line("CONTINUE"),
+
+            Stmt::Let { name, value, .. } => {
+                let value = self.expression(value);
+                self.line(&format!("LET {} = {}", name, value));
+            }
+
+            Stmt::Int { name, value, .. } => {
+                let value = self.expression(value);
+                self.line(&format!("INT {} = {}", name, value));
+            }
+
+            Stmt::Const { name, value, .. } => {
+                let value = self.expression(value);
+                self.line(&format!("CONST {} = {}", name, value));
+            }
+
+            Stmt::Assign { name, value, .. } => {
+                let value = self.expression(value);
+                self.line(&format!("{} = {}", name, value));
+            }
+
+            Stmt::CompoundAssign {
+                name, op, value, ..
+            } => {
+                let value = self.expression(value);
+                self.line(&format!("{} {}= {}", name, op.spelling(), value));
+            }
+
+            Stmt::Input { prompt, name, .. } => match prompt {
+                Some(prompt) => self.line(&format!("INPUT \"{}\", {}", escape(prompt), name)),
+                None => self.line(&format!("INPUT {}", name)),
+            },
+
+            Stmt::Comment(text, _) => self.line(&comment(text)),
+        }
+    }
+
+    fn items(&self, items: &[Expr]) -> String {
+        items
+            .iter()
+            .map(|item| self.expression(item))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn expression(&self, expr: &Expr) -> String {
+        match expr {
+            // kept as written, so that e.g. hex digits and digit separators
+            // survive, but without any parentheses around it
+            Expr::Number(..) => self
+                .text(expr.span())
+                .trim_matches(|c: char| c == '(' || c == ')' || c.is_whitespace())
+                .to_string(),
+
+            Expr::Str(value, _) => format!("\"{}\"", escape(value)),
+
+            Expr::Var(name, _) => name.clone(),
+
+            // `NOT` takes in comparisons but not `AND` or `OR`
+            Expr::Unary {
+                op: UnaryOp::Not,
+                expr,
+                ..
+            } => match **expr {
+                Expr::Binary {
+                    op: BinaryOp::And | BinaryOp::Or,
+                    ..
+                } => format!("NOT ({})", self.expression(expr)),
+                _ => format!("NOT {}", self.expression(expr)),
+            },
+
+            // the operand of a sign is a primary, so anything else is
+            // parenthesized
+            Expr::Unary { op, expr, .. } => match **expr {
+                Expr::Unary { .. } | Expr::Binary { .. } => {
+                    format!("{}({})", op.spelling(), self.expression(expr))
+                }
+                _ => format!("{}{}", op.spelling(), self.expression(expr)),
+            },
+
+            Expr::Binary { .. } => {
+                let (first, links) = expr.left_chain();
+                let mut lhs = first;
+                let mut code = self.expression(first);
+                for link in links {
+                    code = self.parenthesize(lhs, code, link.op, false);
+                    let rhs = self.operand(link.rhs, link.op, true);
+                    code.push_str(&format!(" {} {}", link.op.spelling(), rhs));
+                    lhs = link.expr;
+                }
+                code
+            }
+
+            Expr::Call { func, args, .. } => format!("{}({})", func.name(), self.items(args)),
+        }
+    }
+
+    /// Formats the operand of a binary operator, parenthesizing it if it
+    /// would otherwise group differently. `^` groups to the right and every
+    /// other operator to the left.
+    fn operand(&self, expr: &Expr, parent: BinaryOp, is_rhs: bool) -> String {
+        self.parenthesize(expr, self.expression(expr), parent, is_rhs)
+    }
+
+    /// Parenthesizes a formatted operand, as `operand` does.
+    fn parenthesize(&self, expr: &Expr, code: String, parent: BinaryOp, is_rhs: bool) -> String {
+        match expr {
+            Expr::Binary { op, .. } => {
+                let (own, parent_prec) = (precedence(*op), precedence(parent));
+                let right_assoc = parent == BinaryOp::Pow;
+                // a comparison compared without parentheses is a chained one
+                let chained = op.is_comparison() && parent.is_comparison();
+                if own < parent_prec || (own == parent_prec && is_rhs != right_assoc) || chained {
+                    format!("({})", code)
+                } else {
+                    code
+                }
+            }
+            // and so would take the rest of a comparison or sum with it
+            Expr::Unary {
+                op: UnaryOp::Not, ..
+            } if precedence(parent) >= precedence(BinaryOp::Eq) => format!("({})", code),
+            _ => code,
+        }
+    }
+}
+
+fn comment(text: &str) -> String {
+    if text.is_empty() {
+        "#".to_string()
+    } else {
+        format!("# {}", text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::clear_positions;
+    use crate::format::format_source;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::{compile, interpret};
+
+    #[test]
+    fn test_format_layout() {
+        let source = "\
+# setup
+LET  a=1   # one
+INT i=0x1_F
+
+
+
+WHILE i<3 AND NOT a==2 REPEAT
+IF i==0 THEN
+PRINT \"zero\\t\\%\",i,(a+i)*2
+ELSEIF i==1 THEN
+WRITE -(-a)
+ELSE
+PRINT a-(i-1), 2^(3^2), (2^3)^2, -a^2
+ENDIF
+i=i+1
+ENDWHILE # done
+INPUT \"n? \",n
+";
+        assert_eq!(
+            format_source(source).unwrap(),
+            "\
+# setup
+LET a = 1 # one
+INT i = 0x1_F
+
+WHILE i < 3 AND NOT a == 2 REPEAT
+    IF i == 0 THEN
+        PRINT \"zero\\t%\", i, (a + i) * 2
+    ELSEIF i == 1 THEN
+        WRITE -(-a)
+    ELSE
+        PRINT a - (i - 1), 2 ^ 3 ^ 2, (2 ^ 3) ^ 2, -a ^ 2
+    ENDIF
+    i = i + 1
+ENDWHILE # done
+INPUT \"n? \", n
+"
+        );
+    }
+
+    #[test]
+    fn test_format_samples_round_trip() {
+        for entry in std::fs::read_dir("samples").unwrap() {
+            let path = entry.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+
+            let formatted = format_source(&source).unwrap();
+            assert_eq!(format_source(&formatted).unwrap(), formatted, "{:?}", path);
+
+            let parse = |source: &str| {
+                let mut program = Parser::new(Lexer::new(source)).unwrap().parse().unwrap();
+                clear_positions(&mut program);
+                program
+            };
+            assert_eq!(parse(&formatted), parse(&source), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn test_format_inline_if() {
+        let source = "LET a=1\nWHILE a<3 REPEAT\nIF a==2 THEN   PRINT a # two\nIF a>0 THEN IF a<2 THEN a=a+1\na=a+1\nENDWHILE\n";
+        assert_eq!(
+            format_source(source).unwrap(),
+            "\
+LET a = 1
+WHILE a < 3 REPEAT
+    IF a == 2 THEN PRINT a # two
+    IF a > 0 THEN IF a < 2 THEN a = a + 1
+    a = a + 1
+ENDWHILE
+"
+        );
+    }
+
+    #[test]
+    fn test_format_not_keeps_grouping() {
+        for condition in [
+            "NOT (x > 1 AND y > 2)",
+            "NOT (x > 1 OR x < 0)",
+            "(NOT x > 1) == (NOT y > 2)",
+        ] {
+            let source = format!(
+                "INPUT x\nINPUT y\nLET v = {0}\nPRINT v\nIF {0} THEN\nPRINT \"yes\"\nENDIF\n",
+                condition
+            );
+            let formatted = format_source(&source).unwrap();
+            assert_eq!(format_source(&formatted).unwrap(), formatted);
+            assert_eq!(compile(&formatted).unwrap(), compile(&source).unwrap());
+            for input in ["1\n3\n", "2\n1\n", "-1\n3\n"] {
+                assert_eq!(
+                    interpret(&formatted, input).unwrap(),
+                    interpret(&source, input).unwrap(),
+                    "{}",
+                    formatted
+                );
+            }
+        }
+        assert_eq!(
+            format_source("INPUT a\nINPUT b\nPRINT NOT(a>1 OR b>1) AND (NOT a>1)==0\n").unwrap(),
+            "INPUT a\nINPUT b\nPRINT NOT (a > 1 OR b > 1) AND (NOT a > 1) == 0\n"
+        );
+    }
+
+    #[test]
+    fn test_format_compared_comparisons() {
+        let source = "INPUT x\nPRINT (x>0)==1, 1==(x>0), (x>0)<(x>1)\n";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(
+            formatted,
+            "INPUT x\nPRINT (x > 0) == 1, 1 == (x > 0), (x > 0) < (x > 1)\n"
+        );
+        assert_eq!(compile(&formatted).unwrap(), compile(source).unwrap());
+    }
+
+    #[test]
+    fn test_format_string_escapes() {
+        assert_eq!(
+            format_source(r#"PRINT "\x41\x1b\x7f\0""#).unwrap(),
+            "PRINT \"A\\x1B\\x7F\\0\"\n"
+        );
+    }
+
+    #[test]
+    fn test_format_error() {
+        assert!(format_source("LET x = )").is_err());
+    }
+}
@@ -0,0 +1,948 @@
+//! The interpreter module, which runs a program directly instead of
+//! generating code for it
+
+use crate::ast::{BinaryOp, Builtin, Expr, Pos, Stmt, Type, UnaryOp};
+use crate::error::CompileError;
+use crate::lexer::number;
+use crate::symbols::{Symbol, SymbolTable};
+use crate::typecheck;
+use crate::GenResult;
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The value of a variable or expression. Integers are kept as `f64`s too,
+/// and truncated wherever C would.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Num(f64),
+    Str(String),
+}
+
+impl Value {
+    fn num(&self) -> f64 {
+        match self {
+            Value::Num(n) => *n,
+            Value::Str(_) => unreachable!("the type checker rejects strings in expressions"),
+        }
+    }
+
+    fn str(&self) -> &str {
+        match self {
+            Value::Str(s) => s,
+            Value::Num(_) => unreachable!("the type checker only allows strings here"),
+        }
+    }
+}
+
+/// A statement flattened into straight-line code, so that `GOTO` can jump
+/// anywhere by index, even into or out of a block.
+enum Op<'a> {
+    /// Prints the items, followed by a newline or not.
+    Print(&'a [Expr], bool),
+    Assign(&'a str, &'a Expr),
+    /// Stores `name op value` back in `name`.
+    CompoundAssign(&'a str, BinaryOp, Pos, &'a Expr),
+    Swap(&'a str, &'a str),
+    Input(&'a Option<String>, &'a str),
+    /// Fails unless the condition holds, reporting the line of the `ASSERT`.
+    Assert(&'a Expr, usize),
+    /// Stops the program, with the value as its exit status, or 0 without one.
+    Return(Option<&'a Expr>),
+    /// Jumps to the index unless the condition holds.
+    JumpUnless(&'a Expr, usize),
+    Jump(usize),
+    Goto(&'a str),
+    /// Fails, as raw C can't be run, reporting the line of the `EMIT`.
+    Emit(usize),
+}
+
+/// Flattens a program into ops, working out the type of every variable the
+/// way the C backend declares them: by the first assignment in its scope.
+#[derive(Default)]
+struct Flattener<'a> {
+    ops: Vec<Op<'a>>,
+    /// The scope each op runs in, as an index into `tables`.
+    op_scopes: Vec<usize>,
+    labels: HashMap<&'a str, usize>,
+    /// Constants, which are set before anything else runs, as in C, along
+    /// with their scope.
+    constants: Vec<(&'a str, &'a Expr, usize)>,
+    symbols: SymbolTable,
+    /// The variables visible at the end of the program as a whole and of
+    /// each block, which are all that any op in it can use.
+    tables: Vec<SymbolTable>,
+    scope: usize,
+    /// For each loop the current statement is in, innermost last, the
+    /// `CONTINUE` jumps to patch to the end of its body and the `BREAK` jumps
+    /// to patch to the end of the loop.
+    loops: Vec<(Vec<usize>, Vec<usize>)>,
+}
+
+impl<'a> Flattener<'a> {
+    fn new(program: &'a [Stmt]) -> Self {
+        let mut flattener = Flattener {
+            tables: vec![SymbolTable::new()],
+            ..Default::default()
+        };
+        flattener.block(program);
+        flattener.tables[0] = flattener.symbols.clone();
+        flattener
+    }
+
+    fn push(&mut self, op: Op<'a>) {
+        self.ops.push(op);
+        self.op_scopes.push(self.scope);
+    }
+
+    fn declare(&mut self, name: &str, ty: Type) {
+        self.symbols
+            .get_or_declare(name, Symbol::new(ty, Default::default()));
+    }
+
+    /// Patches the target of the jump at `at` to the next op.
+    fn patch(&mut self, at: usize) {
+        let here = self.ops.len();
+        match &mut self.ops[at] {
+            Op::JumpUnless(_, target) | Op::Jump(target) => *target = here,
+            _ => unreachable!("only jumps are patched"),
+        }
+    }
+
+    /// Flattens the body of a loop, patching any `CONTINUE` in it to what
+    /// comes after it.
+    fn loop_body(&mut self, body: &'a [Stmt]) {
+        self.loops.push((Vec::new(), Vec::new()));
+        self.body(body);
+
+        let continues = std::mem::take(&mut self.loops.last_mut().unwrap().0);
+        for at in continues {
+            self.patch(at);
+        }
+    }
+
+    /// Patches any `BREAK` in the loop just flattened to what comes after it.
+    fn end_loop(&mut self) {
+        let (_, breaks) = self.loops.pop().unwrap();
+        for at in breaks {
+            self.patch(at);
+        }
+    }
+
+    fn block(&mut self, stmts: &'a [Stmt]) {
+        for stmt in stmts {
+            self.statement(stmt);
+        }
+    }
+
+    /// Flattens the body of an `IF`, `ELSE` or loop, in a scope of its own.
+    fn body(&mut self, stmts: &'a [Stmt]) {
+        let outer = self.scope;
+        self.scope = self.tables.len();
+        self.tables.push(SymbolTable::new());
+        self.symbols.push_scope();
+
+        self.block(stmts);
+
+        self.tables[self.scope] = self.symbols.clone();
+        self.symbols.pop_scope();
+        self.scope = outer;
+    }
+
+    fn statement(&mut self, stmt: &'a Stmt) {
+        match stmt {
+            Stmt::Print(items, _) => self.push(Op::Print(items, true)),
+
+            Stmt::Write(items, _) => self.push(Op::Print(items, false)),
+
+            Stmt::Assert(cond, span) => self.push(Op::Assert(cond, span.start.line)),
+
+            Stmt::Return(value, _) => self.push(Op::Return(Some(value))),
+
+            Stmt::Exit(value, _) => self.push(Op::Return(value.as_ref())),
+
+            Stmt::If {
+                branches,
+                else_body,
+                ..
+            } => {
+                let mut ends = Vec::new();
+                for (cond, body) in branches {
+                    let skip = self.ops.len();
+                    self.push(Op::JumpUnless(cond, 0));
+                    self.body(body);
+                    ends.push(self.ops.len());
+                    self.push(Op::Jump(0));
+                    self.patch(skip);
+                }
+
+                if let Some(body) = else_body {
+                    self.body(body);
+                }
+                for end in ends {
+                    self.patch(end);
+                }
+            }
+
+            Stmt::While { cond, body, .. } => {
+                let start = self.ops.len();
+                self.push(Op::JumpUnless(cond, 0));
+                self.loop_body(body);
+                self.push(Op::Jump(start));
+                self.patch(start);
+                self.end_loop();
+            }
+
+            Stmt::DoWhile { body, cond, .. } => {
+                let start = self.ops.len();
+                self.loop_body(body);
+                let exit = self.ops.len();
+                self.push(Op::JumpUnless(cond, 0));
+                self.push(Op::Jump(start));
+                self.patch(exit);
+                self.end_loop();
+            }
+
+            Stmt::Label(name, _) => {
+                self.labels.insert(name, self.ops.len());
+            }
+
+            Stmt::Goto(name, _) => self.push(Op::Goto(name)),
+
+            Stmt::Emit(_, span) => self.push(Op::Emit(span.start.line)),
+
+            Stmt::Swap(first, second, _) => self.push(Op::Swap(first, second)),
+
+            Stmt::Break(_) => {
+                let at = self.ops.len();
+                self.push(Op::Jump(0));
+                let (_, breaks) = self
+                    .loops
+                    .last_mut()
+                    .expect("the parser rejects BREAK outside a loop");
+                breaks.push(at);
+            }
+
+            Stmt::Continue(_) => {
+                let at = self.ops.len();
+                self.push(Op::Jump(0));
+                let (continues, _) = self
+                    .loops
+                    .last_mut()
+                    .expect("the parser rejects CONTINUE outside a loop");
+                continues.push(at);
+            }
+
+            Stmt::Let { name, value, .. } => {
+                let ty = typecheck::let_type(value, &self.symbols);
+                self.declare(name, ty);
+                self.push(Op::Assign(name, value));
+            }
+
+            Stmt::Int { name, value, .. } => {
+                self.declare(name, Type::Int);
+                self.push(Op::Assign(name, value));
+            }
+
+            Stmt::Const { name, value, .. } => {
+                self.declare(name, Type::Float);
+                self.constants.push((name, value, self.scope));
+            }
+
+            Stmt::Assign { name, value, .. } => self.push(Op::Assign(name, value)),
+
+            Stmt::CompoundAssign {
+                name,
+                op,
+                pos,
+                value,
+                ..
+            } => self.push(Op::CompoundAssign(name, *op, *pos, value)),
+
+            Stmt::Input { prompt, name, .. } => {
+                self.declare(name, Type::Float);
+                self.push(Op::Input(prompt, name));
+            }
+
+            Stmt::Comment(..) => {}
+        }
+    }
+}
+
+/// Formats a number as C's `%g` does: to six significant digits, in
+/// exponent form if the exponent is below -4 or above 5, and without
+/// trailing zeros.
+fn format_g(n: f64) -> String {
+    const PRECISION: usize = 6;
+    if !n.is_finite() {
+        return match n {
+            n if n.is_nan() => "nan".to_string(),
+            n if n > 0.0 => "inf".to_string(),
+            _ => "-inf".to_string(),
+        };
+    }
+
+    // the exponent once rounded to the digits shown, so 999999.5 is 1e+06
+    let scientific = format!("{:.*e}", PRECISION - 1, n);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent = exponent.parse::<i32>().unwrap();
+    let trim = |digits: &str| {
+        if digits.contains('.') {
+            digits
+                .trim_end_matches('0')
+                .trim_end_matches('.')
+                .to_string()
+        } else {
+            digits.to_string()
+        }
+    };
+
+    if exponent < -4 || exponent >= PRECISION as i32 {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim(mantissa), sign, exponent.abs())
+    } else {
+        let decimals = (PRECISION as i32 - 1 - exponent) as usize;
+        trim(&format!("{:.*}", decimals, n))
+    }
+}
+
+/// Works out an arithmetic operation or comparison, found at `pos`, where
+/// `int` is whether both operands are integers, as in C.
+fn binary(op: BinaryOp, pos: Pos, int: bool, lhs: f64, rhs: f64) -> GenResult<f64> {
+    Ok(match op {
+        BinaryOp::Add => lhs + rhs,
+        BinaryOp::Sub => lhs - rhs,
+        BinaryOp::Mul => lhs * rhs,
+        BinaryOp::Div if int => {
+            if rhs == 0.0 {
+                return Err(Box::new(CompileError::new(
+                    "integer division by zero",
+                    pos.line,
+                    pos.col,
+                )));
+            }
+            (lhs / rhs).trunc()
+        }
+        BinaryOp::Div => lhs / rhs,
+        BinaryOp::Mod => lhs % rhs,
+        BinaryOp::Pow => lhs.powf(rhs),
+        BinaryOp::Eq => (lhs == rhs) as u8 as f64,
+        BinaryOp::NotEq => (lhs != rhs) as u8 as f64,
+        BinaryOp::Lt => (lhs < rhs) as u8 as f64,
+        BinaryOp::Lte => (lhs <= rhs) as u8 as f64,
+        BinaryOp::Gt => (lhs > rhs) as u8 as f64,
+        BinaryOp::Gte => (lhs >= rhs) as u8 as f64,
+        // these short-circuit, so they're worked out as they're evaluated
+        BinaryOp::And | BinaryOp::Or => unreachable!("handled by eval"),
+    })
+}
+
+/// Runs a parsed and type checked program, reading `INPUT` from `input` and
+/// printing to `output`.
+pub struct Interpreter<R, W> {
+    /// Decimal places for printed floats, as in the C backend, which prints
+    /// them like `%g` without one.
+    pub float_precision: Option<usize>,
+    input: R,
+    output: W,
+    /// Words read from `input` but not yet used by `INPUT`.
+    words: VecDeque<String>,
+    vars: HashMap<String, Value>,
+    /// The variables visible in each scope, and the scope of the op running.
+    tables: Vec<SymbolTable>,
+    scope: usize,
+    status: i32,
+    /// The state of the xorshift generator behind `RANDOM`.
+    rng: Cell<u64>,
+}
+
+impl<R: BufRead, W: Write> Interpreter<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Interpreter {
+            float_precision: None,
+            input,
+            output,
+            words: VecDeque::new(),
+            vars: HashMap::new(),
+            tables: Vec::new(),
+            scope: 0,
+            status: 0,
+            // seeded from the clock, as the C backend seeds `rand`
+            rng: Cell::new(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_nanos() as u64)
+                    | 1,
+            ),
+        }
+    }
+
+    /// The next number from the generator behind `RANDOM`.
+    fn next_random(&self) -> u64 {
+        let mut x = self.rng.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng.set(x);
+        x
+    }
+
+    /// The exit status of the program run: the value of the `RETURN` that
+    /// ended it, or 0 if it ran to the end.
+    pub fn status(&self) -> i32 {
+        self.status
+    }
+
+    /// Runs a whole program, printing exactly what the compiled C would.
+    pub fn run(&mut self, program: &[Stmt]) -> GenResult<()> {
+        let flattener = Flattener::new(program);
+        self.tables = flattener.tables;
+        for (name, value, scope) in flattener.constants {
+            self.scope = scope;
+            let value = self.eval(value)?;
+            self.vars.insert(name.to_string(), value);
+        }
+
+        let mut pc = 0;
+        while let Some(op) = flattener.ops.get(pc) {
+            self.scope = flattener.op_scopes[pc];
+            pc += 1;
+
+            match op {
+                Op::Print(items, newline) => self.print(items, *newline)?,
+
+                Op::Assign(name, value) => {
+                    let value = self.eval(value)?;
+                    self.store(name, value);
+                }
+
+                Op::CompoundAssign(name, op, pos, value) => {
+                    let value = self.eval(&Stmt::compound_value(name, *op, *pos, value))?;
+                    self.store(name, value);
+                }
+
+                Op::Swap(first, second) => {
+                    let first_value = self.vars.remove(*first);
+                    let second_value = self.vars.remove(*second);
+                    self.vars
+                        .extend(first_value.map(|value| (second.to_string(), value)));
+                    self.vars
+                        .extend(second_value.map(|value| (first.to_string(), value)));
+                }
+
+                Op::Input(prompt, name) => {
+                    if let Some(prompt) = prompt {
+                        write!(self.output, "{}", prompt)?;
+                        self.output.flush()?;
+                    }
+                    self.input(name)?;
+                }
+
+                Op::Assert(cond, line) => {
+                    if self.eval(cond)?.num() == 0.0 {
+                        self.output.flush()?;
+                        return Err(format!("assertion failed at line {}", line).into());
+                    }
+                }
+
+                Op::Return(value) => {
+                    if let Some(value) = value {
+                        self.status = self.eval(value)?.num() as i32;
+                    }
+                    break;
+                }
+
+                Op::JumpUnless(cond, target) => {
+                    if self.eval(cond)?.num() == 0.0 {
+                        pc = *target;
+                    }
+                }
+
+                Op::Jump(target) => pc = *target,
+
+                // the parser has checked that every label is defined
+                Op::Goto(name) => pc = flattener.labels[name],
+
+                Op::Emit(line) => {
+                    self.output.flush()?;
+                    return Err(format!("EMIT at line {} can only be compiled to C", line).into());
+                }
+            }
+        }
+
+        self.output.flush()?;
+        Ok(())
+    }
+
+    /// Stores a value in a variable, truncating it if the variable is an
+    /// integer, as C would.
+    fn store(&mut self, name: &str, value: Value) {
+        let value = match (value, self.var_type(name)) {
+            (Value::Num(n), Type::Int) => Value::Num(n.trunc()),
+            (value, _) => value,
+        };
+        self.vars.insert(name.to_string(), value);
+    }
+
+    fn var_type(&self, name: &str) -> Type {
+        self.tables[self.scope].type_of(name).unwrap_or(Type::Float)
+    }
+
+    fn type_of(&self, expr: &Expr) -> Type {
+        typecheck::infer(expr, &self.tables[self.scope]).unwrap_or(Type::Float)
+    }
+
+    /// Prints the items separated by spaces, with floats to
+    /// `float_precision` decimal places or else like `%g`, as `printf` does
+    /// in the C backend.
+    fn print(&mut self, items: &[Expr], newline: bool) -> GenResult<()> {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                write!(self.output, " ")?;
+            }
+
+            match (self.eval(item)?, self.type_of(item)) {
+                (Value::Str(s), _) => write!(self.output, "{}", s)?,
+                (Value::Num(n), Type::Int) => write!(self.output, "{}", n as i64)?,
+                (Value::Num(n), _) => match self.float_precision {
+                    Some(precision) => write!(self.output, "{:.*}", precision, n)?,
+                    None => write!(self.output, "{}", format_g(n))?,
+                },
+            }
+        }
+
+        if newline {
+            writeln!(self.output)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the next word of input into a variable. A word that isn't a
+    /// number reads as 0, and the variable is left alone at the end of the
+    /// input, as with `scanf`.
+    fn input(&mut self, name: &str) -> GenResult<()> {
+        while self.words.is_empty() {
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            self.words
+                .extend(line.split_whitespace().map(|word| word.to_string()));
+        }
+
+        let word = self.words.pop_front().unwrap();
+        let value = match self.var_type(name) {
+            Type::Int => word.parse::<i64>().map(|n| n as f64).unwrap_or(0.0),
+            _ => word.parse().unwrap_or(0.0),
+        };
+        self.vars.insert(name.to_string(), Value::Num(value));
+
+        Ok(())
+    }
+
+    fn eval(&self, expr: &Expr) -> GenResult<Value> {
+        let value = match expr {
+            Expr::Number(value, _) => number(value),
+
+            Expr::Str(value, _) => return Ok(Value::Str(value.clone())),
+
+            Expr::Var(name, _) => {
+                return Ok(self.vars.get(name).cloned().unwrap_or(Value::Num(0.0)));
+            }
+
+            Expr::Unary { op, expr, .. } => {
+                let value = self.eval(expr)?.num();
+                match op {
+                    UnaryOp::Plus => value,
+                    UnaryOp::Minus => -value,
+                    UnaryOp::Not => (value == 0.0) as u8 as f64,
+                }
+            }
+
+            // the type of each operation is worked out from those of its
+            // operands as the chain is walked, rather than from scratch
+            Expr::Binary { .. } => {
+                let (first, links) = expr.left_chain();
+                let mut lhs_type = self.type_of(first);
+                let mut lhs = self.eval(first)?.num();
+
+                for link in links {
+                    let rhs_type = self.type_of(link.rhs);
+                    lhs = match link.op {
+                        BinaryOp::And => {
+                            (lhs != 0.0 && self.eval(link.rhs)?.num() != 0.0) as u8 as f64
+                        }
+                        BinaryOp::Or => {
+                            (lhs != 0.0 || self.eval(link.rhs)?.num() != 0.0) as u8 as f64
+                        }
+                        op => {
+                            let rhs = self.eval(link.rhs)?.num();
+                            let int = lhs_type == Type::Int && rhs_type == Type::Int;
+                            binary(op, link.pos, int, lhs, rhs)?
+                        }
+                    };
+                    lhs_type = typecheck::binary_type(link.op, link.pos, lhs_type, rhs_type)
+                        .unwrap_or(Type::Float);
+                }
+                lhs
+            }
+
+            Expr::Call {
+                func: Builtin::Random,
+                args,
+                span,
+            } => {
+                let lo = self.eval(&args[0])?.num().trunc();
+                let hi = self.eval(&args[1])?.num().trunc();
+                if hi < lo {
+                    return Err(Box::new(CompileError::new(
+                        &format!("RANDOM range is empty: {} to {}", lo, hi),
+                        span.start.line,
+                        span.start.col,
+                    )));
+                }
+                lo + (self.next_random() % (hi - lo + 1.0) as u64) as f64
+            }
+
+            Expr::Call {
+                func: Builtin::Len,
+                args,
+                ..
+            } => self.eval(&args[0])?.str().chars().count() as f64,
+
+            Expr::Call {
+                func: Builtin::Ord,
+                args,
+                ..
+            } => self
+                .eval(&args[0])?
+                .str()
+                .chars()
+                .next()
+                .map_or(0.0, |c| c as u32 as f64),
+
+            Expr::Call {
+                func: Builtin::Chr,
+                args,
+                ..
+            } => {
+                let code = self.eval(&args[0])?.num() as i64 & 127;
+                return Ok(Value::Str(char::from(code as u8).to_string()));
+            }
+
+            Expr::Call { func, args, .. } => {
+                let arg = self.eval(&args[0])?.num();
+                match func {
+                    Builtin::Sqrt => arg.sqrt(),
+                    Builtin::Abs => arg.abs(),
+                    Builtin::Sin => arg.sin(),
+                    Builtin::Cos => arg.cos(),
+                    Builtin::Floor => arg.floor(),
+                    Builtin::Random | Builtin::Len | Builtin::Ord | Builtin::Chr => {
+                        unreachable!("handled above")
+                    }
+                }
+            }
+        };
+
+        Ok(Value::Num(value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interp::{format_g, Interpreter};
+    use crate::interpret;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn run(source: &str, input: &str) -> String {
+        interpret(source, input).unwrap()
+    }
+
+    #[test]
+    fn test_interp_factorial() {
+        let source = std::fs::read_to_string("samples/factorial.teeny").unwrap();
+        assert_eq!(
+            run(&source, "5\n"),
+            "Enter the number whose factorial you wish to calculate\n\n\
+             Factorial of: \n5\n = \n120\n"
+        );
+    }
+
+    #[test]
+    fn test_interp_fib() {
+        let source = std::fs::read_to_string("samples/fib.teeny").unwrap();
+        let output = run(&source, "6");
+        assert!(output.ends_with("\n0\n1\n1\n2\n3\n5\n"));
+    }
+
+    #[test]
+    fn test_interp_block_scope() {
+        let source = "\
+LET a = 1
+IF a > 0 THEN
+    INT n = 7 / 2
+    PRINT n
+ENDIF
+IF a > 0 THEN
+    LET n = 7 / 2
+    PRINT n
+ENDIF
+";
+        assert_eq!(run(source, ""), "3\n3\n");
+    }
+
+    #[test]
+    fn test_interp_compound_assignment() {
+        let source = "\
+LET x = 1
+x += 2
+LET x *= 3
+x -= 0.5
+x /= 2
+INT i = 7
+i /= 2
+i *= 1.5
+PRINT x, i
+";
+        assert_eq!(run(source, ""), "4.25 4\n");
+    }
+
+    #[test]
+    fn test_interp_swap() {
+        let source = "LET x = 1\nLET y = 2.5\nSWAP x, y\nPRINT x, y\n";
+        assert_eq!(run(source, ""), "2.5 1\n");
+    }
+
+    #[test]
+    fn test_interp_return() {
+        let source = "INT x = 3\nPRINT x\nRETURN x * 2\nPRINT 0\n";
+        let program = Parser::new(Lexer::new(source)).unwrap().parse().unwrap();
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::new("".as_bytes(), &mut output);
+        interpreter.run(&program).unwrap();
+
+        assert_eq!(interpreter.status(), 6);
+        drop(interpreter);
+        assert_eq!(String::from_utf8(output).unwrap(), "3\n");
+    }
+
+    #[test]
+    fn test_interp_exit() {
+        for (source, status) in [
+            ("PRINT 1\nEXIT 3\nPRINT 2\n", 3),
+            ("PRINT 1\nEXIT\nPRINT 2\n", 0),
+        ] {
+            let program = Parser::new(Lexer::new(source)).unwrap().parse().unwrap();
+            let mut output = Vec::new();
+            let mut interpreter = Interpreter::new("".as_bytes(), &mut output);
+            interpreter.run(&program).unwrap();
+
+            assert_eq!(interpreter.status(), status);
+            drop(interpreter);
+            assert_eq!(String::from_utf8(output).unwrap(), "1\n");
+        }
+    }
+
+    #[test]
+    fn test_interp_comparison_values() {
+        let source = "LET x = 3\nLET flag = x > 0\nLET off = x < 0\nPRINT flag\nPRINT off\nPRINT (x == 3) * 5\n";
+        assert_eq!(run(source, ""), "1\n0\n5\n");
+    }
+
+    #[test]
+    fn test_interp_goto() {
+        let source = "\
+INT i = 0
+LABEL top
+WRITE i
+i = i + 1
+IF i < 3 THEN
+    GOTO top
+ENDIF
+PRINT \"\"
+";
+        assert_eq!(run(source, ""), "012\n");
+    }
+
+    #[test]
+    fn test_interp_break_continue() {
+        let source = "\
+INT i = 0
+WHILE i < 10 REPEAT
+    i = i + 1
+    IF i % 2 == 0 THEN
+        CONTINUE
+    ENDIF
+    IF i > 7 THEN
+        BREAK
+    ENDIF
+    INT j = 0
+    WHILE 1 == 1 REPEAT
+        j = j + 1
+        IF j == 2 THEN
+            BREAK
+        ENDIF
+    ENDWHILE
+    WRITE i, j
+ENDWHILE
+PRINT \"\"
+";
+        assert_eq!(run(source, ""), "1 23 25 27 2\n");
+    }
+
+    #[test]
+    fn test_interp_assert() {
+        assert_eq!(run("INT i = 2\nASSERT i == 2\nPRINT i\n", ""), "2\n");
+
+        let err = interpret("INT i = 2\nPRINT i\nASSERT i > 2\nPRINT i\n", "").unwrap_err();
+        assert_eq!(err.to_string(), "assertion failed at line 3");
+    }
+
+    #[test]
+    fn test_interp_do_while() {
+        let source = "\
+INT i = 5
+DO
+    PRINT i
+    i = i + 1
+WHILE i < 3
+INT j = 0
+DO
+    j = j + 1
+    IF j == 2 THEN CONTINUE
+    IF j == 4 THEN BREAK
+    WRITE j
+WHILE j < 10
+PRINT \"\"
+";
+        assert_eq!(run(source, ""), "5\n13\n");
+    }
+
+    #[test]
+    fn test_interp_goto_into_loop() {
+        let source = "\
+INT i = 5
+GOTO inside
+WHILE i < 3 REPEAT
+    LABEL inside
+    PRINT i
+    i = i + 1
+ENDWHILE
+";
+        assert_eq!(run(source, ""), "5\n");
+    }
+
+    #[test]
+    fn test_interp_format_g() {
+        // as printed by `printf("%g\n", ...)`
+        for (n, printed) in [
+            (1.23456, "1.23456"),
+            (5.0, "5"),
+            (1e20, "1e+20"),
+            (0.0001, "0.0001"),
+            (0.00001, "1e-05"),
+            (123456.0, "123456"),
+            (1234567.0, "1.23457e+06"),
+            (999999.5, "1e+06"),
+            (-0.0, "-0"),
+            (0.1 + 0.2, "0.3"),
+            (2.5e-7, "2.5e-07"),
+            (100.0, "100"),
+            (-1.5, "-1.5"),
+            (f64::INFINITY, "inf"),
+            (0.000123456789, "0.000123457"),
+        ] {
+            assert_eq!(format_g(n), printed, "{}", n);
+        }
+    }
+
+    #[test]
+    fn test_interp_arithmetic() {
+        let source = "\
+INT i = 7
+LET x = 7
+PRINT i / 2, x / 2, i % 4, 2 ^ 3, -i
+INT j = 2.9
+PRINT j, 0x10, 0b11, 1e2
+";
+        assert_eq!(run(source, ""), "3 3.5 3 8 -7\n2 16 3 100\n");
+
+        // the C would read `010` as octal, so neither runs it
+        assert!(interpret("PRINT 010\n", "").is_err());
+        assert_eq!(run("PRINT 007.5\n", ""), "7.5\n");
+    }
+
+    #[test]
+    fn test_interp_elseif_and_strings() {
+        let source = "\
+LET s = \"big\"
+INPUT n
+IF n < 10 THEN
+    s = \"small\"
+ELSEIF n < 100 THEN
+    s = \"medium\"
+ELSE
+    PRINT \"unchanged\"
+ENDIF
+PRINT n, \"is\", s
+";
+        assert_eq!(run(source, "50"), "50 is medium\n");
+        assert_eq!(run(source, "500"), "unchanged\n500 is big\n");
+    }
+
+    #[test]
+    fn test_interp_input() {
+        let source = "INPUT \"a? \", a\nINPUT b\nINPUT c\nPRINT a, b, c";
+        assert_eq!(run(source, "1.5 oops\n"), "a? 1.5 0 0\n");
+    }
+
+    #[test]
+    fn test_interp_integer_input() {
+        let source = "INT n = 0\nINPUT n\nPRINT n / 2";
+        assert_eq!(run(source, "7\n"), "3\n");
+        assert_eq!(run(source, "x\n"), "0\n");
+    }
+
+    #[test]
+    fn test_interp_const() {
+        let source = "GOTO end\nCONST PI = 3.14159\nLABEL end\nPRINT PI * 2";
+        assert_eq!(run(source, ""), "6.28318\n");
+    }
+
+    #[test]
+    fn test_interp_division_by_zero() {
+        let err = interpret("INT i = 0\nPRINT 1 / i", "").unwrap_err();
+        assert_eq!(err.to_string(), "error at 2:9: integer division by zero");
+    }
+
+    #[test]
+    fn test_interp_math_builtins() {
+        let source =
+            "LET x = -2.25\nPRINT SQRT(16), ABS(x), SIN(0), COS(0), FLOOR(x), SQRT(ABS(x))\n";
+        assert_eq!(run(source, ""), "4 2.25 0 1 -3 1.5\n");
+    }
+
+    #[test]
+    fn test_interp_string_builtins() {
+        let source = "LET s = \"abc\"\nLET c = CHR(ORD(s) + 1)\nPRINT LEN(s), ORD(s), ORD(\"\"), c, LEN(c)\n";
+        assert_eq!(run(source, ""), "3 97 0 b 1\n");
+    }
+
+    #[test]
+    fn test_interp_random() {
+        let source = "INT i = 0\nWHILE i < 50 REPEAT\nPRINT RANDOM(-2, 3.9)\ni += 1\nENDWHILE\n";
+        let output = run(source, "");
+        let values = output.lines().map(|line| line.parse::<i32>().unwrap());
+        assert!(values.clone().all(|n| (-2..=3).contains(&n)));
+        assert!(values.clone().any(|n| n != values.clone().next().unwrap()));
+
+        let err = interpret("PRINT RANDOM(2, 1)", "").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "error at 1:7: RANDOM range is empty: 2 to 1"
+        );
+    }
+}
@@ -0,0 +1,670 @@
+//! The JavaScript backend, generating a script for the browser
+
+use crate::ast::{BinaryOp, Builtin, Expr, Pos, Stmt, Type, UnaryOp};
+use crate::emitter::{doc_comment, escape_string, Emitter, DEFAULT_INDENT_WIDTH};
+use crate::symbols::{Symbol, SymbolTable};
+use crate::typecheck;
+use crate::GenResult;
+
+/// The binding strength of a binary operator in JavaScript, used to decide
+/// where the generated code needs parentheses.
+fn precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Or => 1,
+        BinaryOp::And => 2,
+        BinaryOp::Eq | BinaryOp::NotEq => 3,
+        BinaryOp::Lt | BinaryOp::Lte | BinaryOp::Gt | BinaryOp::Gte => 4,
+        BinaryOp::Add | BinaryOp::Sub => 5,
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 6,
+        // lowered to a call to Math.pow, so never needs parentheses
+        BinaryOp::Pow => 7,
+    }
+}
+
+/// Encodes a decoded string literal for a JavaScript string literal. As
+/// octal escapes aren't allowed in strict mode, control characters are
+/// written as `\x` escapes, which take exactly two digits in JavaScript.
+fn escape_js_string(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_control() && !matches!(c, '\n' | '\t') => {
+                format!("\\x{:02x}", c as u32)
+            }
+            c => escape_string(&c.to_string()),
+        })
+        .collect()
+}
+
+/// Whether any statement in a block, however deeply nested, is a `WRITE`.
+fn uses_write(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        Stmt::Write(..) => true,
+        Stmt::If {
+            branches,
+            else_body,
+            ..
+        } => {
+            branches.iter().any(|(_, body)| uses_write(body))
+                || else_body.as_deref().is_some_and(uses_write)
+        }
+        Stmt::While { body, .. } | Stmt::DoWhile { body, .. } => uses_write(body),
+        _ => false,
+    })
+}
+
+/// Generates JavaScript. Every number is a double in JavaScript, so integer
+/// division and assignments to `INT` variables truncate explicitly.
+///
+/// `console.log` always ends the line, so a program that uses `WRITE` keeps
+/// the unfinished line in a variable, and `PRINT` logs it along with its own
+/// items.
+pub struct JsEmitter {
+    /// Spaces per level of indentation.
+    pub indent_width: usize,
+    /// Decimal places for printed floats, as in the C backend. Without one,
+    /// they're printed as `String` spells them, without trailing zeros.
+    pub float_precision: Option<usize>,
+    depth: usize,
+    buffered: bool,
+    header: String,
+    code: String,
+    declared: SymbolTable,
+    /// The declarations for each block being emitted, innermost last.
+    locals: Vec<String>,
+}
+
+impl JsEmitter {
+    pub fn new() -> Self {
+        JsEmitter {
+            indent_width: DEFAULT_INDENT_WIDTH,
+            float_precision: None,
+            depth: 0,
+            buffered: false,
+            header: String::new(),
+            code: String::new(),
+            declared: SymbolTable::new(),
+            locals: Vec::new(),
+        }
+    }
+
+    /// Declares a variable at the top of the block it is first assigned in,
+    /// or of the script outside of any block, with its doc comment above it,
+    /// as in the C backend.
+    fn declare(&mut self, name: &str, ty: Type, doc: Option<&str>) {
+        if self.declared.contains(name) {
+            for line in doc.map(doc_comment).unwrap_or_default() {
+                self.emit_line(&line);
+            }
+            return;
+        }
+        self.declared.declare(name, Symbol::new(ty, Pos::default()));
+
+        for line in doc.map(doc_comment).unwrap_or_default() {
+            self.declaration_line(&line);
+        }
+
+        match ty {
+            Type::Float | Type::Int => self.declaration_line(&format!("let {} = 0;", name)),
+            Type::Str => self.declaration_line(&format!("let {} = \"\";", name)),
+        }
+    }
+
+    /// Adds a line to the declarations at the top of the current block.
+    fn declaration_line(&mut self, code: &str) {
+        let indent = " ".repeat(self.depth * self.indent_width);
+        match self.locals.last_mut() {
+            Some(locals) => {
+                locals.push_str(&indent);
+                locals.push_str(code);
+                locals.push('\n');
+            }
+            None => self.header_line(code),
+        }
+    }
+
+    fn var_type(&self, name: &str) -> Type {
+        self.declared.type_of(name).unwrap_or(Type::Float)
+    }
+
+    fn type_of(&self, expr: &Expr) -> Type {
+        typecheck::infer(expr, &self.declared).unwrap_or(Type::Float)
+    }
+
+    /// The printed form of each item, with floats to a fixed number of
+    /// decimal places if there is a `float_precision`, as in the C backend.
+    fn print_items(&mut self, items: &[Expr]) -> Vec<String> {
+        let precision = self.float_precision;
+        items
+            .iter()
+            .map(|item| match (self.type_of(item), precision) {
+                (Type::Float, Some(precision)) => match item {
+                    Expr::Var(name, _) => format!("{}.toFixed({})", name, precision),
+                    _ => format!("({}).toFixed({})", self.expression(item), precision),
+                },
+                (Type::Float, None) => format!("String({})", self.expression(item)),
+                _ => self.value(item),
+            })
+            .collect()
+    }
+
+    /// Generates JavaScript for an expression whose value is used as a
+    /// number. Conditions are booleans in JavaScript, so they are converted
+    /// to the 1 or 0 they are in C.
+    fn value(&mut self, expr: &Expr) -> String {
+        if expr.is_condition() {
+            format!("Number({})", self.expression(expr))
+        } else {
+            self.expression(expr)
+        }
+    }
+
+    /// Emits the body of an `IF`, `ELSE` or loop in a scope of its own.
+    fn emit_block(&mut self, stmts: &[Stmt]) -> GenResult<()> {
+        self.declared.push_scope();
+        self.locals.push(String::new());
+        let outer = std::mem::take(&mut self.code);
+
+        let result = stmts.iter().try_for_each(|stmt| self.emit_statement(stmt));
+
+        let body = std::mem::replace(&mut self.code, outer);
+        self.code.push_str(&self.locals.pop().unwrap());
+        self.code.push_str(&body);
+        self.declared.pop_scope();
+
+        result
+    }
+
+    /// Emits an assignment, truncating the value if the variable is an
+    /// integer and the value is not.
+    fn emit_assign(&mut self, name: &str, value: &Expr) {
+        let code = match (self.var_type(name), self.type_of(value)) {
+            (Type::Int, Type::Float) => format!("Math.trunc({})", self.expression(value)),
+            _ => self.value(value),
+        };
+        self.emit_line(&format!("{} = {};", name, code));
+    }
+
+    fn emit_statement(&mut self, stmt: &Stmt) -> GenResult<()> {
+        match stmt {
+            Stmt::Print(items, _) if self.buffered => {
+                let items = self.print_items(items);
+                self.emit_line(&format!(
+                    "console.log(line + [{}].join(\" \"));",
+                    items.join(", ")
+                ));
+                self.emit_line("line = \"\";");
+            }
+
+            Stmt::Print(items, _) => {
+                let items = self.print_items(items);
+                self.emit_line(&format!("console.log({});", items.join(", ")));
+            }
+
+            Stmt::Write(items, _) => {
+                let items = self.print_items(items);
+                self.emit_line(&format!("line += [{}].join(\" \");", items.join(", ")));
+            }
+
+            Stmt::If {
+                branches,
+                else_body,
+                ..
+            } => {
+                for (i, (cond, body)) in branches.iter().enumerate() {
+                    let cond = self.expression(cond);
+                    if i == 0 {
+                        self.emit_line(&format!("if ({}) {{", cond));
+                    } else {
+                        self.emit_line(&format!("}} else if ({}) {{", cond));
+                    }
+                    self.emit_block(body)?;
+                }
+
+                if let Some(body) = else_body {
+                    self.emit_line("} else {");
+                    self.emit_block(body)?;
+                }
+                self.emit_line("}");
+            }
+
+            Stmt::While { cond, body, .. } => {
+                let cond = self.expression(cond);
+                self.emit_line(&format!("while ({}) {{", cond));
+                self.emit_block(body)?;
+                self.emit_line("}");
+            }
+
+            Stmt::Assert(cond, span) => {
+                let cond = self.expression(cond);
+                self.emit_line(&format!("if (!({})) {{", cond));
+                self.emit_line(&format!(
+                    "throw new Error(\"assertion failed at line {}\");",
+                    span.start.line
+                ));
+                self.emit_line("}");
+            }
+
+            Stmt::DoWhile { body, cond, .. } => {
+                self.emit_line("do {");
+                self.emit_block(body)?;
+                let cond = self.expression(cond);
+                self.emit_line(&format!("}} while ({});", cond));
+            }
+
+            Stmt::Label(name, _) => {
+                return Err(format!("LABEL {} is unsupported in the JS backend", name).into())
+            }
+
+            Stmt::Goto(name, _) => {
+                return Err(format!("GOTO {} is unsupported in the JS backend", name).into())
+            }
+
+            Stmt::Return(..) => return Err("RETURN is unsupported in the JS backend".into()),
+
+            Stmt::Exit(..) => return Err("EXIT is unsupported in the JS backend".into()),
+
+            Stmt::Emit(..) => return Err("EMIT is unsupported in the JS backend".into()),
+
+            Stmt::Swap(first, second, _) => {
+                self.emit_line(&format!("[{0}, {1}] = [{1}, {0}];", first, second))
+            }
+
+            Stmt::Break(_) => self.emit_line("break;"),
+
+            Stmt::Continue(_) => self.emit_line("continue;"),
+
+            Stmt::Let {
+                name, value, doc, ..
+            } => {
+                let ty = typecheck::let_type(value, &self.declared);
+                self.declare(name, ty, doc.as_deref());
+                self.emit_assign(name, value);
+            }
+
+            Stmt::Int { name, value, .. } => {
+                self.declare(name, Type::Int, None);
+                self.emit_assign(name, value);
+            }
+
+            Stmt::Const { name, value, .. } => {
+                self.declared.declare(
+                    name,
+                    Symbol {
+                        constant: true,
+                        ..Symbol::new(Type::Float, Pos::default())
+                    },
+                );
+                let value = self.expression(value);
+                self.declaration_line(&format!("const {} = {};", name, value));
+            }
+
+            Stmt::Assign { name, value, .. } => self.emit_assign(name, value),
+
+            Stmt::CompoundAssign {
+                name,
+                op,
+                pos,
+                value,
+                ..
+            } => self.emit_assign(name, &Stmt::compound_value(name, *op, *pos, value)),
+
+            Stmt::Input {
+                prompt, name, doc, ..
+            } => {
+                self.declare(name, Type::Float, doc.as_deref());
+                let prompt = match prompt {
+                    Some(prompt) => format!("\"{}\"", escape_js_string(prompt)),
+                    None => String::new(),
+                };
+                let parse = match self.var_type(name) {
+                    Type::Float => "parseFloat",
+                    Type::Int => "parseInt",
+                    Type::Str => unreachable!("the parser rejects INPUT into a string"),
+                };
+                self.emit_line(&format!("{} = {}(prompt({})) || 0;", name, parse, prompt));
+            }
+
+            Stmt::Comment(text, _) => self.emit_line(&format!("// {}", text)),
+        }
+
+        Ok(())
+    }
+
+    /// Generates JavaScript for an expression.
+    fn expression(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Number(value, _) => value.clone(),
+
+            Expr::Str(value, _) => format!("\"{}\"", escape_js_string(value)),
+
+            Expr::Var(name, _) => name.clone(),
+
+            Expr::Unary {
+                op: UnaryOp::Not,
+                expr,
+                ..
+            } => format!("!({})", self.expression(expr)),
+
+            Expr::Unary { op, expr, .. } => {
+                let sign = if *op == UnaryOp::Minus { "-" } else { "+" };
+                // `- -x` must not run together into a decrement
+                match **expr {
+                    Expr::Unary { .. } => format!("{}({})", sign, self.expression(expr)),
+                    _ => format!("{}{}", sign, self.operand(expr, u8::MAX, false)),
+                }
+            }
+
+            // the type of each operation is worked out from those of its
+            // operands as the chain is walked, rather than from scratch
+            Expr::Binary { .. } => {
+                let (first, links) = expr.left_chain();
+                let (mut lhs, mut lhs_type) = (first, self.type_of(first));
+                let mut code = self.expression(first);
+
+                for link in links {
+                    let op = link.op;
+                    let ty = typecheck::binary_type(op, link.pos, lhs_type, self.type_of(link.rhs))
+                        .unwrap_or(Type::Float);
+
+                    code = match op {
+                        BinaryOp::Pow => {
+                            format!("Math.pow({}, {})", code, self.expression(link.rhs))
+                        }
+
+                        BinaryOp::Div if ty == Type::Int => {
+                            let lhs = self.parenthesize(lhs, lhs_type, code, precedence(op), false);
+                            let rhs = self.operand(link.rhs, precedence(op), true);
+                            format!("Math.trunc({} / {})", lhs, rhs)
+                        }
+
+                        _ => {
+                            let mut code =
+                                self.parenthesize(lhs, lhs_type, code, precedence(op), false);
+                            let rhs = self.operand(link.rhs, precedence(op), true);
+                            match op {
+                                BinaryOp::And => code.push_str(" && "),
+                                BinaryOp::Or => code.push_str(" || "),
+                                BinaryOp::Eq => code.push_str(" === "),
+                                BinaryOp::NotEq => code.push_str(" !== "),
+                                // `x - -1` must not run together into a decrement
+                                BinaryOp::Add | BinaryOp::Sub if rhs.starts_with(op.spelling()) => {
+                                    code.push_str(&format!(" {} ", op.spelling()))
+                                }
+                                _ => code.push_str(op.spelling()),
+                            }
+                            code.push_str(&rhs);
+                            code
+                        }
+                    };
+                    (lhs, lhs_type) = (link.expr, ty);
+                }
+                code
+            }
+
+            Expr::Call {
+                func: Builtin::Random,
+                args,
+                ..
+            } => {
+                let lo = self.expression(&args[0]);
+                let hi = self.expression(&args[1]);
+                format!(
+                    "(Math.trunc({0}) + Math.floor(Math.random() * (Math.trunc({1}) - Math.trunc({0}) + 1)))",
+                    lo, hi
+                )
+            }
+
+            Expr::Call {
+                func: Builtin::Len,
+                args,
+                ..
+            } => format!("{}.length", self.expression(&args[0])),
+
+            Expr::Call {
+                func: Builtin::Ord,
+                args,
+                ..
+            } => format!("({}.charCodeAt(0) || 0)", self.expression(&args[0])),
+
+            Expr::Call {
+                func: Builtin::Chr,
+                args,
+                ..
+            } => format!(
+                "String.fromCharCode(Math.trunc({}) & 127)",
+                self.expression(&args[0])
+            ),
+
+            Expr::Call { func, args, .. } => {
+                let name = match func {
+                    Builtin::Sqrt => "Math.sqrt",
+                    Builtin::Abs => "Math.abs",
+                    Builtin::Sin => "Math.sin",
+                    Builtin::Cos => "Math.cos",
+                    Builtin::Floor => "Math.floor",
+                    Builtin::Random | Builtin::Len | Builtin::Ord | Builtin::Chr => {
+                        unreachable!("handled above")
+                    }
+                };
+                format!("{}({})", name, self.expression(&args[0]))
+            }
+        }
+    }
+
+    /// Generates JavaScript for the operand of an operator with the given
+    /// precedence, parenthesizing it where needed, as in the C backend.
+    fn operand(&mut self, expr: &Expr, parent: u8, is_rhs: bool) -> String {
+        let code = self.expression(expr);
+        self.parenthesize(expr, self.type_of(expr), code, parent, is_rhs)
+    }
+
+    /// Parenthesizes the JavaScript generated for an operand of type `ty`, as
+    /// `operand` does.
+    fn parenthesize(
+        &self,
+        expr: &Expr,
+        ty: Type,
+        code: String,
+        parent: u8,
+        is_rhs: bool,
+    ) -> String {
+        // only AND and OR take their operands as booleans
+        if expr.is_condition() && parent > precedence(BinaryOp::And) {
+            return format!("Number({})", code);
+        }
+
+        let own = match expr {
+            // integer division is lowered to a call to Math.trunc
+            Expr::Binary {
+                op: BinaryOp::Div, ..
+            } if ty == Type::Int => return code,
+            Expr::Binary { op, .. } => precedence(*op),
+            _ => return code,
+        };
+
+        if own < parent || (is_rhs && own == parent) {
+            format!("({})", code)
+        } else {
+            code
+        }
+    }
+}
+
+impl Default for JsEmitter {
+    fn default() -> Self {
+        JsEmitter::new()
+    }
+}
+
+impl Emitter for JsEmitter {
+    fn emit(&mut self, code: &str) {
+        self.code.push_str(code);
+    }
+
+    /// Emits a line of code, indenting it to the current depth, with blocks
+    /// opened by `{` and closed by `}` as in the C backend.
+    fn emit_line(&mut self, code: &str) {
+        if code.starts_with('}') {
+            self.depth = self.depth.saturating_sub(1);
+        }
+
+        self.code
+            .push_str(&" ".repeat(self.depth * self.indent_width));
+        self.code.push_str(code);
+        self.code.push('\n');
+
+        if code.ends_with('{') {
+            self.depth += 1;
+        }
+    }
+
+    fn header_line(&mut self, code: &str) {
+        self.header.push_str(code);
+        self.header.push('\n');
+    }
+
+    /// Generates a script for a whole program. Fails on `LABEL` and `GOTO`,
+    /// which JavaScript has no equivalent for.
+    fn emit_program(&mut self, program: &[Stmt]) -> GenResult<()> {
+        self.buffered = uses_write(program);
+        if self.buffered {
+            self.header_line("let line = \"\";");
+        }
+
+        for stmt in program {
+            self.emit_statement(stmt)?;
+        }
+
+        if self.buffered {
+            self.emit_line("if (line !== \"\") {");
+            self.emit_line("console.log(line);");
+            self.emit_line("}");
+        }
+
+        Ok(())
+    }
+
+    fn output(&self) -> String {
+        let mut output = String::new();
+        output.push_str(&self.header);
+        output.push_str(&self.code);
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compile_with;
+    use crate::js::JsEmitter;
+
+    fn compile(source: &str) -> String {
+        compile_with(source, JsEmitter::new()).unwrap()
+    }
+
+    #[test]
+    fn test_js_hello() {
+        let source = std::fs::read_to_string("samples/hello.teeny").unwrap();
+        assert_eq!(
+            compile(&source),
+            "console.log(\"Hello, world!\");
+console.log(\"Second line\");
+console.log(\"Third line\");
+"
+        );
+    }
+
+    #[test]
+    fn test_js_variables_and_loops() {
+        let source = "\
+INT i = 0
+LET s = \"x\"
+WHILE i < 3 REPEAT
+    IF i == 1 THEN
+        PRINT s, i
+    ELSE
+        WRITE i / 2, i * 1.5
+    ENDIF
+    i = i + 1
+ENDWHILE
+";
+        assert_eq!(
+            compile(source),
+            "let line = \"\";
+let i = 0;
+let s = \"\";
+i = 0;
+s = \"x\";
+while (i<3) {
+    if (i === 1) {
+        console.log(line + [s, i].join(\" \"));
+        line = \"\";
+    } else {
+        line += [Math.trunc(i / 2), String(i*1.5)].join(\" \");
+    }
+    i = i+1;
+}
+if (line !== \"\") {
+    console.log(line);
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_js_operators() {
+        let code = compile("LET a = 7\nINT i = 7\nPRINT a % 2 ^ 3, -(-a), i / 2 * 2\nWHILE a > 1 AND NOT a == 2 REPEAT\nENDWHILE");
+        assert!(code.contains(
+            "console.log(String(a%Math.pow(2, 3)), String(-(-a)), Math.trunc(i / 2)*2);\nwhile (a>1 && !(a === 2)) {"
+        ));
+    }
+
+    #[test]
+    fn test_js_leading_zeros() {
+        // a leading zero makes a legacy octal literal, or a syntax error
+        let code = compile("LET x = 007.5\nPRINT x + 00.25");
+        assert!(code.contains("x = 7.5;\nconsole.log(String(x+0.25));"));
+        assert!(compile_with("PRINT 010", JsEmitter::new()).is_err());
+    }
+
+    #[test]
+    fn test_js_comparison_values() {
+        let code =
+            compile("LET x = 3\nLET flag = x > 0 AND x < 5\nPRINT flag, (x > 2) * 5, x == 3");
+        assert!(code.contains("flag = Number(x>0 && x<5);\n"));
+        assert!(code.contains("console.log(flag, Number(x>2)*5, Number(x === 3));"));
+    }
+
+    #[test]
+    fn test_js_string_builtins() {
+        let code = compile("LET s = \"abc\"\nPRINT LEN(s), ORD(s), CHR(ORD(s) + 1)");
+        assert!(code.contains(
+            "console.log(s.length, (s.charCodeAt(0) || 0), String.fromCharCode(Math.trunc((s.charCodeAt(0) || 0)+1) & 127));"
+        ));
+    }
+
+    #[test]
+    fn test_js_input() {
+        let code = compile("INPUT \"n? \", n\nINT k = n");
+        assert_eq!(
+            code,
+            "let n = 0;\nlet k = 0;\nn = parseFloat(prompt(\"n? \")) || 0;\nk = Math.trunc(n);\n"
+        );
+        assert!(compile("INPUT n").contains("n = parseFloat(prompt()) || 0;"));
+        assert!(compile("INT n = 0\nINPUT n").contains("n = parseInt(prompt()) || 0;"));
+    }
+
+    #[test]
+    fn test_js_string_escapes() {
+        assert!(compile(r#"PRINT "\x1b[1m\0" "#).contains(r#"console.log("\x1b[1m\x00");"#));
+    }
+
+    #[test]
+    fn test_js_rejects_goto() {
+        let err = compile_with("LABEL top\nGOTO top", JsEmitter::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "LABEL top is unsupported in the JS backend"
+        );
+    }
+}
@@ -1,20 +1,137 @@
-///! The lexer module
+//! The lexer module
+
+use crate::error::CompileError;
+use crate::GenResult;
+use std::fmt;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+/// The default maximum length of an identifier, in characters.
+pub const DEFAULT_MAX_IDENT_LEN: usize = 255;
+
+/// The default maximum length of a string literal, in characters.
+pub const DEFAULT_MAX_STRING_LEN: usize = 4096;
+
+/// How many characters past the current one the lexer looks at, at most.
+const LOOKAHEAD: usize = 3;
+
+/// How much of a streamed source is read before the characters before the
+/// current token are dropped.
+const STREAM_WINDOW: usize = 4096;
+
+const DIGIT_SEPARATOR_MISPLACED: &str = "digit separator '_' must be placed between two digits";
+
+const INTEGER_OUT_OF_RANGE: &str = "integer literal out of range";
+
+/// Turns `\r\n` and lone `\r` line endings into `\n`, so that a file
+/// written on any platform lexes the same.
+pub fn normalize_line_endings(input: &str) -> String {
+    input.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Whether a number literal is an integer, i.e. has neither a fractional part
+/// nor an exponent.
+pub fn is_integer_literal(value: &str) -> bool {
+    ["0x", "0X", "0b", "0B"]
+        .iter()
+        .any(|prefix| value.starts_with(prefix))
+        || !value.contains(['.', 'e', 'E'])
+}
+
+/// Parses a number literal, in any of the forms the lexer accepts.
+pub fn number(value: &str) -> f64 {
+    let radix = |prefixes: [&str; 2], radix| {
+        prefixes
+            .iter()
+            .find_map(|prefix| value.strip_prefix(prefix))
+            .map(|digits| u64::from_str_radix(digits, radix).unwrap() as f64)
+    };
+
+    radix(["0x", "0X"], 16)
+        .or_else(|| radix(["0b", "0B"], 2))
+        .unwrap_or_else(|| value.parse().unwrap())
+}
+
+/// A file being read in place of the `INCLUDE` directive that named it.
+struct Include {
+    lexer: Box<Lexer>,
+    /// The file name as the directive spells it.
+    name: String,
+    /// Where the directive is, which the included tokens are all reported
+    /// at, as the source they're in isn't the one being lexed.
+    line: usize,
+    col: usize,
+    end_line: usize,
+    end_col: usize,
+}
 
 pub struct Lexer {
-    pub source: String,
+    pub source: Vec<char>,
     pub curpos: isize,
     pub curchar: char,
+    pub line: usize,
+    pub col: usize,
+    startline: usize,
+    startcol: usize,
+    pub max_ident_len: usize,
+    pub max_string_len: usize,
+    peeked: Option<Token>,
+    /// Set once iteration has reached the end of the source or an error.
+    done: bool,
+    /// Where the rest of the source comes from when it is streamed rather
+    /// than all in `source`, until it runs out.
+    reader: Option<Box<dyn BufRead>>,
+    /// An error reading the source, reported in place of the next token.
+    read_error: Option<String>,
+    /// The file the source was read from, which `INCLUDE`s are relative to.
+    /// Without it, `INCLUDE` is an error.
+    pub path: Option<PathBuf>,
+    /// The files that include this one, directly or not, to catch cycles.
+    includers: Vec<PathBuf>,
+    included: Option<Include>,
+    /// Whether the last token lexed from this source ended a line, as an
+    /// `INCLUDE` must be the first thing on its line.
+    at_line_start: bool,
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Self {
-        let mut source = input.to_owned();
+        let mut source = normalize_line_endings(input).chars().collect::<Vec<char>>();
         source.push('\n');
 
+        Lexer::with_source(source, None)
+    }
+
+    /// Lexes source read from `reader` a line at a time as the tokens are
+    /// asked for, rather than all at once. What has been read is only dropped
+    /// once it runs past `STREAM_WINDOW` characters, and then only up to the
+    /// start of the current token, so the lexer holds up to that many
+    /// characters plus the current token and the whole lines read to look
+    /// `LOOKAHEAD` characters past it. A token or line longer than the window
+    /// grows the buffer to fit it.
+    pub fn from_reader(reader: impl BufRead + 'static) -> Self {
+        Lexer::with_source(Vec::new(), Some(Box::new(reader)))
+    }
+
+    fn with_source(source: Vec<char>, reader: Option<Box<dyn BufRead>>) -> Self {
         let mut lexer = Lexer {
-            source: source,
+            source,
             curpos: -1,
             curchar: '\u{0000}',
+            line: 1,
+            col: 0,
+            startline: 1,
+            startcol: 1,
+            max_ident_len: DEFAULT_MAX_IDENT_LEN,
+            max_string_len: DEFAULT_MAX_STRING_LEN,
+            peeked: None,
+            done: false,
+            reader,
+            read_error: None,
+            path: None,
+            includers: Vec::new(),
+            included: None,
+            at_line_start: true,
         };
 
         lexer.next_char();
@@ -22,13 +139,46 @@ impl Lexer {
         lexer
     }
 
+    /// Reads more of a streamed source, until `source` holds the character at
+    /// `pos` or there's no more to read. Like `new`, ends the source with a
+    /// newline.
+    fn fill(&mut self, pos: usize) {
+        while self.source.len() <= pos {
+            let reader = match &mut self.reader {
+                Some(reader) => reader,
+                None => return,
+            };
+
+            // a `\r\n` is never split across two lines
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(0) => {}
+                Ok(_) => {
+                    self.source.extend(normalize_line_endings(&line).chars());
+                    continue;
+                }
+                Err(err) => self.read_error = Some(err.to_string()),
+            }
+            self.reader = None;
+            self.source.push('\n');
+        }
+    }
+
     fn next_char(&mut self) {
         self.curpos += 1;
+        self.fill(self.curpos as usize + LOOKAHEAD);
+
+        if self.curchar == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
 
         if self.curpos as usize >= self.source.len() {
             self.curchar = '\u{0000}';
         } else {
-            self.curchar = self.source.chars().nth(self.curpos as usize).unwrap();
+            self.curchar = self.source[self.curpos as usize];
         }
     }
 
@@ -36,39 +186,224 @@ impl Lexer {
         if (self.curpos + 1) as usize >= self.source.len() {
             return Some('\u{0000}');
         }
-        self.source.chars().nth((self.curpos + 1) as usize)
+        Some(self.source[(self.curpos + 1) as usize])
     }
 
-    fn abort(&self, message: &str) {
-        panic!("Lexer error: {}", message);
+    fn slice(&self, startpos: usize, endpos: usize) -> String {
+        self.source[startpos..endpos].iter().collect()
+    }
+
+    fn abort<T>(&self, message: &str) -> GenResult<T> {
+        self.abort_at(message, self.startline, self.startcol)
+    }
+
+    /// Like `abort`, but pointing at a position within the current token.
+    fn abort_at<T>(&self, message: &str, line: usize, col: usize) -> GenResult<T> {
+        Err(Box::new(CompileError::new(message, line, col)))
+    }
+
+    /// Advances over the digits following the current one, which may be
+    /// separated by single underscores, as in `1_000_000`.
+    fn skip_digits(&mut self, radix: u32) -> GenResult<()> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_digit(radix) => self.next_char(),
+                Some('_') => {
+                    let after = self.source.get((self.curpos + 2) as usize);
+                    if !self.curchar.is_digit(radix) || !after.is_some_and(|c| c.is_digit(radix)) {
+                        return self.abort(DIGIT_SEPARATOR_MISPLACED);
+                    }
+                    self.next_char();
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Discards the rest of the current line, so that the next token is the
+    /// newline ending it. Used by the parser to resume after an error.
+    pub fn skip_line(&mut self) {
+        self.peeked = None;
+        if let Some(include) = &mut self.included {
+            return include.lexer.skip_line();
+        }
+        while self.curchar != '\n' && self.curchar != '\u{0000}' {
+            self.next_char();
+        }
     }
 
     fn skip_whitespace(&mut self) {
-        while self.curchar == ' ' || self.curchar == '\t' || self.curchar == '\r' {
+        while self.curchar == ' ' || self.curchar == '\t' {
             self.next_char();
         }
     }
 
-    fn skip_comment(&mut self) {
-        if self.curchar == '#' {
-            while self.curchar != '\n' {
-                self.next_char();
+    /// Returns the next token, consuming it.
+    pub fn get_token(&mut self) -> GenResult<Token> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.read_token(),
+        }
+    }
+
+    /// Returns the next token without consuming it, so that the following
+    /// `get_token` (or `peek_token`) call returns the same token. Note that
+    /// `line` and `col` already point past a peeked token.
+    pub fn peek_token(&mut self) -> GenResult<Token> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.read_token()?);
+        }
+
+        Ok(self.peeked.clone().unwrap())
+    }
+
+    /// Lexes the next token, from the file an `INCLUDE` names while it's
+    /// being read, or else from this source.
+    fn read_token(&mut self) -> GenResult<Token> {
+        loop {
+            if let Some(include) = &mut self.included {
+                match include.lexer.get_token() {
+                    Ok(token) if token.kind == TokenType::Eof => self.included = None,
+                    Ok(token) => {
+                        return Ok(Token {
+                            line: include.line,
+                            col: include.col,
+                            end_line: include.end_line,
+                            end_col: include.end_col,
+                            ..token
+                        })
+                    }
+                    Err(err) => {
+                        return match err.downcast::<CompileError>() {
+                            Ok(err) => Err(Box::new(CompileError::new(
+                                &format!(
+                                    "in {:?} at {}:{}: {}",
+                                    include.name, err.line, err.col, err.message
+                                ),
+                                include.line,
+                                include.col,
+                            ))),
+                            Err(err) => Err(err),
+                        };
+                    }
+                }
+                continue;
+            }
+
+            let token = self.lex_token()?;
+            let at_line_start = self.at_line_start;
+            self.at_line_start = token.kind == TokenType::Newline;
+
+            if token.kind != TokenType::Include {
+                return Ok(token);
+            }
+            if !at_line_start {
+                return self.abort("INCLUDE must be the first thing on its line");
             }
+            self.include(token)?;
         }
     }
 
-    pub fn get_token(&mut self) -> Token {
+    /// Starts reading the file named after `INCLUDE`, relative to this one.
+    fn include(&mut self, directive: Token) -> GenResult<()> {
+        let name = self.lex_token()?;
+        if name.kind != TokenType::String {
+            return self.abort("expected a file name in quotes after INCLUDE");
+        }
+        // only a comment may follow the file name on its line
         self.skip_whitespace();
-        self.skip_comment();
+        if !matches!(self.curchar, '\n' | '#' | '\u{0000}') {
+            return self.abort_at(
+                "expected a newline after the INCLUDE file name",
+                self.line,
+                self.col,
+            );
+        }
+        let path = match &self.path {
+            Some(path) => path.with_file_name(&name.spelling),
+            None => {
+                return self.abort("INCLUDE is only supported in a source file");
+            }
+        };
+
+        let mut includers = self.includers.clone();
+        includers.extend(self.path.as_ref().and_then(|path| path.canonicalize().ok()));
+        let source = path
+            .canonicalize()
+            .and_then(|canonical| {
+                if includers.contains(&canonical) {
+                    return Ok(None);
+                }
+                std::fs::read_to_string(&path).map(Some)
+            })
+            .or_else(|err| self.abort(&format!("cannot INCLUDE {:?}: {}", name.spelling, err)))?;
+        let source = match source {
+            Some(source) => source,
+            None => return self.abort(&format!("{:?} includes itself", name.spelling)),
+        };
+
+        let mut lexer = Lexer::new(&source);
+        lexer.max_ident_len = self.max_ident_len;
+        lexer.max_string_len = self.max_string_len;
+        lexer.path = Some(path);
+        lexer.includers = includers;
+
+        self.included = Some(Include {
+            lexer: Box::new(lexer),
+            name: name.spelling,
+            line: directive.line,
+            col: directive.col,
+            end_line: name.end_line,
+            end_col: name.end_col,
+        });
+        Ok(())
+    }
+
+    fn lex_token(&mut self) -> GenResult<Token> {
+        self.skip_whitespace();
+
+        if let Some(err) = self.read_error.take() {
+            return self.abort_at(
+                &format!("failed to read source: {}", err),
+                self.line,
+                self.col,
+            );
+        }
+
+        // a streamed source is only needed from the character before this
+        // token on
+        if self.reader.is_some() && self.curpos as usize > STREAM_WINDOW {
+            let consumed = self.curpos as usize - 1;
+            self.source.drain(..consumed);
+            self.curpos -= consumed as isize;
+        }
 
+        self.startline = self.line;
+        self.startcol = self.col;
         let mut token = Token::new(TokenType::Eof, "");
 
         match self.curchar {
             '\n' => token = Token::new(TokenType::Newline, "\n"),
+            '+' | '-' | '*' | '/' if self.peek() == Some('=') => {
+                let kind = match self.curchar {
+                    '+' => TokenType::PlusEq,
+                    '-' => TokenType::MinusEq,
+                    '*' => TokenType::AsteriskEq,
+                    _ => TokenType::SlashEq,
+                };
+                let spelling = format!("{}=", self.curchar);
+                self.next_char();
+                token = Token::new(kind, &spelling);
+            }
             '+' => token = Token::new(TokenType::Plus, "+"),
             '-' => token = Token::new(TokenType::Minus, "-"),
             '*' => token = Token::new(TokenType::Asterisk, "*"),
             '/' => token = Token::new(TokenType::Slash, "/"),
+            '%' => token = Token::new(TokenType::Percent, "%"),
+            '^' => token = Token::new(TokenType::Caret, "^"),
+            ',' => token = Token::new(TokenType::Comma, ","),
+            '(' => token = Token::new(TokenType::LParen, "("),
+            ')' => token = Token::new(TokenType::RParen, ")"),
             '=' => {
                 if self.peek() == Some('=') {
                     self.next_char();
@@ -98,104 +433,286 @@ impl Lexer {
                     self.next_char();
                     token = Token::new(TokenType::NotEq, "!=");
                 } else {
-                    self.abort("! must be followed by =");
+                    return self.abort("! must be followed by =");
                 }
             }
 
+            '#' => {
+                let startpos = self.curpos as usize + 1;
+
+                while let Some(c) = self.peek() {
+                    if c == '\n' || c == '\u{0000}' {
+                        break;
+                    }
+                    self.next_char();
+                }
+
+                token = Token::new(
+                    TokenType::Comment,
+                    self.slice(startpos, (self.curpos + 1) as usize).trim(),
+                );
+            }
+
             '"' => {
                 self.next_char();
                 let startpos = self.curpos as usize;
+                let mut value = String::new();
 
                 while self.curchar != '"' {
-                    if self.curchar == '%'
-                        || self.curchar == '\r'
-                        || self.curchar == '\n'
-                        || self.curchar == '\\'
-                        || self.curchar == '\t'
-                    {
-                        self.abort(&format!(
-                            "Unsupported character in string: {}",
-                            self.curchar
-                        ));
+                    match self.curchar {
+                        '\\' => {
+                            let (line, col) = (self.line, self.col);
+                            self.next_char();
+                            match self.curchar {
+                                'n' => value.push('\n'),
+                                't' => value.push('\t'),
+                                '\\' => value.push('\\'),
+                                '"' => value.push('"'),
+                                '%' => value.push('%'),
+                                '0' => value.push('\0'),
+                                // exactly two digits, unlike in C, and only
+                                // up to 7F, since strings are characters
+                                // rather than bytes
+                                'x' => {
+                                    let digits = self.source[self.curpos as usize + 1..]
+                                        .iter()
+                                        .take(2)
+                                        .collect::<String>();
+                                    if digits.len() != 2
+                                        || !digits.chars().all(|c| c.is_ascii_hexdigit())
+                                    {
+                                        return self.abort_at(
+                                            "expected two hex digits after \\x",
+                                            line,
+                                            col,
+                                        );
+                                    }
+                                    let code = u8::from_str_radix(&digits, 16).unwrap();
+                                    if !code.is_ascii() {
+                                        return self.abort_at(
+                                            &format!(
+                                                "escape \\x{} is out of range, the largest is \\x7F",
+                                                digits
+                                            ),
+                                            line,
+                                            col,
+                                        );
+                                    }
+
+                                    value.push(code as char);
+                                    self.next_char();
+                                    self.next_char();
+                                }
+                                c => {
+                                    return self.abort_at(
+                                        &format!("Unsupported escape sequence in string: \\{}", c),
+                                        line,
+                                        col,
+                                    )
+                                }
+                            }
+                        }
+                        '\n' | '\u{0000}' => return self.abort("unterminated string literal"),
+                        '\t' => {
+                            return self.abort(&format!(
+                                "Unsupported character in string: {}",
+                                self.curchar
+                            ))
+                        }
+                        c => value.push(c),
                     }
                     self.next_char();
+
+                    let len = self.curpos as usize - startpos;
+                    if len > self.max_string_len {
+                        return self.abort(&format!(
+                            "string literal exceeds the maximum length of {} (found at least {})",
+                            self.max_string_len, len
+                        ));
+                    }
+                }
+
+                token = Token::new(TokenType::String, &value);
+            }
+
+            '0' if matches!(self.peek(), Some('x' | 'X' | 'b' | 'B')) => {
+                let startpos = self.curpos as usize;
+                self.next_char();
+                let (radix, name) = match self.curchar {
+                    'x' | 'X' => (16, "hexadecimal"),
+                    _ => (2, "binary"),
+                };
+
+                self.skip_digits(radix)?;
+                if let Some(c) = self.peek().filter(|c| c.is_ascii_alphanumeric()) {
+                    return self.abort(&format!("invalid digit {:?} in {} literal", c, name));
+                }
+
+                // the prefix alone, e.g. `0x`
+                let digits = self
+                    .slice(startpos + 2, (self.curpos + 1) as usize)
+                    .replace('_', "");
+                if digits.is_empty() {
+                    return self.abort(&format!("{} literal has no digits", name));
+                }
+                if i32::from_str_radix(&digits, radix).is_err() {
+                    return self.abort(INTEGER_OUT_OF_RANGE);
                 }
 
                 token = Token::new(
-                    TokenType::String,
-                    &self.source[startpos..self.curpos as usize],
+                    TokenType::Number,
+                    &self
+                        .slice(startpos, (self.curpos + 1) as usize)
+                        .replace('_', ""),
                 );
             }
 
-            c if c.is_digit(10) => {
+            // a leading `.`, as in `.5`, only starts a number before a digit
+            c if c.is_ascii_digit()
+                || (c == '.' && self.peek().is_some_and(|c| c.is_ascii_digit())) =>
+            {
                 let startpos = self.curpos as usize;
+                if c != '.' {
+                    self.skip_digits(10)?;
+                } else if startpos > 0 && self.source[startpos - 1].is_ascii_alphanumeric() {
+                    // or `1.5.5` would lex as `1.5` followed by `.5`
+                    return self.abort("unexpected '.' after number");
+                }
 
-                while let Some(c) = self.peek() {
-                    if c.is_digit(10) {
+                if c == '.' || self.peek() == Some('.') {
+                    if c != '.' {
                         self.next_char();
-                    } else {
-                        break;
                     }
-                }
 
-                if let Some('.') = self.peek() {
+                    // also at the end of the source, where there's no next
+                    // character at all
+                    match self.peek() {
+                        Some(c) if c.is_ascii_digit() => {}
+                        Some('_') => return self.abort(DIGIT_SEPARATOR_MISPLACED),
+                        _ => return self.abort("expected digit after decimal point"),
+                    }
+
                     self.next_char();
+                    self.skip_digits(10)?;
+                }
 
-                    if let Some(c) = self.peek() {
-                        if !c.is_digit(10) {
-                            self.abort(
-                                "numbers must have at least one digit after the decimal point",
-                            );
-                        }
+                if let Some('e' | 'E') = self.peek() {
+                    self.next_char();
+                    if let Some('+' | '-') = self.peek() {
+                        self.next_char();
                     }
 
-                    self.next_char();
-                    while let Some(c) = self.peek() {
-                        if c.is_digit(10) {
-                            self.next_char();
-                        } else {
-                            break;
-                        }
+                    if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                        return self.abort(&format!(
+                            "malformed exponent in {:?}: expected at least one digit",
+                            self.slice(startpos, (self.curpos + 1) as usize)
+                        ));
                     }
+                    self.next_char();
+                    self.skip_digits(10)?;
                 }
 
-                token = Token::new(
-                    TokenType::Number,
-                    &self.source[startpos..(self.curpos + 1) as usize],
-                );
+                // separators are only for readability, C never sees them
+                let number = self
+                    .slice(startpos, (self.curpos + 1) as usize)
+                    .replace('_', "");
+                let integer = !number.contains(['.', 'e', 'E']);
+                // C would read the rest of the digits as octal
+                if integer && number.len() > 1 && number.starts_with('0') {
+                    return self.abort(&format!(
+                        "integer literal {:?} has a leading zero, which C reads as octal",
+                        number
+                    ));
+                }
+                // integers are C `int`s, taken to be 32 bits wide
+                if integer && number.parse::<i32>().is_err() {
+                    return self.abort(INTEGER_OUT_OF_RANGE);
+                }
+                // a float's leading zeros don't change its value, but
+                // JavaScript rejects them, so only one is kept before a `.`
+                let digits = number.trim_start_matches('0');
+                let number = match digits.chars().next() {
+                    Some(c) if c.is_ascii_digit() => digits.to_string(),
+                    _ => format!("0{}", digits),
+                };
+                token = Token::new(TokenType::Number, &number);
             }
 
-            c if c.is_ascii_alphabetic() => {
+            // keywords are all ASCII, so only an exact match is one
+            c if c.is_alphabetic() => {
                 let startpos = self.curpos as usize;
 
                 while let Some(c) = self.peek() {
-                    if c.is_ascii_alphanumeric() {
+                    if c.is_alphanumeric() {
                         self.next_char();
                     } else {
                         break;
                     }
+
+                    let len = self.curpos as usize - startpos + 1;
+                    if len > self.max_ident_len {
+                        return self.abort(&format!(
+                            "identifier exceeds the maximum length of {} (found at least {})",
+                            self.max_ident_len, len
+                        ));
+                    }
                 }
 
                 token = Token::new(
                     TokenType::Ident,
-                    &self.source[startpos..(self.curpos + 1) as usize],
+                    &self.slice(startpos, (self.curpos + 1) as usize),
                 );
             }
 
             '\u{0000}' => {}
 
-            _ => self.abort(&format!("Unsupported token: {}", self.curchar)),
+            _ => return self.abort(&format!("Unsupported token: {}", self.curchar)),
         }
 
+        token.line = self.startline;
+        token.col = self.startcol;
         self.next_char();
-        token
+        token.end_line = self.line;
+        token.end_col = self.col;
+        Ok(token)
     }
 }
 
-#[derive(Debug, Clone)]
+/// Yields the tokens up to, but not including, `Eof`. Iteration stops after
+/// the first error.
+impl Iterator for Lexer {
+    type Item = GenResult<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.get_token() {
+            Ok(token) if token.kind == TokenType::Eof => {
+                self.done = true;
+                None
+            }
+            Ok(token) => Some(Ok(token)),
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// A token and where it is in the source. Tokens compare equal only if
+/// their positions match too; see `eq_ignoring_pos` otherwise.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub kind: TokenType,
     pub spelling: String,
+    pub line: usize,
+    pub col: usize,
+    /// The position just past the end of the token.
+    pub end_line: usize,
+    pub end_col: usize,
 }
 
 impl Token {
@@ -207,63 +724,202 @@ impl Token {
                 kind
             },
             spelling: spelling.to_string(),
+            line: 0,
+            col: 0,
+            end_line: 0,
+            end_col: 0,
         }
     }
+
+    /// Whether both tokens have the same kind and spelling, wherever they
+    /// are.
+    pub fn eq_ignoring_pos(&self, other: &Token) -> bool {
+        self.kind == other.kind && self.spelling == other.spelling
+    }
+}
+
+/// The token as it was written, with a string in quotes, followed by where it
+/// is if that's known. Newlines and the end of the source, which have no
+/// spelling to show, are named instead. The alternate form, `{:#}`, leaves
+/// out the position, for messages that already give it.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            TokenType::String => write!(f, "\"{}\"", self.spelling)?,
+            TokenType::Newline | TokenType::Eof => write!(f, "{}", self.kind)?,
+            _ => f.write_str(&self.spelling)?,
+        }
+        if self.line > 0 && !f.alternate() {
+            write!(f, " at {}:{}", self.line, self.col)?;
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum TokenType {
+    And,
+    Assert,
     Asterisk,
+    AsteriskEq,
+    Break,
+    Caret,
+    Comma,
+    Comment,
+    Const,
+    Continue,
+    Do,
+    Else,
+    Elseif,
+    Emit,
     Endif,
     Endwhile,
     Eof,
     Eq,
     EqEq,
+    Exit,
     Goto,
     Gt,
     Gte,
     Ident,
     If,
+    Include,
     Input,
+    Int,
     Label,
     Let,
+    LParen,
     Lt,
     Lte,
     Minus,
+    MinusEq,
     Newline,
+    Not,
     NotEq,
     Number,
+    Or,
+    Percent,
     Plus,
+    PlusEq,
     Print,
     Repeat,
+    Return,
+    RParen,
     Slash,
+    SlashEq,
     String,
+    Swap,
     Then,
     While,
+    Write,
 }
 
 impl TokenType {
     pub fn get_token_type_for_ident(ident: &str) -> TokenType {
         match ident {
+            "AND" => TokenType::And,
+            "ASSERT" => TokenType::Assert,
+            "BREAK" => TokenType::Break,
+            "CONST" => TokenType::Const,
+            "CONTINUE" => TokenType::Continue,
+            "DO" => TokenType::Do,
+            "ELSE" => TokenType::Else,
+            "ELSEIF" => TokenType::Elseif,
+            "EMIT" => TokenType::Emit,
             "ENDIF" => TokenType::Endif,
             "ENDWHILE" => TokenType::Endwhile,
+            "EXIT" => TokenType::Exit,
             "GOTO" => TokenType::Goto,
             "IF" => TokenType::If,
+            "INCLUDE" => TokenType::Include,
             "INPUT" => TokenType::Input,
+            "INT" => TokenType::Int,
             "LABEL" => TokenType::Label,
             "LET" => TokenType::Let,
+            "NOT" => TokenType::Not,
+            "OR" => TokenType::Or,
             "REPEAT" => TokenType::Repeat,
+            "RETURN" => TokenType::Return,
+            "SWAP" => TokenType::Swap,
             "THEN" => TokenType::Then,
             "WHILE" => TokenType::While,
             "PRINT" => TokenType::Print,
+            "WRITE" => TokenType::Write,
             _ => TokenType::Ident,
         }
     }
 }
 
+/// Shows a kind of token the way it's written in the source, or what it is
+/// for the kinds that have no single spelling, for messages to the user.
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            TokenType::And => "AND",
+            TokenType::Assert => "ASSERT",
+            TokenType::Asterisk => "*",
+            TokenType::AsteriskEq => "*=",
+            TokenType::Break => "BREAK",
+            TokenType::Caret => "^",
+            TokenType::Comma => ",",
+            TokenType::Comment => "comment",
+            TokenType::Const => "CONST",
+            TokenType::Continue => "CONTINUE",
+            TokenType::Do => "DO",
+            TokenType::Else => "ELSE",
+            TokenType::Elseif => "ELSEIF",
+            TokenType::Endif => "ENDIF",
+            TokenType::Emit => "EMIT",
+            TokenType::Endwhile => "ENDWHILE",
+            TokenType::Eof => "end of file",
+            TokenType::Eq => "=",
+            TokenType::EqEq => "==",
+            TokenType::Exit => "EXIT",
+            TokenType::Goto => "GOTO",
+            TokenType::Gt => ">",
+            TokenType::Gte => ">=",
+            TokenType::Ident => "identifier",
+            TokenType::If => "IF",
+            TokenType::Include => "INCLUDE",
+            TokenType::Input => "INPUT",
+            TokenType::Int => "INT",
+            TokenType::Label => "LABEL",
+            TokenType::Let => "LET",
+            TokenType::LParen => "(",
+            TokenType::Lt => "<",
+            TokenType::Lte => "<=",
+            TokenType::Minus => "-",
+            TokenType::MinusEq => "-=",
+            TokenType::Newline => "newline",
+            TokenType::Not => "NOT",
+            TokenType::NotEq => "!=",
+            TokenType::Number => "number",
+            TokenType::Or => "OR",
+            TokenType::Percent => "%",
+            TokenType::Plus => "+",
+            TokenType::PlusEq => "+=",
+            TokenType::Print => "PRINT",
+            TokenType::Repeat => "REPEAT",
+            TokenType::Return => "RETURN",
+            TokenType::RParen => ")",
+            TokenType::Slash => "/",
+            TokenType::SlashEq => "/=",
+            TokenType::String => "string",
+            TokenType::Swap => "SWAP",
+            TokenType::Then => "THEN",
+            TokenType::While => "WHILE",
+            TokenType::Write => "WRITE",
+        };
+        f.write_str(text)
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::lexer::{Lexer, TokenType};
+    use crate::error::CompileError;
+    use crate::lexer::{Lexer, Token, TokenType, DEFAULT_MAX_IDENT_LEN};
+    use crate::GenResult;
 
     #[test]
     fn test_tokenize() {
@@ -277,6 +933,20 @@ mod test {
         println!();
     }
 
+    #[test]
+    fn test_display_token() {
+        let mut lexer = Lexer::new("PRINT \"hi there\"\n");
+        let print = lexer.get_token().unwrap();
+        let string = lexer.get_token().unwrap();
+        let newline = lexer.get_token().unwrap();
+
+        assert_eq!(print.to_string(), "PRINT at 1:1");
+        assert_eq!(string.to_string(), "\"hi there\" at 1:7");
+        assert_eq!(format!("{:#}", string), "\"hi there\"");
+        assert_eq!(format!("{:#}", newline), "newline");
+        assert_eq!(Token::new(TokenType::Number, "42").to_string(), "42");
+    }
+
     fn read_source(infile: &str) -> String {
         use std::fs::File;
         use std::io::{BufReader, Read};
@@ -287,83 +957,723 @@ mod test {
         buffer
     }
 
-    fn lex(source: &str) {
+    fn lex(source: &str) -> GenResult<()> {
         let mut lexer = Lexer::new(source);
 
-        let mut token = lexer.get_token();
+        let mut token = lexer.get_token()?;
         while token.kind != TokenType::Eof {
             println!("{:?}", token);
-            token = lexer.get_token();
+            token = lexer.get_token()?;
         }
+
+        Ok(())
     }
 
     #[test]
     fn test_lex_operators() {
-        lex("+ -\t* /   ");
+        lex("+ -\t* /   ").unwrap();
     }
 
     #[test]
     fn test_lex_all_operators() {
-        lex("+- */ >>= = != <<= ==");
+        lex("+- */ % >>= = != <<= ==").unwrap();
+    }
+
+    #[test]
+    fn test_lex_parens() {
+        let mut lexer = Lexer::new("(a)");
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::LParen);
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::Ident);
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::RParen);
+    }
+
+    #[test]
+    fn test_lex_percent() {
+        let mut lexer = Lexer::new("7 % 3");
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::Number);
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::Percent);
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::Number);
     }
 
     #[test]
     fn test_lex_comments() {
-        lex("+- # This is a comment!\n */");
+        lex("+- # This is a comment!\n */").unwrap();
+    }
+
+    #[test]
+    fn test_lex_comment_token() {
+        let mut lexer = Lexer::new("PRINT 1 #  show one  \n#\n");
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::Print);
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::Number);
+
+        let comment = lexer.get_token().unwrap();
+        assert_eq!(comment.kind, TokenType::Comment);
+        assert_eq!(comment.spelling, "show one");
+        assert_eq!((comment.line, comment.col), (1, 9));
+
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::Newline);
+        assert_eq!(lexer.get_token().unwrap().spelling, "");
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::Newline);
+    }
+
+    #[test]
+    fn test_lex_trailing_comment_without_newline() {
+        let mut lexer = Lexer::new("RETURN x # done");
+        for kind in [
+            TokenType::Return,
+            TokenType::Ident,
+            TokenType::Comment,
+            TokenType::Newline,
+            TokenType::Eof,
+        ] {
+            assert_eq!(lexer.get_token().unwrap().kind, kind);
+        }
+    }
+
+    #[test]
+    fn test_lex_indented_comment_lines() {
+        let mut lexer = Lexer::new("  # a\n\t# b\n   \n    # c\nPRINT 1");
+        let expected = [
+            (TokenType::Comment, "a", 1, 3),
+            (TokenType::Newline, "\n", 1, 6),
+            (TokenType::Comment, "b", 2, 2),
+            (TokenType::Newline, "\n", 2, 5),
+            (TokenType::Newline, "\n", 3, 4),
+            (TokenType::Comment, "c", 4, 5),
+            (TokenType::Newline, "\n", 4, 8),
+            (TokenType::Print, "PRINT", 5, 1),
+            (TokenType::Number, "1", 5, 7),
+        ];
+
+        for (kind, spelling, line, col) in expected {
+            let token = lexer.get_token().unwrap();
+            assert_eq!(
+                (token.kind, token.spelling.as_str(), token.line, token.col),
+                (kind, spelling, line, col)
+            );
+        }
     }
 
     #[test]
     fn test_lex_strings() {
-        lex("+- \"This is a string\" # This is a comment!\n */");
+        lex("+- \"This is a string\" # This is a comment!\n */").unwrap();
+    }
+
+    #[test]
+    fn test_lex_large_program() {
+        use std::time::{Duration, Instant};
+
+        let source = "LET foo = foo * 3 + 2.5\nPRINT \"hello, world\"\n".repeat(2000);
+        let start = Instant::now();
+        let mut lexer = Lexer::new(&source);
+
+        let mut count = 0;
+        while lexer.get_token().unwrap().kind != TokenType::Eof {
+            count += 1;
+        }
+        assert_eq!(count, 2000 * 12 + 1);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_token_type_display() {
+        for (kind, text) in [
+            (TokenType::Then, "THEN"),
+            (TokenType::Endwhile, "ENDWHILE"),
+            (TokenType::Gte, ">="),
+            (TokenType::PlusEq, "+="),
+            (TokenType::LParen, "("),
+            (TokenType::Ident, "identifier"),
+            (TokenType::Number, "number"),
+            (TokenType::String, "string"),
+            (TokenType::Newline, "newline"),
+            (TokenType::Eof, "end of file"),
+        ] {
+            assert_eq!(kind.to_string(), text);
+        }
+
+        // every keyword shows as itself
+        for keyword in ["AND", "ELSEIF", "GOTO", "REPEAT", "WRITE"] {
+            assert_eq!(
+                TokenType::get_token_type_for_ident(keyword).to_string(),
+                keyword
+            );
+        }
+    }
+
+    #[test]
+    fn test_lex_from_reader() {
+        use std::io::{BufReader, Cursor};
+
+        let source = ["fib", "factorial", "statements", "average"]
+            .iter()
+            .map(|name| read_source(&format!("samples/{}.teeny", name)))
+            .collect::<String>()
+            .repeat(200)
+            + "PRINT \"\\x41\" # no newline at the end";
+        let reader = BufReader::with_capacity(8, Cursor::new(source.clone().into_bytes()));
+
+        let streamed = Lexer::from_reader(reader)
+            .collect::<GenResult<Vec<_>>>()
+            .unwrap();
+        assert_eq!(streamed, tokens(&source));
+    }
+
+    #[test]
+    fn test_lex_include() {
+        let dir = std::env::temp_dir().join(format!("ttc-rs-{}-include", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.teeny"), "LET a = 1\n").unwrap();
+        std::fs::write(dir.join("main.teeny"), "INCLUDE \"lib.teeny\"\nPRINT a\n").unwrap();
+        std::fs::write(dir.join("loop.teeny"), "PRINT 1\nINCLUDE \"loop.teeny\"\n").unwrap();
+        std::fs::write(
+            dir.join("noted.teeny"),
+            "INCLUDE \"lib.teeny\" # a\nPRINT a\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("inline.teeny"), "INCLUDE \"lib.teeny\" PRINT a\n").unwrap();
+
+        let lex_file = |name: &str| {
+            let path = dir.join(name);
+            let mut lexer = Lexer::new(&std::fs::read_to_string(&path).unwrap());
+            lexer.path = Some(path);
+            lexer.collect::<GenResult<Vec<_>>>()
+        };
+
+        let kinds = |tokens: Vec<Token>| tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds(lex_file("main.teeny").unwrap()),
+            kinds(tokens("LET a = 1\n\n\nPRINT a\n"))
+        );
+
+        let err = lex_file("loop.teeny").unwrap_err().to_string();
+        assert!(err.contains("\"loop.teeny\" includes itself"), "{}", err);
+
+        assert_eq!(
+            kinds(lex_file("noted.teeny").unwrap()),
+            kinds(tokens("LET a = 1\n\n# a\nPRINT a\n"))
+        );
+        let err = lex_file("inline.teeny").unwrap_err().to_string();
+        assert!(
+            err.contains("expected a newline after the INCLUDE file name"),
+            "{}",
+            err
+        );
+
+        let mut lexer = Lexer::new("INCLUDE \"lib.teeny\"\n");
+        let err = lexer.get_token().unwrap_err().to_string();
+        assert!(
+            err.contains("INCLUDE is only supported in a source file"),
+            "{}",
+            err
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lex_line_endings() {
+        let source = "LET a = 1\n\nIF a > 0 THEN\n  PRINT \"yes\" # done\nENDIF\n";
+        assert_eq!(tokens(&source.replace('\n', "\r\n")), tokens(source));
+        assert_eq!(tokens(&source.replace('\n', "\r")), tokens(source));
+    }
+
+    #[test]
+    fn test_lex_positions() {
+        let mut lexer = Lexer::new("LET foo = 12.5\n  PRINT \"hi\"");
+        let expected = [
+            (TokenType::Let, 1, 1),
+            (TokenType::Ident, 1, 5),
+            (TokenType::Eq, 1, 9),
+            (TokenType::Number, 1, 11),
+            (TokenType::Newline, 1, 15),
+            (TokenType::Print, 2, 3),
+            (TokenType::String, 2, 9),
+            (TokenType::Newline, 2, 13),
+            (TokenType::Eof, 3, 1),
+        ];
+
+        for (kind, line, col) in expected {
+            let token = lexer.get_token().unwrap();
+            assert_eq!((token.kind, token.line, token.col), (kind, line, col));
+        }
+    }
+
+    fn tokens(source: &str) -> Vec<Token> {
+        Lexer::new(source).collect::<GenResult<_>>().unwrap()
+    }
+
+    fn token(kind: TokenType, spelling: &str, start: (usize, usize), end: (usize, usize)) -> Token {
+        Token {
+            line: start.0,
+            col: start.1,
+            end_line: end.0,
+            end_col: end.1,
+            ..Token::new(kind, spelling)
+        }
+    }
+
+    #[test]
+    fn test_lex_compound_assignment() {
+        let kinds = tokens("x += 1 -= *= /= + =")
+            .into_iter()
+            .map(|token| token.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                TokenType::Ident,
+                TokenType::PlusEq,
+                TokenType::Number,
+                TokenType::MinusEq,
+                TokenType::AsteriskEq,
+                TokenType::SlashEq,
+                TokenType::Plus,
+                TokenType::Eq,
+                TokenType::Newline,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_token_stream() {
+        assert_eq!(
+            tokens("LET foo = 12.5\nPRINT foo"),
+            vec![
+                token(TokenType::Let, "LET", (1, 1), (1, 4)),
+                token(TokenType::Ident, "foo", (1, 5), (1, 8)),
+                token(TokenType::Eq, "=", (1, 9), (1, 10)),
+                token(TokenType::Number, "12.5", (1, 11), (1, 15)),
+                token(TokenType::Newline, "\n", (1, 15), (2, 1)),
+                token(TokenType::Print, "PRINT", (2, 1), (2, 6)),
+                token(TokenType::Ident, "foo", (2, 7), (2, 10)),
+                token(TokenType::Newline, "\n", (2, 10), (3, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_token_eq_ignoring_pos() {
+        let ident = tokens("foo foo");
+        assert_ne!(ident[0], ident[1]);
+        assert!(ident[0].eq_ignoring_pos(&ident[1]));
+        assert!(!ident[0].eq_ignoring_pos(&Token::new(TokenType::Ident, "bar")));
+    }
+
+    #[test]
+    fn test_lex_error_position() {
+        let err = lex("LET a = 1\nLET b ! 2").unwrap_err();
+        assert!(err.to_string().contains("error at 2:7"));
+    }
+
+    #[test]
+    fn test_lex_comma() {
+        let mut lexer = Lexer::new("INPUT \"x: \", x");
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::Input);
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::String);
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::Comma);
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::Ident);
+    }
+
+    #[test]
+    fn test_lex_ident_within_limit() {
+        let mut lexer = Lexer::new(&"a".repeat(DEFAULT_MAX_IDENT_LEN));
+        assert_eq!(
+            lexer.get_token().unwrap().spelling.len(),
+            DEFAULT_MAX_IDENT_LEN
+        );
+    }
+
+    #[test]
+    fn test_lex_ident_too_long() {
+        let err = lex(&"a".repeat(DEFAULT_MAX_IDENT_LEN + 1)).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("exceeds the maximum length of 255"));
+    }
+
+    #[test]
+    fn test_lex_iterator() {
+        let kinds = Lexer::new("PRINT 1\n")
+            .map(|token| token.unwrap().kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::Print,
+                TokenType::Number,
+                TokenType::Newline,
+                TokenType::Newline
+            ]
+        );
+
+        let mut lexer = Lexer::new("PRINT $ 1");
+        assert!(lexer.next().unwrap().is_ok());
+        assert!(lexer.next().unwrap().is_err());
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn test_lex_decimal_point() {
+        let mut lexer = Lexer::new("12.5");
+        assert_eq!(lexer.get_token().unwrap().spelling, "12.5");
+
+        for source in ["12.", "12.\n", "12. ", "12.x"] {
+            let err = lex(source).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("expected digit after decimal point"),
+                "{:?}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_lex_leading_dot() {
+        for (source, spelling) in [(".5", "0.5"), (".0", "0.0"), (".25e2", "0.25e2")] {
+            let token = Lexer::new(source).get_token().unwrap();
+            assert_eq!(
+                (token.kind, token.spelling.as_str()),
+                (TokenType::Number, spelling)
+            );
+        }
+
+        assert!(lex("1.5.5")
+            .unwrap_err()
+            .to_string()
+            .contains("unexpected '.' after number"));
+        for source in [".", ". 5", ".x"] {
+            let err = lex(source).unwrap_err();
+            assert!(
+                err.to_string().contains("Unsupported token: ."),
+                "{:?}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_lex_unicode_ident() {
+        let mut lexer = Lexer::new("número=π2 LEТ");
+        let mut next = || {
+            let token = lexer.get_token().unwrap();
+            (token.kind, token.spelling)
+        };
+        assert_eq!(next(), (TokenType::Ident, "número".to_string()));
+        assert_eq!(next(), (TokenType::Eq, "=".to_string()));
+        assert_eq!(next(), (TokenType::Ident, "π2".to_string()));
+        // a Cyrillic "Т" doesn't make a keyword
+        assert_eq!(next(), (TokenType::Ident, "LEТ".to_string()));
+    }
+
+    #[test]
+    fn test_lex_string_too_long() {
+        let mut lexer = Lexer::new("PRINT \"this is too long\"");
+        lexer.max_string_len = 8;
+        lexer.get_token().unwrap();
+        let err = lexer.get_token().unwrap_err();
+        assert!(err.to_string().contains("exceeds the maximum length of 8"));
+    }
+
+    fn lex_string(source: &str) -> String {
+        let token = Lexer::new(source).get_token().unwrap();
+        assert_eq!(token.kind, TokenType::String);
+        token.spelling
+    }
+
+    #[test]
+    fn test_lex_string_escapes() {
+        assert_eq!(lex_string(r#""a\nb""#), "a\nb");
+        assert_eq!(lex_string(r#""a\tb""#), "a\tb");
+        assert_eq!(lex_string(r#""a\\b""#), "a\\b");
+        assert_eq!(lex_string(r#""a\"b""#), "a\"b");
+        assert_eq!(lex_string(r#""100\%""#), "100%");
+        assert_eq!(lex_string(r#""100%""#), "100%");
+    }
+
+    #[test]
+    fn test_lex_string_hex_and_nul_escapes() {
+        assert_eq!(lex_string(r#""\x41\x62c""#), "Abc");
+        assert_eq!(lex_string(r#""\x1b[0m\x7F""#), "\u{1b}[0m\u{7f}");
+        assert_eq!(lex_string(r#""a\0b""#), "a\0b");
+
+        for (source, message) in [
+            (
+                r#"PRINT "ab\x""#,
+                "error at 1:10: expected two hex digits after \\x",
+            ),
+            (
+                r#"PRINT "\xZZ""#,
+                "error at 1:8: expected two hex digits after \\x",
+            ),
+            (
+                r#"PRINT "\x4""#,
+                "error at 1:8: expected two hex digits after \\x",
+            ),
+            (
+                r#"PRINT "\x+1""#,
+                "error at 1:8: expected two hex digits after \\x",
+            ),
+            (
+                r#"PRINT "\xFF""#,
+                "error at 1:8: escape \\xFF is out of range, the largest is \\x7F",
+            ),
+        ] {
+            let err = lex(source).unwrap_err();
+            assert_eq!(err.to_string(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_lex_string_invalid_escape() {
+        let err = lex(r#""a\qb""#).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Unsupported escape sequence in string"));
+    }
+
+    #[test]
+    fn test_lex_string_raw_tab() {
+        let err = lex("\"a\tb\"").unwrap_err();
+        assert!(err.to_string().contains("Unsupported character in string"));
+    }
+
+    #[test]
+    fn test_lex_unterminated_string() {
+        let mut lexer = Lexer::new("PRINT 1\nPRINT   \"never closed\nPRINT 2");
+        while lexer.get_token().unwrap().kind != TokenType::Newline {}
+        lexer.get_token().unwrap();
+
+        let err = lexer.get_token().unwrap_err();
+        let err = err.downcast_ref::<CompileError>().unwrap();
+        assert_eq!(err.message, "unterminated string literal");
+        assert_eq!((err.line, err.col), (2, 9));
+    }
+
+    #[test]
+    fn test_lex_unterminated_string_at_eof() {
+        let err = lex("PRINT \"abc").unwrap_err();
+        assert!(err.to_string().contains("unterminated string literal"));
     }
 
     #[test]
     fn test_lex_numbers() {
-        lex("+-123 9.8654*/");
+        lex("+-123 9.8654*/").unwrap();
     }
 
     #[test]
     fn test_lex_average() {
-        lex(&read_source("samples/average.teeny"));
+        lex(&read_source("samples/average.teeny")).unwrap();
     }
 
     #[test]
     fn test_lex_keyword() {
-        lex("IF+-123 foo*THEN/");
+        lex("IF+-123 foo*THEN/").unwrap();
     }
 
     #[test]
     fn test_lex_factorial() {
-        lex(&read_source("samples/factorial.teeny"));
+        lex(&read_source("samples/factorial.teeny")).unwrap();
     }
 
     #[test]
     fn test_lex_hello() {
-        lex(&read_source("samples/hello.teeny"));
+        lex(&read_source("samples/hello.teeny")).unwrap();
     }
 
     #[test]
     fn test_lex_statements() {
-        lex(&read_source("samples/statements.teeny"));
+        lex(&read_source("samples/statements.teeny")).unwrap();
     }
 
     #[test]
     fn test_lex_expressions() {
-        lex(&read_source("samples/expression.teeny"));
+        lex(&read_source("samples/expression.teeny")).unwrap();
     }
 
     #[test]
     fn test_lex_fib() {
-        lex(&read_source("samples/fib.teeny"));
+        lex(&read_source("samples/fib.teeny")).unwrap();
     }
 
     #[test]
     fn test_lex_minmax() {
-        lex(&read_source("samples/minmax.teeny"));
+        lex(&read_source("samples/minmax.teeny")).unwrap();
     }
 
     #[test]
     fn test_lex_vector() {
-        lex(&read_source("samples/vector.teeny"));
+        lex(&read_source("samples/vector.teeny")).unwrap();
+    }
+
+    #[test]
+    fn test_peek_token() {
+        let mut lexer = Lexer::new("LET foo = 1");
+
+        assert_eq!(lexer.peek_token().unwrap().kind, TokenType::Let);
+        assert_eq!(lexer.peek_token().unwrap().kind, TokenType::Let);
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::Let);
+
+        let peeked = lexer.peek_token().unwrap();
+        let token = lexer.get_token().unwrap();
+        assert_eq!(
+            (peeked.kind, peeked.spelling),
+            (TokenType::Ident, "foo".to_string())
+        );
+        assert_eq!((token.kind, token.col), (TokenType::Ident, 5));
+
+        for kind in [TokenType::Eq, TokenType::Number, TokenType::Newline] {
+            assert_eq!(lexer.peek_token().unwrap().kind, kind);
+            assert_eq!(lexer.get_token().unwrap().kind, kind);
+        }
+        assert_eq!(lexer.peek_token().unwrap().kind, TokenType::Eof);
+        assert_eq!(lexer.get_token().unwrap().kind, TokenType::Eof);
+    }
+
+    #[test]
+    fn test_hex_and_binary_literals() {
+        let mut lexer = Lexer::new("0xFF 0b1010 0x1f");
+        for spelling in ["0xFF", "0b1010", "0x1f"] {
+            let token = lexer.get_token().unwrap();
+            assert_eq!(
+                (token.kind, token.spelling),
+                (TokenType::Number, spelling.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_hex_literal_followed_by_dot() {
+        let mut lexer = Lexer::new("0xFF.5");
+        let token = lexer.get_token().unwrap();
+        assert_eq!(
+            (token.kind, token.spelling),
+            (TokenType::Number, "0xFF".to_string())
+        );
+        assert!(lexer.get_token().is_err());
+    }
+
+    #[test]
+    fn test_integer_literal_range() {
+        for source in [
+            "2147483647",
+            "0x7FFFFFFF",
+            "0b1111111111111111111111111111111",
+            "1e20",
+        ] {
+            assert!(lex(source).is_ok(), "{}", source);
+        }
+        for source in [
+            "PRINT 99999999999999999999",
+            "PRINT 2_147_483_648",
+            "PRINT 0x80000000",
+        ] {
+            assert_eq!(
+                lex(source).unwrap_err().to_string(),
+                "error at 1:7: integer literal out of range",
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_lex_leading_zeros() {
+        for (source, spelling) in [
+            ("0", "0"),
+            ("0.5", "0.5"),
+            ("007.5", "7.5"),
+            ("00.5", "0.5"),
+            ("0e3", "0e3"),
+            ("012e1", "12e1"),
+        ] {
+            let token = Lexer::new(source).get_token().unwrap();
+            assert_eq!(
+                (token.kind, token.spelling.as_str()),
+                (TokenType::Number, spelling)
+            );
+        }
+        for (source, number) in [
+            ("PRINT 010", "010"),
+            ("PRINT 09", "09"),
+            ("PRINT 0_0", "00"),
+        ] {
+            assert_eq!(
+                lex(source).unwrap_err().to_string(),
+                format!(
+                    "error at 1:7: integer literal {:?} has a leading zero, which C reads as octal",
+                    number
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_malformed_hex_and_binary_literals() {
+        for (source, message) in [
+            ("0x", "hexadecimal literal has no digits"),
+            ("0x + 1", "hexadecimal literal has no digits"),
+            ("0b", "binary literal has no digits"),
+            ("0xFG", "invalid digit 'G' in hexadecimal literal"),
+            ("0b102", "invalid digit '2' in binary literal"),
+            ("0x1FFFFFFFFFFFFFFFF", "integer literal out of range"),
+        ] {
+            let err = lex(source).unwrap_err();
+            assert!(err.to_string().contains(message), "{}: {}", source, err);
+        }
+    }
+
+    #[test]
+    fn test_digit_separators() {
+        let mut lexer = Lexer::new("1_000_000 3.141_592 0xFF_FF 0b1010_0101 12");
+        for spelling in ["1000000", "3.141592", "0xFFFF", "0b10100101", "12"] {
+            let token = lexer.get_token().unwrap();
+            assert_eq!(
+                (token.kind, token.spelling),
+                (TokenType::Number, spelling.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_misplaced_digit_separators() {
+        for source in [
+            "1__0", "1_.5", "1._5", "1_", "3.14_", "0x_FF", "0b1_", "1_ + 2",
+        ] {
+            let err = lex(source).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("digit separator '_' must be placed between two digits"),
+                "{}: {}",
+                source,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_scientific_notation() {
+        let mut lexer = Lexer::new("1.5e10 2e-3 6.02E+23 1_000e1_0");
+        for spelling in ["1.5e10", "2e-3", "6.02E+23", "1000e10"] {
+            let token = lexer.get_token().unwrap();
+            assert_eq!(
+                (token.kind, token.spelling),
+                (TokenType::Number, spelling.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_malformed_exponents() {
+        for (source, message) in [
+            ("1e", "malformed exponent in \"1e\""),
+            ("1e+", "malformed exponent in \"1e+\""),
+            ("2.5E-x", "malformed exponent in \"2.5E-\""),
+            ("1e_5", "malformed exponent in \"1e\""),
+        ] {
+            let err = lex(source).unwrap_err();
+            assert!(err.to_string().contains(message), "{}: {}", source, err);
+        }
     }
 }
@@ -1,8 +1,187 @@
+use emitter::{CEmitter, Emitter};
+use interp::Interpreter;
+use lexer::{Lexer, Token, TokenType};
+use optimize::{eliminate_dead_code, fold_constants};
+use parser::Parser;
 use std::error::Error;
+use typecheck::typecheck;
 
 type GenError = Box<dyn Error>;
 pub type GenResult<T> = Result<T, GenError>;
 
+pub mod ast;
 pub mod emitter;
+pub mod error;
+pub mod format;
+pub mod interp;
+pub mod js;
 pub mod lexer;
+pub mod optimize;
 pub mod parser;
+pub mod symbols;
+pub mod typecheck;
+
+/// Lexes Teeny Tiny source code to completion, returning every token up to
+/// and including the final `Eof`, or the first error.
+pub fn tokenize(source: &str) -> GenResult<Vec<Token>> {
+    let mut lexer = Lexer::new(source);
+    let mut tokens = Vec::new();
+
+    loop {
+        let token = lexer.get_token()?;
+        let eof = token.kind == TokenType::Eof;
+        tokens.push(token);
+        if eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+/// Compiles Teeny Tiny source code to C, returning the generated C without
+/// touching the filesystem.
+pub fn compile(source: &str) -> GenResult<String> {
+    compile_with(source, CEmitter::new("out.c"))
+}
+
+/// Compiles Teeny Tiny source code to C like `compile`, for callers such as
+/// fuzzers that feed it arbitrary input. Whatever the source, however
+/// malformed or deeply nested, this returns an `Err` rather than panicking,
+/// and it never exits the process or touches the filesystem. A panic within
+/// the compiler, which is a bug, is caught and returned as an `Err` too.
+pub fn try_compile(source: &str) -> GenResult<String> {
+    catch_panic(|| compile(source))
+}
+
+/// Runs `f`, turning a panic into an `Err` with the panic's message.
+fn catch_panic<T>(f: impl FnOnce() -> GenResult<T> + std::panic::UnwindSafe) -> GenResult<T> {
+    std::panic::catch_unwind(f).unwrap_or_else(|payload| {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => payload
+                .downcast_ref::<String>()
+                .cloned()
+                .unwrap_or_default(),
+        };
+        Err(format!("internal compiler error: {}", message).into())
+    })
+}
+
+/// Compiles Teeny Tiny source code with the given backend, returning the
+/// generated code.
+pub fn compile_with<E: Emitter>(source: &str, mut emitter: E) -> GenResult<String> {
+    let mut program = Parser::from_source(source)?.parse()?;
+    typecheck(&program)?;
+    fold_constants(&mut program);
+    eliminate_dead_code(&mut program);
+    emitter.emit_program(&program)?;
+    Ok(emitter.finish())
+}
+
+/// Runs Teeny Tiny source code directly, feeding it `input` and returning
+/// what it printed.
+pub fn interpret(source: &str, input: &str) -> GenResult<String> {
+    let program = Parser::from_source(source)?.parse()?;
+    typecheck(&program)?;
+
+    let mut output = Vec::new();
+    Interpreter::new(input.as_bytes(), &mut output).run(&program)?;
+    Ok(String::from_utf8(output)?)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lexer::{Lexer, TokenType};
+    use crate::{catch_panic, compile, tokenize, try_compile};
+
+    #[test]
+    fn test_compile() {
+        let code = compile("PRINT \"hi\"").unwrap();
+        assert!(code.starts_with("#include <stdio.h>\n"));
+        assert!(code.contains("printf(\"hi\\n\");"));
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let source = std::fs::read_to_string("samples/fib.teeny").unwrap();
+
+        let mut lexer = Lexer::new(&source);
+        let mut expected = Vec::new();
+        loop {
+            let token = lexer.get_token().unwrap();
+            expected.push(token.clone());
+            if token.kind == TokenType::Eof {
+                break;
+            }
+        }
+
+        assert_eq!(tokenize(&source).unwrap(), expected);
+        assert!(tokenize("PRINT $").is_err());
+    }
+
+    #[test]
+    fn test_compile_error() {
+        assert!(compile("PRINT x").is_err());
+    }
+
+    #[test]
+    fn test_try_compile_malformed() {
+        let deep = |open: &str, inner: &str, close: &str| {
+            format!("{}{}{}", open.repeat(10_000), inner, close.repeat(10_000))
+        };
+
+        for source in [
+            deep("IF 1 > 0 THEN\n", "PRINT 1\n", "ENDIF\n"),
+            deep("WHILE 1 > 0 REPEAT\n", "BREAK\n", "ENDWHILE\n"),
+            deep("IF 1 > 0 THEN ", "PRINT 1", ""),
+            deep("PRINT (", "1", ")"),
+            deep("PRINT SQRT(", "1", ")"),
+            deep("PRINT -(", "1", ")"),
+            deep("IF NOT ", "1 > 0", " THEN\nENDIF\n"),
+            "PRINT ((((".to_string(),
+            "IF 1 > 0 THEN\nWHILE 1 > 0 REPEAT\nENDIF\nENDWHILE".to_string(),
+            "PRINT \"unterminated".to_string(),
+            "PRINT \"\\x\"".to_string(),
+            "PRINT \"\\xé1\"".to_string(),
+            "PRINT \"\\q\"".to_string(),
+            "PRINT \"\0\r\"\n".to_string(),
+            "PRINT 0xFFFFFFFFFFFFFFFFFFFFFFFF".to_string(),
+            "PRINT 0x".to_string(),
+            "PRINT 1e".to_string(),
+            "PRINT 1__0".to_string(),
+            "LET $ = 1".to_string(),
+            "PRINT ,\r\r#".to_string(),
+            "LABEL\nGOTO\nEXIT \"x\"\nSWAP a,".to_string(),
+            "ELSEIF 1 > 0 THEN\nENDIF\nENDWHILE\nWHILE".to_string(),
+            "PRINT \"abc\nPRINT 1\n".to_string(),
+            "LET s = \"a\\\"\n".to_string(),
+            deep("IF 1 > 0 THEN\n", "PRINT \"", ""),
+            "PRINT 1\u{1}\u{7f}\n".to_string(),
+            "PRINT 1 @ 2\n".to_string(),
+            "\u{feff}PRINT 1\n".to_string(),
+            "LET a = 1 \u{1b}[0m\n".to_string(),
+            "PRINT \u{fffd}\u{200b}\n".to_string(),
+            "INCLUDE \"x.teeny\"\n".to_string(),
+            "LET a = 1\nGOTO a\n\t\t`~".to_string(),
+        ] {
+            assert!(try_compile(&source).is_err(), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn test_try_compile_catches_panics() {
+        let err = catch_panic::<()>(|| panic!("oops")).unwrap_err();
+        assert_eq!(err.to_string(), "internal compiler error: oops");
+        let err = catch_panic::<()>(|| panic!("{} oops", 2)).unwrap_err();
+        assert_eq!(err.to_string(), "internal compiler error: 2 oops");
+        assert_eq!(catch_panic(|| Ok(1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_try_compile_long_chains() {
+        let code = try_compile(&format!("LET x = 1\nPRINT x{}\n", " + 1".repeat(10_000))).unwrap();
+        assert!(code.contains(&format!("x{}", "+1".repeat(10_000))));
+
+        // `^` groups to the right, so each one nests the rest of the chain
+        assert!(try_compile(&format!("PRINT 2{}\n", " ^ 2".repeat(10_000))).is_err());
+    }
+}
@@ -1,34 +1,294 @@
 use std::fs::File;
 use std::io::{BufReader, Read};
-use ttc_rs::emitter::Emitter;
+use ttc_rs::ast;
+use ttc_rs::emitter::{CEmitter, Emitter, Standard, DEFAULT_FUNCTION_NAME};
+use ttc_rs::error::{CompileError, CompileErrors, Diagnostic, TooManyErrors, Warning};
+use ttc_rs::format::format_source;
+use ttc_rs::interp::Interpreter;
+use ttc_rs::js::JsEmitter;
 use ttc_rs::lexer::Lexer;
-use ttc_rs::parser::Parser;
+use ttc_rs::optimize::{eliminate_dead_code, fold_constants};
+use ttc_rs::parser::{CompileStats, Parser, DEFAULT_MAX_ERRORS};
+use ttc_rs::typecheck::typecheck;
 use ttc_rs::GenResult;
 
+/// Where the generated C goes unless `-o` says otherwise.
+const DEFAULT_OUTFILE: &str = "out.c";
+
+/// Where the generated JavaScript goes unless `-o` says otherwise.
+const DEFAULT_JS_OUTFILE: &str = "out.js";
+
 fn main() {
-    let args = std::env::args().skip(1).collect::<Vec<String>>();
-    if args.len() != 1 {
-        usage();
+    let mut infile = None;
+    let mut outfile = None;
+    let mut emit_stdout = false;
+    let mut js = false;
+    let mut run = false;
+    let mut fmt = false;
+    let mut dump_tokens = false;
+    let mut json_tokens = false;
+    let mut dump_ast = false;
+    let mut float_precision = None;
+    let mut unbuffered_stdout = false;
+    let mut function_name = DEFAULT_FUNCTION_NAME.to_string();
+    let mut standard = Standard::C99;
+    let mut double = false;
+    let mut line_directives = false;
+    let mut werror = false;
+    let mut max_errors = DEFAULT_MAX_ERRORS;
+    let mut allow_raw = false;
+    let mut show_stats = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => match args.next() {
+                Some(path) => outfile = Some(path),
+                None => usage(),
+            },
+            "--float-precision" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => float_precision = Some(n),
+                None => usage(),
+            },
+            "--func-name" => match args.next().filter(|name| is_c_identifier(name)) {
+                Some(name) => function_name = name,
+                None => usage(),
+            },
+            "--std" => match args.next().as_deref() {
+                Some("c89") => standard = Standard::C89,
+                Some("c99") => standard = Standard::C99,
+                _ => usage(),
+            },
+            "--max-errors" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => max_errors = n,
+                _ => usage(),
+            },
+            "--double" => double = true,
+            "--line-directives" => line_directives = true,
+            "--werror" => werror = true,
+            "--allow-raw" => allow_raw = true,
+            "--stats" => show_stats = true,
+            "--emit-stdout" => emit_stdout = true,
+            "--target-stdout-buffered" => unbuffered_stdout = true,
+            "--js" => js = true,
+            "--run" => run = true,
+            "--fmt" => fmt = true,
+            "--dump-tokens" => dump_tokens = true,
+            "--dump-tokens=json" => {
+                dump_tokens = true;
+                json_tokens = true;
+            }
+            "--dump-ast" => dump_ast = true,
+            flag if flag.starts_with('-') => usage(),
+            _ if infile.is_none() => infile = Some(arg),
+            _ => usage(),
+        }
     }
 
-    match read_source(&args[0]) {
+    let infile = match infile {
+        Some(infile) => infile,
+        None => usage(),
+    };
+
+    match read_source(&infile) {
+        Ok(source) if json_tokens => {
+            let tokens = match lexer(&infile, &source).collect::<GenResult<Vec<_>>>() {
+                Ok(tokens) => tokens,
+                Err(err) => report(&infile, &source, err),
+            };
+            println!("[");
+            for (i, token) in tokens.iter().enumerate() {
+                println!(
+                    "  {{\"kind\": {}, \"spelling\": {}, \"line\": {}, \"col\": {}}}{}",
+                    json_string(&format!("{:?}", token.kind)),
+                    json_string(&token.spelling),
+                    token.line,
+                    token.col,
+                    if i + 1 < tokens.len() { "," } else { "" }
+                );
+            }
+            println!("]");
+        }
+
+        Ok(source) if dump_tokens => {
+            for token in lexer(&infile, &source) {
+                match token {
+                    Ok(token) => println!(
+                        "{}:{} {:?} {:?}",
+                        token.line, token.col, token.kind, token.spelling
+                    ),
+                    Err(err) => report(&infile, &source, err),
+                }
+            }
+        }
+
+        Ok(source) if dump_ast => {
+            let program = Parser::new(lexer(&infile, &source)).and_then(|mut p| {
+                p.max_errors = max_errors;
+                p.allow_raw = allow_raw;
+                p.parse()
+            });
+            match program {
+                Ok(program) => print!("{}", ast::dump(&program)),
+                Err(err) => report(&infile, &source, err),
+            }
+        }
+
+        Ok(source) if fmt => match format_source(&source) {
+            Ok(formatted) => print!("{}", formatted),
+            Err(err) => report(&infile, &source, err),
+        },
+
         Ok(source) => {
-            let mut emitter = Emitter::new("out.c");
-            let mut parser = Parser::new(Lexer::new(&source), &mut emitter);
-            parser.parse();
+            let mut warnings = Vec::new();
+            let mut stats = CompileStats::default();
+            let program = Parser::new(lexer(&infile, &source))
+                .and_then(|mut p| {
+                    p.max_errors = max_errors;
+                    p.allow_raw = allow_raw;
+                    // the interpreter and JavaScript always use doubles
+                    p.double = double || run || js;
+                    let program = p.parse()?;
+                    warnings.extend_from_slice(p.warnings());
+                    stats = p.stats();
+                    Ok(program)
+                })
+                .and_then(|program| typecheck(&program).map(|_| program));
+            let mut program = match program {
+                Ok(program) => program,
+                Err(err) => report(&infile, &source, err),
+            };
+
+            if run {
+                warn(&infile, &source, &warnings, werror);
+                let stdin = std::io::stdin();
+                let stdout = std::io::stdout();
+                let mut interpreter = Interpreter::new(stdin.lock(), stdout.lock());
+                interpreter.float_precision = float_precision;
+                if let Err(err) = interpreter.run(&program) {
+                    report(&infile, &source, err);
+                }
+                std::process::exit(interpreter.status());
+            }
+
+            fold_constants(&mut program);
+            warnings.extend(eliminate_dead_code(&mut program));
+            warn(&infile, &source, &warnings, werror);
+
+            if js {
+                let mut emitter = JsEmitter::new();
+                emitter.float_precision = float_precision;
+                if let Err(err) = emitter.emit_program(&program) {
+                    report(&infile, &source, err);
+                }
+
+                let code = emitter.finish();
+                if emit_stdout {
+                    print!("{}", code);
+                    print_stats(stats, show_stats);
+                    return;
+                }
+
+                let outfile = outfile.unwrap_or_else(|| DEFAULT_JS_OUTFILE.to_string());
+                match std::fs::write(&outfile, code) {
+                    Ok(_) => {
+                        println!("Program compiled successfully");
+                        print_stats(stats, show_stats);
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to write {}: {}", outfile, err);
+                        std::process::exit(1);
+                    }
+                }
+                return;
+            }
+
+            let outfile = outfile.unwrap_or_else(|| DEFAULT_OUTFILE.to_string());
+            let mut emitter = CEmitter::new(outfile);
+            emitter.float_precision = float_precision;
+            emitter.unbuffered_stdout = unbuffered_stdout;
+            emitter.function_name = function_name;
+            emitter.standard = standard;
+            emitter.double = double;
+            if line_directives {
+                emitter.line_directives = Some(infile.clone());
+            }
+            if let Err(err) = emitter.emit_program(&program) {
+                report(&infile, &source, err);
+            }
+
+            if emit_stdout {
+                print!("{}", emitter.finish());
+                print_stats(stats, show_stats);
+                return;
+            }
+
             match emitter.write_file() {
-                Ok(_) => println!("Program compiled successfully"),
-                Err(err) => eprintln!("Failed to compile to C code: {:?}", err),
+                Ok(_) => {
+                    println!("Program compiled successfully");
+                    print_stats(stats, show_stats);
+                }
+                Err(err) => {
+                    eprintln!("Failed to compile to C code: {:?}", err);
+                    std::process::exit(1);
+                }
             }
         }
 
-        Err(err) => eprintln!(
-            "Error while trying to open source file {}: {}",
-            args[0], err
-        ),
+        Err(err) => {
+            eprintln!("Error while trying to open source file {}: {}", infile, err);
+            std::process::exit(1);
+        }
     }
 }
 
+/// Prints the counts from `--stats`, if it was given. They go to stderr so
+/// that they don't end up in code printed with `--emit-stdout`.
+fn print_stats(stats: CompileStats, show_stats: bool) {
+    if show_stats {
+        eprint!("{}", stats);
+    }
+}
+
+/// Prints the warnings found in the source file, each with the line of
+/// source it points at, and exits if there are any and they count as errors.
+fn warn(infile: &str, source: &str, warnings: &[Warning], werror: bool) {
+    for warning in warnings {
+        eprintln!("{}: {}", infile, warning.render(source));
+    }
+    if werror && !warnings.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// Prints the errors found in the source file, each with the line of source
+/// it points at, and exits.
+fn report(infile: &str, source: &str, err: Box<dyn std::error::Error>) -> ! {
+    if let Some(errs) = err.downcast_ref::<CompileErrors>() {
+        for err in &errs.0 {
+            eprintln!("{}: {}", infile, err.render(source));
+        }
+    } else if let Some(errs) = err.downcast_ref::<TooManyErrors>() {
+        for err in &errs.0 {
+            eprintln!("{}: {}", infile, err.render(source));
+        }
+        eprintln!("{}: too many errors; aborting", infile);
+    } else if let Some(err) = err.downcast_ref::<CompileError>() {
+        eprintln!("{}: {}", infile, err.render(source));
+    } else {
+        eprintln!("{}: {}", infile, err);
+    }
+    std::process::exit(1);
+}
+
+/// A lexer for the source read from `infile`, which the source's `INCLUDE`s
+/// are relative to.
+fn lexer(infile: &str, source: &str) -> Lexer {
+    let mut lexer = Lexer::new(source);
+    lexer.path = Some(infile.into());
+    lexer
+}
+
 fn read_source(infile: &str) -> GenResult<String> {
     let mut reader = BufReader::new(File::open(infile)?);
     let mut buffer = String::new();
@@ -36,7 +296,64 @@ fn read_source(infile: &str) -> GenResult<String> {
     Ok(buffer)
 }
 
-fn usage() {
-    eprintln!("Usage: ttc source-file");
-    std::process::exit(0);
+/// `value` as a JSON string literal.
+fn json_string(value: &str) -> String {
+    let mut json = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Whether `name` can name a C function.
+fn is_c_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: ttc [options] source-file");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!(
+        "  -o, --output <path>  write the generated C to <path> (default: {})",
+        DEFAULT_OUTFILE
+    );
+    eprintln!("  --emit-stdout        print the generated code instead of writing a file");
+    eprintln!("  --target-stdout-buffered");
+    eprintln!("                       turn off stdout buffering in the generated C, so that");
+    eprintln!("                       prompts show up before INPUT waits");
+    eprintln!("  --func-name <name>   wrap the generated C in `int <name>(void)` instead of main");
+    eprintln!("  --double             declare numbers in the generated C as double, not float");
+    eprintln!("  --std <c89|c99>      the C standard to generate code for (default: c99)");
+    eprintln!("  --line-directives    add #line directives, so that C compiler errors and");
+    eprintln!("                       debuggers refer to lines of the source file");
+    eprintln!("  --float-precision <n> print floats with <n> decimal places, rather than to");
+    eprintln!("                       six significant digits without trailing zeros");
+    eprintln!(
+        "  --js                 generate JavaScript instead of C (default output: {})",
+        DEFAULT_JS_OUTFILE
+    );
+    eprintln!(
+        "  --max-errors <n>     stop after reporting <n> errors (default: {})",
+        DEFAULT_MAX_ERRORS
+    );
+    eprintln!("  --allow-raw          allow EMIT to put raw C in the generated code");
+    eprintln!("  --stats              print counts of statements, variables, labels and GOTOs");
+    eprintln!("  --werror             fail on warnings, such as unused labels or dead code");
+    eprintln!("  --run                run the program directly instead of compiling it");
+    eprintln!("  --fmt                print the program reformatted instead of compiling it");
+    eprintln!("  --dump-tokens        print the program's tokens instead of compiling it");
+    eprintln!("  --dump-tokens=json   print them as a JSON array instead");
+    eprintln!("  --dump-ast           print the program's syntax tree instead of compiling it");
+    std::process::exit(1);
 }
@@ -0,0 +1,591 @@
+//! The optimizer module, which simplifies a parsed and type checked program
+//! before code is generated for it
+
+use crate::ast::{BinaryOp, Expr, Pos, Span, Stmt, UnaryOp};
+use crate::error::Warning;
+use crate::lexer::{is_integer_literal, number};
+
+/// The value of an expression worked out at compile time, typed as the type
+/// checker would type the expression.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Constant {
+    Int(i32),
+    Float(f64),
+}
+
+impl Constant {
+    fn value(self) -> f64 {
+        match self {
+            Constant::Int(n) => n as f64,
+            Constant::Float(x) => x,
+        }
+    }
+
+    /// The literal for the constant, spelled so that C reads back the same
+    /// value and type. A negative constant is a negated literal.
+    fn to_expr(self, span: Span) -> Expr {
+        let (negative, spelling) = match self {
+            Constant::Int(n) => (n < 0, n.unsigned_abs().to_string()),
+            // `{:?}` always has a `.` or an exponent, so it stays a float
+            Constant::Float(x) => (x.is_sign_negative(), format!("{:?}", x.abs())),
+        };
+        let literal = Expr::Number(spelling, span);
+
+        if negative {
+            Expr::Unary {
+                op: UnaryOp::Minus,
+                pos: span.start,
+                span,
+                expr: Box::new(literal),
+            }
+        } else {
+            literal
+        }
+    }
+}
+
+/// Folds constant arithmetic and comparisons into literals throughout a
+/// program, so that e.g. `2 * 3 + 1` is emitted as `7`.
+///
+/// Folding follows C's rules: integers stay integers, with `/` truncating,
+/// and anything involving a float is worked out as a `double`. Anything that
+/// can't be folded exactly is left as it is, namely division by zero,
+/// integer overflow and `^`, which backends compute with differing precision.
+pub fn fold_constants(program: &mut [Stmt]) {
+    for stmt in program {
+        fold_statement(stmt);
+    }
+}
+
+fn fold_statement(stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Print(items, _) | Stmt::Write(items, _) => {
+            for item in items {
+                fold(item);
+            }
+        }
+
+        Stmt::If {
+            branches,
+            else_body,
+            ..
+        } => {
+            for (cond, body) in branches {
+                fold(cond);
+                fold_constants(body);
+            }
+            if let Some(body) = else_body {
+                fold_constants(body);
+            }
+        }
+
+        Stmt::While { cond, body, .. } | Stmt::DoWhile { body, cond, .. } => {
+            fold(cond);
+            fold_constants(body);
+        }
+
+        // a condition gives the variable a `LET` declares its type, so it's
+        // kept, with only its operands folded
+        Stmt::Let { value, .. } if value.is_condition() => match value {
+            Expr::Unary { expr, .. } => {
+                fold(expr);
+            }
+            Expr::Binary { lhs, rhs, .. } => {
+                fold(lhs);
+                fold(rhs);
+            }
+            _ => {}
+        },
+
+        Stmt::Assert(value, _)
+        | Stmt::Return(value, _)
+        | Stmt::Exit(Some(value), _)
+        | Stmt::Let { value, .. }
+        | Stmt::Int { value, .. }
+        | Stmt::Const { value, .. }
+        | Stmt::Assign { value, .. }
+        | Stmt::CompoundAssign { value, .. } => {
+            fold(value);
+        }
+
+        Stmt::Exit(None, _)
+        | Stmt::Label(..)
+        | Stmt::Goto(..)
+        | Stmt::Emit(..)
+        | Stmt::Swap(..)
+        | Stmt::Break(_)
+        | Stmt::Continue(_)
+        | Stmt::Input { .. }
+        | Stmt::Comment(..) => {}
+    }
+}
+
+/// Folds the constant parts of an expression in place, returning its value
+/// if the whole of it is constant.
+fn fold(expr: &mut Expr) -> Option<Constant> {
+    let constant = match expr {
+        Expr::Number(value, _) if is_integer_literal(value) => {
+            let value = number(value);
+            (value <= i32::MAX as f64).then_some(Constant::Int(value as i32))
+        }
+        Expr::Number(value, _) => Some(Constant::Float(number(value))),
+
+        Expr::Str(..) | Expr::Var(..) => None,
+
+        Expr::Unary { op, expr, .. } => match (op, fold(expr)?) {
+            (UnaryOp::Plus, value) => Some(value),
+            (UnaryOp::Minus, Constant::Int(n)) => n.checked_neg().map(Constant::Int),
+            (UnaryOp::Minus, Constant::Float(x)) => Some(Constant::Float(-x)),
+            (UnaryOp::Not, value) => Some(Constant::Int((value.value() == 0.0) as i32)),
+        },
+
+        Expr::Binary { .. } => return fold_chain(expr),
+
+        Expr::Call { args, .. } => {
+            for arg in args {
+                fold(arg);
+            }
+            None
+        }
+    };
+
+    replace_constant(expr, constant)
+}
+
+/// Folds a chain of binary operations down the left of an expression a link
+/// at a time, taking the chain apart and putting it back together as it goes,
+/// rather than recursing once for each link.
+fn fold_chain(expr: &mut Expr) -> Option<Constant> {
+    // the operations from the outermost in, each missing its left operand
+    let mut links = Vec::new();
+    let mut folded = std::mem::replace(expr, Expr::placeholder());
+    while let Expr::Binary { lhs, .. } = &mut folded {
+        let next = std::mem::replace(&mut **lhs, Expr::placeholder());
+        links.push(std::mem::replace(&mut folded, next));
+    }
+
+    let mut constant = fold(&mut folded);
+    for mut link in links.into_iter().rev() {
+        let Expr::Binary { op, lhs, rhs, .. } = &mut link else {
+            unreachable!("only binary operations are in the chain");
+        };
+        // both sides are folded, even if only one of them is constant
+        let rhs = fold(rhs);
+        let op = *op;
+        **lhs = folded;
+
+        constant = constant
+            .zip(rhs)
+            .and_then(|(lhs, rhs)| fold_binary(op, lhs, rhs));
+        constant = replace_constant(&mut link, constant);
+        folded = link;
+    }
+
+    *expr = folded;
+    constant
+}
+
+/// Replaces an expression with the literal for its value, if it's constant
+/// and has one, returning the value.
+fn replace_constant(expr: &mut Expr, constant: Option<Constant>) -> Option<Constant> {
+    // the most negative int has no literal of its own in C
+    let constant = constant.filter(|constant| match constant {
+        Constant::Int(n) => *n != i32::MIN,
+        Constant::Float(x) => x.is_finite(),
+    })?;
+
+    if !matches!(expr, Expr::Number(..)) {
+        *expr = constant.to_expr(expr.span());
+    }
+    Some(constant)
+}
+
+fn fold_binary(op: BinaryOp, lhs: Constant, rhs: Constant) -> Option<Constant> {
+    let truth = |cond: bool| Some(Constant::Int(cond as i32));
+    let (x, y) = (lhs.value(), rhs.value());
+
+    match (op, lhs, rhs) {
+        (BinaryOp::Add, Constant::Int(a), Constant::Int(b)) => a.checked_add(b).map(Constant::Int),
+        (BinaryOp::Sub, Constant::Int(a), Constant::Int(b)) => a.checked_sub(b).map(Constant::Int),
+        (BinaryOp::Mul, Constant::Int(a), Constant::Int(b)) => a.checked_mul(b).map(Constant::Int),
+        // `checked_div` also catches division by zero
+        (BinaryOp::Div, Constant::Int(a), Constant::Int(b)) => a.checked_div(b).map(Constant::Int),
+
+        (BinaryOp::Add, ..) => Some(Constant::Float(x + y)),
+        (BinaryOp::Sub, ..) => Some(Constant::Float(x - y)),
+        (BinaryOp::Mul, ..) => Some(Constant::Float(x * y)),
+        (BinaryOp::Div, ..) => Some(Constant::Float(x / y)),
+        // lowered to fmod, which is what `%` on floats is in Rust
+        (BinaryOp::Mod, ..) => Some(Constant::Float(x % y)),
+        (BinaryOp::Pow, ..) => None,
+
+        (BinaryOp::Eq, ..) => truth(x == y),
+        (BinaryOp::NotEq, ..) => truth(x != y),
+        (BinaryOp::Lt, ..) => truth(x < y),
+        (BinaryOp::Lte, ..) => truth(x <= y),
+        (BinaryOp::Gt, ..) => truth(x > y),
+        (BinaryOp::Gte, ..) => truth(x >= y),
+        (BinaryOp::And, ..) => truth(x != 0.0 && y != 0.0),
+        (BinaryOp::Or, ..) => truth(x != 0.0 || y != 0.0),
+    }
+}
+
+/// Removes code that can never run, once constants have been folded: the
+/// branches of an `IF` whose condition is always false, or that come after
+/// one that is always true, loops whose condition is always false, and
+/// statements after a `GOTO` up to the next `LABEL`. Returns a warning for
+/// each removal.
+///
+/// Code containing a `LABEL` can still be reached by a `GOTO`, and so is
+/// never removed. Nor are unreachable `LET`, `INT`, `CONST` and `INPUT`
+/// statements, as later code may use the variables they declare.
+pub fn eliminate_dead_code(program: &mut Vec<Stmt>) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    eliminate(program, &mut warnings);
+    warnings
+}
+
+fn eliminate(stmts: &mut Vec<Stmt>, warnings: &mut Vec<Warning>) {
+    // whether the current statement follows a GOTO, and if so whether its
+    // removal has been warned about yet
+    let mut after_goto: Option<bool> = None;
+
+    for stmt in std::mem::take(stmts) {
+        if has_label(std::slice::from_ref(&stmt)) {
+            after_goto = None;
+        }
+
+        if let Some(warned) = &mut after_goto {
+            if !matches!(
+                stmt,
+                Stmt::Comment(..)
+                    | Stmt::Let { .. }
+                    | Stmt::Int { .. }
+                    | Stmt::Const { .. }
+                    | Stmt::Input { .. }
+            ) {
+                if !*warned {
+                    warnings.push(warning(
+                        "unreachable code after GOTO removed",
+                        stmt.span().start,
+                    ));
+                    *warned = true;
+                }
+                continue;
+            }
+        }
+
+        if let Stmt::Goto(..) = stmt {
+            after_goto = Some(false);
+        }
+        stmts.extend(simplify(stmt, warnings));
+    }
+}
+
+/// The statement with its dead parts removed, or `None` if all of it is.
+fn simplify(stmt: Stmt, warnings: &mut Vec<Warning>) -> Option<Stmt> {
+    match stmt {
+        Stmt::If {
+            branches,
+            mut else_body,
+            span,
+        } => {
+            let mut kept = Vec::new();
+            let count = branches.len();
+
+            for (i, (cond, mut body)) in branches.into_iter().enumerate() {
+                match truth(&cond) {
+                    Some(false) if !has_label(&body) => {
+                        warnings.push(warning(
+                            "condition is always false, so its body is removed",
+                            cond.span().start,
+                        ));
+                    }
+
+                    Some(true) if i + 1 < count || else_body.is_some() => {
+                        warnings.push(warning(
+                            "condition is always true, so the branches after it are removed",
+                            cond.span().start,
+                        ));
+                        eliminate(&mut body, warnings);
+                        kept.push((cond, body));
+                        else_body = None;
+                        break;
+                    }
+
+                    _ => {
+                        eliminate(&mut body, warnings);
+                        kept.push((cond, body));
+                    }
+                }
+            }
+
+            if let Some(body) = &mut else_body {
+                eliminate(body, warnings);
+            }
+
+            if kept.is_empty() {
+                // only the ELSE is left, which always runs
+                let body = else_body?;
+                let always = Expr::Number("1".to_string(), span);
+                return Some(Stmt::If {
+                    branches: vec![(always, body)],
+                    else_body: None,
+                    span,
+                });
+            }
+
+            Some(Stmt::If {
+                branches: kept,
+                else_body,
+                span,
+            })
+        }
+
+        Stmt::While { cond, body, .. } if truth(&cond) == Some(false) && !has_label(&body) => {
+            warnings.push(warning(
+                "condition is always false, so the loop is removed",
+                cond.span().start,
+            ));
+            None
+        }
+
+        Stmt::While {
+            cond,
+            mut body,
+            span,
+        } => {
+            eliminate(&mut body, warnings);
+            Some(Stmt::While { cond, body, span })
+        }
+
+        Stmt::DoWhile {
+            mut body,
+            cond,
+            span,
+        } => {
+            eliminate(&mut body, warnings);
+            Some(Stmt::DoWhile { body, cond, span })
+        }
+
+        stmt => Some(stmt),
+    }
+}
+
+fn warning(message: &str, pos: Pos) -> Warning {
+    Warning::new(message, pos.line, pos.col)
+}
+
+/// Whether a folded condition is always true or always false, if it's
+/// constant.
+fn truth(cond: &Expr) -> Option<bool> {
+    match cond {
+        Expr::Number(value, _) => Some(number(value) != 0.0),
+        Expr::Unary {
+            op: UnaryOp::Minus,
+            expr,
+            ..
+        } => truth(expr),
+        _ => None,
+    }
+}
+
+/// Whether any of the statements is, or contains, a `LABEL`.
+fn has_label(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        Stmt::Label(..) => true,
+        Stmt::If {
+            branches,
+            else_body,
+            ..
+        } => {
+            branches.iter().any(|(_, body)| has_label(body))
+                || else_body.as_deref().is_some_and(has_label)
+        }
+        Stmt::While { body, .. } | Stmt::DoWhile { body, .. } => has_label(body),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::{BinaryOp, Expr, Pos, Span, Stmt};
+    use crate::emitter::{CEmitter, Emitter};
+    use crate::lexer::Lexer;
+    use crate::optimize::{eliminate_dead_code, fold_constants};
+    use crate::parser::Parser;
+    use crate::typecheck::typecheck;
+
+    /// The C generated for the folded program, flush left.
+    fn emit_folded(source: &str) -> String {
+        let mut program = Parser::new(Lexer::new(source)).unwrap().parse().unwrap();
+        fold_constants(&mut program);
+        emit_program(&program)
+    }
+
+    /// The C generated for the program with its dead code removed, and the
+    /// warnings about what was.
+    fn emit_live(source: &str) -> (String, Vec<String>) {
+        let mut program = Parser::new(Lexer::new(source)).unwrap().parse().unwrap();
+        fold_constants(&mut program);
+        let warnings = eliminate_dead_code(&mut program);
+        (
+            emit_program(&program),
+            warnings.iter().map(|warning| warning.to_string()).collect(),
+        )
+    }
+
+    fn emit_program(program: &[Stmt]) -> String {
+        let mut emitter = CEmitter::new("dummy.c");
+        emitter.indent_width = 0;
+        emitter.emit_program(program).unwrap();
+        emitter.code().to_string()
+    }
+
+    #[test]
+    fn test_fold_constant_expressions() {
+        let code = emit_folded(
+            "LET x = 2 * 3 + 1\nINT i = 7 / 2 - 0x10\nPRINT 1.5 * 4, 7 % 4, -(2 - 2.5)\nASSERT 2 + 3 > 4\nWHILE NOT 1 == 1 OR 2 < 1 REPEAT\nENDWHILE\n",
+        );
+        assert!(code.contains("x = 7;\n"));
+        assert!(code.contains("i = -13;\n"));
+        assert!(code.contains("printf(\"%g %g %g\\n\", 6.0, 3.0, 0.5);\n"));
+        assert!(code.contains("if (!(1)) {\n"));
+        assert!(code.contains("while (0) {\n"));
+    }
+
+    #[test]
+    fn test_fold_leaves_variables() {
+        let code = emit_folded("LET x = 1\nLET y = x * 2 + 3\nLET z = x - (1 - 4) * 2\n");
+        assert!(code.contains("y = x*2+3;\n"));
+        assert!(code.contains("z = x - -6;\n"));
+    }
+
+    #[test]
+    fn test_fold_leaves_what_cant_be_folded() {
+        let code =
+            emit_folded("INT i = 1 / 0\nLET x = 1.5 / 0\nINT j = 2147483647 + 1\nLET y = 2 ^ 3\n");
+        assert!(code.contains("i = 1/0;\n"));
+        assert!(code.contains("x = 1.5/0;\n"));
+        assert!(code.contains("j = 2147483647+1;\n"));
+        assert!(code.contains("y = powf(2, 3);\n"));
+    }
+
+    #[test]
+    fn test_long_chain() {
+        // the parser doesn't limit how long a flat chain of operations is, so
+        // a pass mustn't recurse once for each of them
+        let chain = |first: Expr| {
+            (0..100_000).fold(first, |lhs, _| Expr::Binary {
+                op: BinaryOp::Add,
+                pos: Pos::default(),
+                span: Span::default(),
+                lhs: Box::new(lhs),
+                rhs: Box::new(Expr::Number("1".to_string(), Span::default())),
+            })
+        };
+        let let_stmt = |name: &str, value| Stmt::Let {
+            name: name.to_string(),
+            value,
+            doc: None,
+            span: Span::default(),
+        };
+
+        let mut program = vec![
+            let_stmt("x", Expr::Number("0".to_string(), Span::default())),
+            let_stmt("y", chain(Expr::Var("x".to_string(), Span::default()))),
+            let_stmt("z", chain(Expr::Number("0".to_string(), Span::default()))),
+        ];
+        typecheck(&program).unwrap();
+        fold_constants(&mut program.clone());
+        fold_constants(&mut program);
+        eliminate_dead_code(&mut program);
+
+        let code = emit_program(&program);
+        assert!(code.contains(&format!("y = x{};\n", "+1".repeat(100_000))));
+        assert!(code.contains("z = 100000;\n"));
+    }
+
+    #[test]
+    fn test_fold_nested_in_calls_and_blocks() {
+        let code = emit_folded("WHILE 1 < 2 REPEAT\nPRINT SQRT(2 * 8)\nBREAK\nENDWHILE\n");
+        assert!(code.contains("while (1) {\nprintf(\"%g\\n\", sqrtf(16));\n"));
+    }
+
+    #[test]
+    fn test_eliminate_false_if() {
+        let (code, warnings) =
+            emit_live("LET x = 1\nIF 0 > 1 THEN\nPRINT \"never\"\nENDIF\nPRINT x\n");
+        assert_eq!(code, "x = 1;\nprintf(\"%g\\n\", x);\nreturn 0;\n}\n");
+        assert_eq!(
+            warnings,
+            vec!["warning at 2:4: condition is always false, so its body is removed"]
+        );
+    }
+
+    #[test]
+    fn test_eliminate_branches() {
+        let source = "\
+LET x = 1
+IF 1 > 2 THEN
+    PRINT 1
+ELSEIF x > 0 THEN
+    PRINT 2
+ELSEIF 2 > 1 THEN
+    PRINT 3
+ELSE
+    PRINT 4
+ENDIF
+IF 1 > 2 THEN
+    PRINT 5
+ELSE
+    PRINT 6
+ENDIF
+WHILE 1 == 2 REPEAT
+    PRINT 7
+ENDWHILE
+";
+        let (code, warnings) = emit_live(source);
+        assert!(code.starts_with(
+            "x = 1;\nif (x>0) {\nprintf(\"%d\\n\", 2);\n} else if (1) {\nprintf(\"%d\\n\", 3);\n}\nif (1) {\nprintf(\"%d\\n\", 6);\n}\nreturn 0;\n"
+        ));
+        assert_eq!(
+            warnings,
+            vec![
+                "warning at 2:4: condition is always false, so its body is removed",
+                "warning at 6:8: condition is always true, so the branches after it are removed",
+                "warning at 11:4: condition is always false, so its body is removed",
+                "warning at 16:7: condition is always false, so the loop is removed",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_eliminate_after_goto() {
+        let source = "\
+GOTO skip
+PRINT 1
+LET x = 2
+PRINT x
+LABEL skip
+PRINT 3
+GOTO done
+IF 1 > 0 THEN
+    LABEL inside
+    PRINT 4
+ENDIF
+LABEL done
+";
+        let (code, warnings) = emit_live(source);
+        assert!(code.starts_with(
+            "goto skip;\nx = 2;\nskip:\nprintf(\"%d\\n\", 3);\ngoto done;\nif (1) {\ninside:\n"
+        ));
+        assert_eq!(
+            warnings,
+            vec!["warning at 2:1: unreachable code after GOTO removed"]
+        );
+
+        let (_, warnings) = emit_live("IF 0 > 1 THEN\nLABEL top\nENDIF\nGOTO top\n");
+        assert!(warnings.is_empty());
+    }
+}
@@ -1,412 +1,2942 @@
 //! The Parser module
 
-use crate::emitter::Emitter;
-use crate::lexer::{Lexer, Token, TokenType};
-use std::collections::HashSet;
+use crate::ast::{BinaryOp, Builtin, Expr, Pos, Span, Stmt, Type, UnaryOp};
+use crate::error::{CompileError, CompileErrors, TooManyErrors, Warning};
+use crate::lexer::{number, Lexer, Token, TokenType};
+use crate::symbols::{Symbol, SymbolTable};
+use crate::GenResult;
+use std::collections::HashMap;
+use std::fmt;
 
-pub struct Parser<'a> {
+/// The default maximum depth statements can be nested in blocks, and
+/// expressions in each other and in the statements, so that neither the
+/// parser nor the passes that walk the tree it builds run out of stack on a
+/// pathological program.
+pub const DEFAULT_MAX_NESTING: usize = 256;
+
+/// The default number of errors the parser reports before giving up on the
+/// rest of the program.
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+
+/// Counts of what a program contains, for `--stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CompileStats {
+    /// Every statement, including those nested in blocks.
+    pub statements: usize,
+    /// The variables and constants declared.
+    pub variables: usize,
+    pub labels: usize,
+    pub gotos: usize,
+}
+
+impl fmt::Display for CompileStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "statements: {}", self.statements)?;
+        writeln!(f, "variables: {}", self.variables)?;
+        writeln!(f, "labels: {}", self.labels)?;
+        writeln!(f, "gotos: {}", self.gotos)
+    }
+}
+
+pub struct Parser {
     lexer: Lexer,
-    emitter: &'a mut Emitter,
+    /// How deeply statements and expressions can be nested, counting both
+    /// together.
+    pub max_nesting: usize,
+    /// How many errors are reported before giving up, if there are more.
+    pub max_errors: usize,
+    /// Whether `EMIT` may pass raw C through to the generated code.
+    pub allow_raw: bool,
+    /// Whether numbers are `double`s rather than `float`s where they end up,
+    /// which decides how large a literal can be before it overflows.
+    pub double: bool,
     curtoken: Token,
-    symbols: HashSet<String>,
-    declared_labels: HashSet<String>,
-    gotoed_labels: HashSet<String>,
+    /// Where the token before `curtoken` ended, to close the span of the node
+    /// it was the last token of.
+    prev_end: Pos,
+    symbols: SymbolTable,
+    /// Where each label was declared.
+    declared_labels: HashMap<String, Pos>,
+    /// The position of every `GOTO` to each label.
+    gotoed_labels: HashMap<String, Vec<(usize, usize)>>,
+    comments: Vec<(String, Span)>,
+    /// How many loops the current statement is nested in, as `BREAK` and
+    /// `CONTINUE` are only allowed inside one.
+    loop_depth: usize,
+    /// How many statements the current one is nested in.
+    statement_depth: usize,
+    /// How deep the expression being parsed is, so far.
+    expression_depth: usize,
+    /// Whether the nesting limit has been exceeded, which stops the parse
+    /// rather than reporting the same for every level past it.
+    too_deep: bool,
+    /// The declarations after the first of a chained `LET`, which go after
+    /// it in the enclosing block.
+    chained: Vec<Stmt>,
+    errors: Vec<CompileError>,
+    warnings: Vec<Warning>,
+    stats: CompileStats,
+}
+
+fn binary_op(kind: TokenType) -> Option<BinaryOp> {
+    match kind {
+        TokenType::Plus => Some(BinaryOp::Add),
+        TokenType::Minus => Some(BinaryOp::Sub),
+        TokenType::Asterisk => Some(BinaryOp::Mul),
+        TokenType::Slash => Some(BinaryOp::Div),
+        TokenType::Percent => Some(BinaryOp::Mod),
+        TokenType::Caret => Some(BinaryOp::Pow),
+        TokenType::EqEq => Some(BinaryOp::Eq),
+        TokenType::NotEq => Some(BinaryOp::NotEq),
+        TokenType::Lt => Some(BinaryOp::Lt),
+        TokenType::Lte => Some(BinaryOp::Lte),
+        TokenType::Gt => Some(BinaryOp::Gt),
+        TokenType::Gte => Some(BinaryOp::Gte),
+        TokenType::And => Some(BinaryOp::And),
+        TokenType::Or => Some(BinaryOp::Or),
+        _ => None,
+    }
+}
+
+/// The operator of a compound assignment such as `+=`.
+fn compound_op(kind: TokenType) -> Option<BinaryOp> {
+    match kind {
+        TokenType::PlusEq => Some(BinaryOp::Add),
+        TokenType::MinusEq => Some(BinaryOp::Sub),
+        TokenType::AsteriskEq => Some(BinaryOp::Mul),
+        TokenType::SlashEq => Some(BinaryOp::Div),
+        _ => None,
+    }
+}
+
+/// The binding power of comparisons, and what the operand of `NOT` is parsed
+/// with, so that `NOT a == b` is `NOT (a == b)`.
+const NOT_BP: u8 = 5;
+
+/// The binding power of `+` and `-`, the loosest of the arithmetic operators.
+const ARITHMETIC_BP: u8 = 7;
+
+/// The left and right binding powers of a binary operator, from loosest to
+/// tightest: `OR`, `AND`, comparisons, `+` and `-`, `*`, `/` and `%`, and
+/// `^`. A right power above the left makes the operator left-associative,
+/// and one below it, as for `^`, right-associative.
+fn binding_power(kind: TokenType) -> Option<(u8, u8)> {
+    match binary_op(kind)? {
+        BinaryOp::Or => Some((1, 2)),
+        BinaryOp::And => Some((3, 4)),
+        BinaryOp::Eq
+        | BinaryOp::NotEq
+        | BinaryOp::Lt
+        | BinaryOp::Lte
+        | BinaryOp::Gt
+        | BinaryOp::Gte => Some((NOT_BP, 6)),
+        BinaryOp::Add | BinaryOp::Sub => Some((ARITHMETIC_BP, 8)),
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => Some((9, 10)),
+        BinaryOp::Pow => Some((12, 11)),
+    }
+}
+
+fn binary(op: BinaryOp, pos: Pos, lhs: Expr, rhs: Expr) -> Expr {
+    Expr::Binary {
+        op,
+        pos,
+        span: lhs.span().merge(rhs.span()),
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+    }
+}
+
+/// The text and span of a comment token.
+fn comment(token: &Token) -> (String, Span) {
+    let span = Span::new(
+        Pos {
+            line: token.line,
+            col: token.col,
+        },
+        Pos {
+            line: token.end_line,
+            col: token.end_col,
+        },
+    );
+    (token.spelling.clone(), span)
+}
+
+/// The name and position of the first variable used in an expression that
+/// `matches`.
+fn find_variable<'a>(expr: &'a Expr, matches: &dyn Fn(&str) -> bool) -> Option<(&'a str, Pos)> {
+    match expr {
+        Expr::Number(..) | Expr::Str(..) => None,
+        Expr::Var(name, span) if matches(name) => Some((name, span.start)),
+        Expr::Var(..) => None,
+        Expr::Unary { expr, .. } => find_variable(expr, matches),
+        Expr::Binary { .. } => {
+            let (first, links) = expr.left_chain();
+            find_variable(first, matches).or_else(|| {
+                links
+                    .iter()
+                    .find_map(|link| find_variable(link.rhs, matches))
+            })
+        }
+        Expr::Call { args, .. } => args.iter().find_map(|arg| find_variable(arg, matches)),
+    }
+}
+
+impl Parser {
+    /// A parser reading tokens from `lexer`. It only builds the syntax tree;
+    /// `parse` returns the program, which is then handed to an emitter's
+    /// `emit_program`.
+    pub fn new(lexer: Lexer) -> GenResult<Self> {
+        let mut parser = Parser {
+            lexer,
+            max_nesting: DEFAULT_MAX_NESTING,
+            max_errors: DEFAULT_MAX_ERRORS,
+            allow_raw: false,
+            double: false,
+            curtoken: Token::new(TokenType::Eof, ""),
+            prev_end: Pos::default(),
+            symbols: SymbolTable::new(),
+            declared_labels: HashMap::new(),
+            gotoed_labels: HashMap::new(),
+            comments: Vec::new(),
+            loop_depth: 0,
+            statement_depth: 0,
+            too_deep: false,
+            expression_depth: 0,
+            chained: Vec::new(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            stats: CompileStats::default(),
+        };
+        parser.next_token()?;
+
+        Ok(parser)
+    }
+
+    /// A parser for the given source, for when the lexer needs no settings of
+    /// its own.
+    pub fn from_source(source: &str) -> GenResult<Self> {
+        Parser::new(Lexer::new(source))
+    }
+
+    fn check_token(&self, kind: TokenType) -> bool {
+        self.curtoken.kind == kind
+    }
+
+    /// Advances to the next token. Comments can appear anywhere, so they are
+    /// set aside here and attached at the next statement boundary.
+    fn next_token(&mut self) -> GenResult<()> {
+        self.prev_end = Pos {
+            line: self.curtoken.end_line,
+            col: self.curtoken.end_col,
+        };
+        self.curtoken = self.lexer.get_token()?;
+        while self.check_token(TokenType::Comment) {
+            self.comments.push(comment(&self.curtoken));
+            self.curtoken = self.lexer.get_token()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the token after the current one without advancing. Comments in
+    /// between are set aside just as `next_token` would.
+    fn peek_token(&mut self) -> GenResult<Token> {
+        let mut token = self.lexer.peek_token()?;
+        while token.kind == TokenType::Comment {
+            let comment_token = self.lexer.get_token()?;
+            self.comments.push(comment(&comment_token));
+            token = self.lexer.peek_token()?;
+        }
+
+        Ok(token)
+    }
+
+    /// Takes the comments on the lines directly above a `LET` or `INPUT` as
+    /// its doc comment, leaving any others. A comment ending the line of the
+    /// statement before, `prev`, belongs to that statement instead.
+    fn take_doc(&mut self, prev: Option<&Stmt>) -> Option<String> {
+        if !matches!(self.curtoken.kind, TokenType::Let | TokenType::Input) {
+            return None;
+        }
+
+        let mut line = self.curtoken.line;
+        let mut count = 0;
+        for (_, span) in self.comments.iter().rev() {
+            let trailing = prev.is_some_and(|prev| prev.span().end.line == span.start.line);
+            if span.start.line + 1 != line || trailing {
+                break;
+            }
+            line = span.start.line;
+            count += 1;
+        }
+
+        if count == 0 {
+            return None;
+        }
+        let doc = self.comments.split_off(self.comments.len() - count);
+        Some(
+            doc.into_iter()
+                .map(|(text, _)| text)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    }
+
+    fn take_comments(&mut self, stmts: &mut Vec<Stmt>) {
+        stmts.extend(
+            self.comments
+                .drain(..)
+                .map(|(text, span)| Stmt::Comment(text, span)),
+        );
+    }
+
+    fn match_token(&mut self, kind: TokenType) -> GenResult<()> {
+        if !self.check_token(kind) {
+            return self.abort(&format!(
+                "expected {} but found {}",
+                kind, self.curtoken.kind
+            ));
+        }
+        self.next_token()
+    }
+
+    /// Fails if `name`, found at `pos`, is a constant.
+    fn check_not_constant(&self, name: &str, pos: Pos) -> GenResult<()> {
+        if !self.symbols.get(name).is_some_and(|symbol| symbol.constant) {
+            return Ok(());
+        }
+
+        Err(Box::new(CompileError::new(
+            &format!("cannot assign to constant {:?}", name),
+            pos.line,
+            pos.col,
+        )))
+    }
+
+    /// Matches a variable to `SWAP`, returning its name and type. It must be
+    /// a declared numeric variable.
+    fn match_swapped(&mut self) -> GenResult<(String, Type)> {
+        let pos = self.position();
+        let name = self.match_ident()?;
+        self.check_not_constant(&name, pos)?;
+
+        let message = match self.symbols.type_of(&name) {
+            Some(Type::Str) => format!("type error: cannot SWAP string variable {:?}", name),
+            Some(ty) => return Ok((name, ty)),
+            None => format!("Undeclared variable: {:?}", name),
+        };
+
+        Err(Box::new(CompileError::new(&message, pos.line, pos.col)))
+    }
+
+    /// Matches an identifier, returning its name.
+    fn match_ident(&mut self) -> GenResult<String> {
+        let name = self.curtoken.spelling.clone();
+        self.match_token(TokenType::Ident)?;
+        Ok(name)
+    }
+
+    fn abort<T>(&self, message: &str) -> GenResult<T> {
+        Err(Box::new(CompileError::new(
+            message,
+            self.curtoken.line,
+            self.curtoken.col,
+        )))
+    }
+
+    /// Fails at the end of the source if it was reached inside a block, with
+    /// an error at the keyword that opened the block.
+    fn check_terminated(&self, keyword: &str, start: Pos) -> GenResult<()> {
+        if !self.check_token(TokenType::Eof) {
+            return Ok(());
+        }
+
+        Err(Box::new(CompileError::new(
+            &format!(
+                "unterminated {} block started at line {}",
+                keyword, start.line
+            ),
+            start.line,
+            start.col,
+        )))
+    }
+
+    /// NL ::= "\n"+
+    fn parse_newline(&mut self) -> GenResult<()> {
+        if self.check_token(TokenType::RParen) {
+            return self.abort("unmatched ')' without an opening '('");
+        }
+        self.match_token(TokenType::Newline)?;
+        self.skip_blank_lines()
+    }
+
+    /// Skips any number of newlines. Lines holding only whitespace or a
+    /// comment lex as bare newlines, since the comment is set aside, so these
+    /// are skipped too.
+    fn skip_blank_lines(&mut self) -> GenResult<()> {
+        while self.check_token(TokenType::Newline) {
+            self.next_token()?;
+        }
+
+        Ok(())
+    }
+
+    /// primary ::= number | ident | call | "(" (expression | comparison) ")"
+    ///
+    /// A comparison in parentheses is a value like any other, 1 if it holds
+    /// and 0 if it doesn't.
+    fn parse_primary(&mut self) -> GenResult<Expr> {
+        let start = self.position();
+
+        if self.check_token(TokenType::LParen) {
+            self.next_token()?;
+            let mut expr = self.parse_expression_bp(0)?;
+
+            if !self.check_token(TokenType::RParen) {
+                return self.abort(&format!(
+                    "missing ')' to close the '(' at {}:{}",
+                    start.line, start.col
+                ));
+            }
+            self.next_token()?;
+
+            // the parentheses are part of the expression's source
+            let outer = self.span_from(start);
+            match &mut expr {
+                Expr::Number(_, span) | Expr::Str(_, span) | Expr::Var(_, span) => *span = outer,
+                Expr::Unary { span, .. } | Expr::Binary { span, .. } | Expr::Call { span, .. } => {
+                    *span = outer
+                }
+            }
+            Ok(expr)
+        } else if self.check_token(TokenType::Number) {
+            let value = self.curtoken.spelling.clone();
+            let (max, width) = if self.double {
+                (f64::MAX, "double")
+            } else {
+                (f32::MAX as f64, "float")
+            };
+            if number(&value) > max {
+                self.warnings.push(Warning::new(
+                    &format!(
+                        "float literal is too large for a {}, so it becomes infinity",
+                        width
+                    ),
+                    start.line,
+                    start.col,
+                ));
+            }
+            self.next_token()?;
+            Ok(Expr::Number(value, self.span_from(start)))
+        } else if self.check_token(TokenType::Ident) {
+            if self.peek_token()?.kind == TokenType::LParen {
+                return match Builtin::from_name(&self.curtoken.spelling) {
+                    Some(func) => self.parse_call(func, start),
+                    None => self.abort(&format!("unknown function: {:?}", self.curtoken.spelling)),
+                };
+            }
+
+            if !self.symbols.contains(&self.curtoken.spelling) {
+                // only report each undeclared variable once
+                self.symbols
+                    .declare(&self.curtoken.spelling, Symbol::new(Type::Float, start));
+                return self.abort(&format!(
+                    "Undeclared variable: {:?}",
+                    self.curtoken.spelling
+                ));
+            }
+
+            let name = self.match_ident()?;
+            Ok(Expr::Var(name, self.span_from(start)))
+        } else {
+            self.abort(&format!("Unexpected token: {:#}", self.curtoken))
+        }
+    }
+
+    /// call ::= ident "(" value { "," value } ")"
+    fn parse_call(&mut self, func: Builtin, start: Pos) -> GenResult<Expr> {
+        self.next_token()?;
+        self.match_token(TokenType::LParen)?;
+
+        let mut args = vec![self.parse_value()?.0];
+        while self.check_token(TokenType::Comma) {
+            self.next_token()?;
+            args.push(self.parse_value()?.0);
+        }
+
+        if !self.check_token(TokenType::RParen) {
+            return self.abort(&format!(
+                "missing ')' to close the call to {} at {}:{}",
+                func.name(),
+                start.line,
+                start.col
+            ));
+        }
+        self.next_token()?;
+
+        if args.len() != func.arity() {
+            return Err(Box::new(CompileError::new(
+                &format!(
+                    "{} takes {} argument{}, but got {}",
+                    func.name(),
+                    func.arity(),
+                    if func.arity() == 1 { "" } else { "s" },
+                    args.len()
+                ),
+                start.line,
+                start.col,
+            )));
+        }
+
+        Ok(Expr::Call {
+            func,
+            args,
+            span: self.span_from(start),
+        })
+    }
+
+    /// unary ::= ["+" | "-"] primary
+    fn parse_unary(&mut self) -> GenResult<Expr> {
+        let op = match self.curtoken.kind {
+            TokenType::Plus => UnaryOp::Plus,
+            TokenType::Minus => UnaryOp::Minus,
+            _ => return self.parse_primary(),
+        };
+        let pos = self.position();
+        self.next_token()?;
+        let expr = self.parse_primary()?;
+
+        Ok(Expr::Unary {
+            op,
+            pos,
+            span: Span::new(pos, expr.span().end),
+            expr: Box::new(expr),
+        })
+    }
+
+    /// Parses an expression whose binary operators all bind at least as
+    /// tightly as `min_bp`, by precedence climbing over `binding_power`.
+    ///
+    /// `AND`, `OR` and `NOT` only take conditions, i.e. comparisons or other
+    /// logical operations, and a comparison's operands are arithmetic.
+    fn parse_expression_bp(&mut self, min_bp: u8) -> GenResult<Expr> {
+        let depth = self.expression_depth;
+        self.nest_expression()?;
+
+        let mut lhs = if self.check_token(TokenType::Not) && min_bp <= NOT_BP {
+            let pos = self.position();
+            self.next_token()?;
+            let expr = self.parse_expression_bp(NOT_BP)?;
+            self.expect_condition(&expr)?;
+
+            Expr::Unary {
+                op: UnaryOp::Not,
+                pos,
+                span: Span::new(pos, expr.span().end),
+                expr: Box::new(expr),
+            }
+        } else {
+            self.parse_unary()?
+        };
+        // whether `lhs` is an operation built by the loop below, rather than
+        // one in parentheses, which is a value like any other
+        let mut built = false;
+
+        while let Some((left_bp, right_bp)) = binding_power(self.curtoken.kind) {
+            if left_bp < min_bp {
+                break;
+            }
+
+            let op = binary_op(self.curtoken.kind).unwrap();
+            // `a < b < c` would compare the result of `a < b` to `c`
+            if let Expr::Binary { op: prev, .. } = &lhs {
+                if built && op.is_comparison() && prev.is_comparison() {
+                    return self.abort("chained comparison; use AND");
+                }
+            }
+            let logical = matches!(op, BinaryOp::And | BinaryOp::Or);
+            if logical {
+                self.expect_condition(&lhs)?;
+            }
+
+            let pos = self.position();
+            self.next_token()?;
+            let rhs = self.parse_expression_bp(right_bp)?;
+            if logical {
+                self.expect_condition(&rhs)?;
+            }
+            // the loop carries on with the operation as its left operand, so
+            // a chain of left-associative operators is as deep as one of them
+            lhs = binary(op, pos, lhs, rhs);
+            built = true;
+        }
+
+        self.expression_depth = depth;
+        Ok(lhs)
+    }
+
+    fn nest_expression(&mut self) -> GenResult<()> {
+        self.expression_depth += 1;
+        self.check_nesting()
+    }
+
+    fn check_nesting(&mut self) -> GenResult<()> {
+        if self.statement_depth + self.expression_depth > self.max_nesting {
+            self.too_deep = true;
+            return self.abort(&format!(
+                "maximum nesting depth of {} exceeded",
+                self.max_nesting
+            ));
+        }
+        Ok(())
+    }
+
+    /// expression ::= an arithmetic expression, without comparisons or
+    /// logical operators
+    fn parse_expression(&mut self) -> GenResult<Expr> {
+        self.parse_expression_bp(ARITHMETIC_BP)
+    }
+
+    /// comparison ::= a condition, as taken by `IF`, `ELSEIF` and `WHILE`
+    fn parse_comparison(&mut self) -> GenResult<Expr> {
+        let expr = self.parse_expression_bp(0)?;
+        self.expect_condition(&expr)?;
+        Ok(expr)
+    }
+
+    /// Fails unless `expr`, just parsed, is a condition.
+    fn expect_condition(&self, expr: &Expr) -> GenResult<()> {
+        if expr.is_condition() {
+            return Ok(());
+        }
+        self.abort(&format!(
+            "Expected comparison operator, but got {}",
+            self.curtoken.kind
+        ))
+    }
+
+    /// Records an error and skips ahead to the start of the next line, so that
+    /// parsing can carry on and report any further errors, unless there are
+    /// already `max_errors` of them. Exceeding the nesting limit ends the
+    /// parse there, as the lines after it are likely nested just as deeply.
+    fn recover(&mut self, err: Box<dyn std::error::Error>) -> GenResult<()> {
+        match err.downcast::<CompileError>() {
+            Ok(_) if self.errors.len() >= self.max_errors => {
+                return Err(Box::new(TooManyErrors(self.errors.clone())));
+            }
+            Ok(err) => self.errors.push(*err),
+            Err(err) => return Err(err),
+        }
+        if self.too_deep {
+            return Err(Box::new(CompileErrors(self.errors.clone())));
+        }
+
+        while !self.check_token(TokenType::Newline) && !self.check_token(TokenType::Eof) {
+            if self.next_token().is_err() {
+                // the rest of the line is already known to be bad
+                self.lexer.skip_line();
+            }
+        }
+        if self.check_token(TokenType::Newline) {
+            self.parse_newline()?;
+        }
+
+        Ok(())
+    }
+
+    /// Parses the condition line that opens a block. If it is malformed, the
+    /// error is recorded and the block is still parsed, so that its closing
+    /// keyword doesn't turn up as a second, spurious error.
+    ///
+    /// With `allow_inline`, the terminator may be followed by a statement on
+    /// the same line instead, as in an inline `IF`, which is returned as
+    /// `true` along with the condition.
+    fn parse_block_header(
+        &mut self,
+        terminator: TokenType,
+        allow_inline: bool,
+    ) -> GenResult<(Expr, bool)> {
+        let header = self.parse_comparison().and_then(|cond| {
+            self.match_token(terminator)?;
+            if allow_inline && !self.check_token(TokenType::Newline) {
+                return Ok((cond, true));
+            }
+            self.parse_newline()?;
+            Ok((cond, false))
+        });
+
+        match header {
+            Ok(header) => Ok(header),
+            Err(err) => {
+                self.recover(err)?;
+                // never emitted, since the program as a whole fails to parse
+                Ok((Expr::Number("0".to_string(), Span::default()), false))
+            }
+        }
+    }
+
+    /// { statement }, the body of an `IF`, `ELSE` or loop, up to (but not
+    /// including) any of the given terminators. The names declared in it go
+    /// out of scope at the end.
+    fn parse_body(&mut self, terminators: &[TokenType]) -> GenResult<Vec<Stmt>> {
+        self.symbols.push_scope();
+        let body = self.parse_block(terminators);
+        self.symbols.pop_scope();
+        body
+    }
+
+    /// { statement }, up to (but not including) any of the given terminators
+    fn parse_block(&mut self, terminators: &[TokenType]) -> GenResult<Vec<Stmt>> {
+        let mut stmts = Vec::new();
+
+        while !terminators.contains(&self.curtoken.kind) && !self.check_token(TokenType::Eof) {
+            let doc = self.take_doc(stmts.last());
+            self.take_comments(&mut stmts);
+            // a statement that fails leaves the depths where it got to
+            let depth = self.statement_depth;
+            let stmt = self.parse_statement();
+            self.statement_depth = depth;
+            let mut chained = std::mem::take(&mut self.chained);
+            match stmt {
+                Ok(mut stmt) => {
+                    if let Stmt::Let { doc: slot, .. } | Stmt::Input { doc: slot, .. } = &mut stmt {
+                        *slot = doc;
+                    }
+                    self.stats.statements += 1 + chained.len();
+                    stmts.push(stmt);
+                    stmts.append(&mut chained);
+                }
+                Err(err) => self.recover(err)?,
+            }
+        }
+        self.take_comments(&mut stmts);
+
+        Ok(stmts)
+    }
+
+    /// { statement } "ENDWHILE", the rest of a `WHILE` loop after its
+    /// header
+    fn parse_while_body(&mut self, start: Pos, cond: Expr) -> GenResult<Stmt> {
+        self.loop_depth += 1;
+        let body = self.parse_body(&[TokenType::Endwhile]);
+        self.loop_depth -= 1;
+        let body = body?;
+        self.check_terminated("WHILE", start)?;
+        self.match_token(TokenType::Endwhile)?;
+
+        Ok(Stmt::While {
+            cond,
+            body,
+            span: self.span_from(start),
+        })
+    }
+
+    /// { statement } "WHILE" comparison, the rest of a `DO` loop. A `WHILE`
+    /// in the body is told apart from the closing one by its `REPEAT`.
+    fn parse_do_body(&mut self, start: Pos) -> GenResult<(Vec<Stmt>, Expr)> {
+        self.symbols.push_scope();
+        let body_and_cond = self.parse_do_statements(start);
+        self.symbols.pop_scope();
+        let (body, cond) = body_and_cond?;
+
+        // the condition comes after the body, so it is out of the body's
+        // scope, but isn't known to be the condition until it's been parsed
+        let out_of_scope = |name: &str| !self.symbols.contains(name);
+        if let Some((name, pos)) = find_variable(&cond, &out_of_scope) {
+            return Err(Box::new(CompileError::new(
+                &format!("{:?} is out of scope in the DO condition", name),
+                pos.line,
+                pos.col,
+            )));
+        }
+
+        Ok((body, cond))
+    }
+
+    fn parse_do_statements(&mut self, start: Pos) -> GenResult<(Vec<Stmt>, Expr)> {
+        let mut body = Vec::new();
+
+        loop {
+            body.extend(self.parse_block(&[TokenType::While])?);
+            self.check_terminated("DO", start)?;
+
+            let while_start = self.position();
+            self.match_token(TokenType::While)?;
+            let cond = self.parse_comparison()?;
+            if !self.check_token(TokenType::Repeat) {
+                return Ok((body, cond));
+            }
+
+            self.match_token(TokenType::Repeat)?;
+            self.parse_newline()?;
+            body.push(self.parse_while_body(while_start, cond)?);
+            self.parse_newline()?;
+        }
+    }
+
+    /// ("+=" | "-=" | "*=" | "/=") expression, the rest of a compound
+    /// assignment to `name`, found at `name_pos`
+    fn parse_compound_assign(
+        &mut self,
+        start: Pos,
+        name: String,
+        name_pos: Pos,
+    ) -> GenResult<Stmt> {
+        if !self.symbols.contains(&name) {
+            return Err(Box::new(CompileError::new(
+                &format!("assignment to undeclared variable: {:?}", name),
+                name_pos.line,
+                name_pos.col,
+            )));
+        }
+
+        let pos = self.position();
+        let op = compound_op(self.curtoken.kind).unwrap();
+        self.next_token()?;
+        let value = self.parse_expression()?;
+
+        Ok(Stmt::CompoundAssign {
+            name,
+            op,
+            pos,
+            value,
+            span: self.span_from(start),
+        })
+    }
+
+    /// "=" value, the rest of the declaration of `name`, found at `name_pos`,
+    /// in a `LET`
+    fn parse_let(&mut self, start: Pos, name: String, name_pos: Pos) -> GenResult<Stmt> {
+        let pos = self.position();
+        let value = self
+            .match_token(TokenType::Eq)
+            .and_then(|_| self.parse_value());
+
+        // declared only once the initializer has been parsed, so that it
+        // cannot refer to the variable it initializes, but even if that fails,
+        // so that later uses don't add follow-on errors
+        let ty = match value {
+            Ok((_, ty)) => ty,
+            Err(_) => Type::Float,
+        };
+        if !self.symbols.contains(&name) {
+            self.stats.variables += 1;
+        }
+        let declared = self
+            .symbols
+            .get_or_declare(&name, Symbol::new(ty, name_pos))
+            .ty;
+
+        let (value, ty) = value?;
+        self.check_assignable(&name, declared, ty, pos)?;
+
+        Ok(Stmt::Let {
+            name,
+            value,
+            doc: None,
+            span: self.span_from(start),
+        })
+    }
+
+    /// value ::= string | expression | comparison
+    ///
+    /// Returns the value along with its type as a `LET` would declare it:
+    /// `Str` for a string literal or a string variable on its own, `Int` for
+    /// a comparison, whose value is 0 or 1, and `Float` for any other value.
+    /// Strings used within expressions are left to the type checker.
+    fn parse_value(&mut self) -> GenResult<(Expr, Type)> {
+        if self.check_token(TokenType::String) {
+            let start = self.position();
+            let value = self.curtoken.spelling.clone();
+            self.match_token(TokenType::String)?;
+            let value = Expr::Str(value, self.span_from(start));
+            return Ok((value, Type::Str));
+        }
+
+        let expr = self.parse_expression_bp(0)?;
+        let ty = match &expr {
+            Expr::Var(name, _) if self.symbols.type_of(name) == Some(Type::Str) => Type::Str,
+            Expr::Call { func, .. } if func.result_type() == Type::Str => Type::Str,
+            expr if expr.is_condition() => Type::Int,
+            _ => Type::Float,
+        };
+
+        Ok((expr, ty))
+    }
+
+    /// Checks that a value of the given type may be stored in a variable
+    /// declared with another, i.e. that neither or both are strings.
+    fn check_assignable(&self, name: &str, declared: Type, value: Type, pos: Pos) -> GenResult<()> {
+        let message = match (declared, value) {
+            (Type::Str, Type::Float | Type::Int) => {
+                format!(
+                    "type error: cannot assign a number to string variable {:?}",
+                    name
+                )
+            }
+            (Type::Float | Type::Int, Type::Str) => {
+                format!(
+                    "type error: cannot assign a string to numeric variable {:?}",
+                    name
+                )
+            }
+            _ => return Ok(()),
+        };
+
+        Err(Box::new(CompileError::new(&message, pos.line, pos.col)))
+    }
+
+    fn position(&self) -> Pos {
+        Pos {
+            line: self.curtoken.line,
+            col: self.curtoken.col,
+        }
+    }
+
+    /// The span from `start` to the end of the last token matched.
+    fn span_from(&self, start: Pos) -> Span {
+        Span::new(start, self.prev_end)
+    }
+
+    /// items ::= value { "," value }
+    fn parse_print_items(&mut self) -> GenResult<Vec<Expr>> {
+        let mut items = Vec::new();
+
+        loop {
+            items.push(self.parse_value()?.0);
+
+            if !self.check_token(TokenType::Comma) {
+                break;
+            }
+            self.match_token(TokenType::Comma)?;
+        }
+
+        Ok(items)
+    }
+
+    /// statement ::= "PRINT" items NL
+    ///             | "WRITE" items NL
+    ///             | "ASSERT" comparison NL
+    ///             | "IF" comparison "THEN" NL { statement }
+    ///               { "ELSEIF" comparison "THEN" NL { statement } }
+    ///               [ "ELSE" NL { statement } ] "ENDIF" NL
+    ///             | "IF" comparison "THEN" statement
+    ///             | "WHILE" comparison "REPEAT" NL { statement } "ENDWHILE" NL
+    ///             | "DO" NL { statement } "WHILE" comparison NL
+    ///             | "LABEL" ident NL
+    ///             | "GOTO" ident NL
+    ///             | "EMIT" string NL
+    ///             | "BREAK" NL
+    ///             | "CONTINUE" NL
+    ///             | "LET" ident "=" value { "," ident "=" value } NL
+    ///             | "INT" ident "=" expression NL
+    ///             | "CONST" ident "=" expression NL
+    ///             | ident "=" value NL
+    ///             | "INPUT" [string ","] ident NL
+    fn parse_statement(&mut self) -> GenResult<Stmt> {
+        self.skip_blank_lines()?;
+        let start = self.position();
+
+        self.expression_depth = 0;
+        self.statement_depth += 1;
+        self.check_nesting()?;
+
+        // each kind of block is parsed by a function of its own, and every
+        // other statement by one more, so that the frames each level of
+        // nesting adds to the stack stay small
+        let stmt = match self.curtoken.kind {
+            TokenType::If => return self.parse_if(start),
+
+            TokenType::While => self.parse_while(start)?,
+            TokenType::Do => self.parse_do(start)?,
+            _ => self.parse_simple_statement(start)?,
+        };
+
+        self.parse_newline()?;
+        self.statement_depth -= 1;
+        Ok(stmt)
+    }
+
+    /// The rest of an `IF` after its keyword, up to and including the newline
+    /// that ends it. For an `IF` on one line, that's the newline of the
+    /// statement it runs.
+    fn parse_if(&mut self, start: Pos) -> GenResult<Stmt> {
+        self.match_token(TokenType::If)?;
+        let mut branches = Vec::new();
+
+        loop {
+            let (cond, inline) = self.parse_block_header(TokenType::Then, branches.is_empty())?;
+            if inline {
+                // the statement ends the line, so the newline is
+                // already taken care of
+                self.symbols.push_scope();
+                let body = self.parse_statement();
+                self.symbols.pop_scope();
+                let mut body = vec![body?];
+                body.append(&mut self.chained);
+                self.stats.statements += body.len();
+                return Ok(Stmt::If {
+                    span: Span::new(start, body.last().unwrap().span().end),
+                    branches: vec![(cond, body)],
+                    else_body: None,
+                });
+            }
+
+            let body = self.parse_body(&[TokenType::Elseif, TokenType::Else, TokenType::Endif])?;
+            branches.push((cond, body));
+
+            if !self.check_token(TokenType::Elseif) {
+                break;
+            }
+            self.match_token(TokenType::Elseif)?;
+        }
+
+        let mut else_body = None;
+        if self.check_token(TokenType::Else) {
+            self.match_token(TokenType::Else)?;
+            self.parse_newline()?;
+            else_body = Some(self.parse_body(&[TokenType::Elseif, TokenType::Endif])?);
+
+            if self.check_token(TokenType::Elseif) {
+                return self.abort("ELSEIF cannot follow ELSE");
+            }
+        }
+        self.check_terminated("IF", start)?;
+        self.match_token(TokenType::Endif)?;
+
+        let stmt = Stmt::If {
+            branches,
+            else_body,
+            span: self.span_from(start),
+        };
+        self.parse_newline()?;
+        Ok(stmt)
+    }
+
+    /// A `WHILE` loop, up to the newline that ends it.
+    fn parse_while(&mut self, start: Pos) -> GenResult<Stmt> {
+        self.match_token(TokenType::While)?;
+        let (cond, _) = self.parse_block_header(TokenType::Repeat, false)?;
+        self.parse_while_body(start, cond)
+    }
+
+    /// A `DO` loop, up to the newline that ends it.
+    fn parse_do(&mut self, start: Pos) -> GenResult<Stmt> {
+        self.match_token(TokenType::Do)?;
+        self.parse_newline()?;
+
+        self.loop_depth += 1;
+        let body_and_cond = self.parse_do_body(start);
+        self.loop_depth -= 1;
+        let (body, cond) = body_and_cond?;
+
+        Ok(Stmt::DoWhile {
+            body,
+            cond,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Any statement that doesn't contain others, up to the newline that
+    /// ends it.
+    fn parse_simple_statement(&mut self, start: Pos) -> GenResult<Stmt> {
+        let stmt = match self.curtoken.kind {
+            TokenType::Print => {
+                self.match_token(TokenType::Print)?;
+                let items = self.parse_print_items()?;
+                Stmt::Print(items, self.span_from(start))
+            }
+
+            TokenType::Write => {
+                self.match_token(TokenType::Write)?;
+                let items = self.parse_print_items()?;
+                Stmt::Write(items, self.span_from(start))
+            }
+
+            TokenType::Assert => {
+                self.match_token(TokenType::Assert)?;
+                let cond = self.parse_comparison()?;
+                Stmt::Assert(cond, self.span_from(start))
+            }
+
+            TokenType::Return => {
+                self.match_token(TokenType::Return)?;
+                let value = self.parse_expression()?;
+                Stmt::Return(value, self.span_from(start))
+            }
+
+            TokenType::Exit => {
+                self.match_token(TokenType::Exit)?;
+                let value = if self.check_token(TokenType::Newline) {
+                    None
+                } else {
+                    Some(self.parse_expression()?)
+                };
+                Stmt::Exit(value, self.span_from(start))
+            }
+
+            TokenType::Label => {
+                self.match_token(TokenType::Label)?;
+
+                if self.declared_labels.contains_key(&self.curtoken.spelling) {
+                    return self.abort(&format!("Duplicate label: {:?}", &self.curtoken.spelling));
+                }
+                let name = self.match_ident()?;
+                self.declared_labels.insert(name.clone(), start);
+                self.stats.labels += 1;
+
+                Stmt::Label(name, self.span_from(start))
+            }
+
+            TokenType::Goto => {
+                let pos = self.position();
+                self.match_token(TokenType::Goto)?;
+                let name = self.match_ident()?;
+                self.gotoed_labels
+                    .entry(name.clone())
+                    .or_default()
+                    .push((pos.line, pos.col));
+                self.stats.gotos += 1;
+
+                Stmt::Goto(name, self.span_from(start))
+            }
+
+            TokenType::Emit => {
+                if !self.allow_raw {
+                    return self.abort("EMIT needs raw C to be allowed, with --allow-raw");
+                }
+                self.match_token(TokenType::Emit)?;
+                let code = self.curtoken.spelling.clone();
+                self.match_token(TokenType::String)?;
+
+                Stmt::Emit(code, self.span_from(start))
+            }
+
+            TokenType::Swap => {
+                self.match_token(TokenType::Swap)?;
+                let (first, first_type) = self.match_swapped()?;
+                self.match_token(TokenType::Comma)?;
+                let (second, second_type) = self.match_swapped()?;
+
+                if first_type != second_type {
+                    return Err(Box::new(CompileError::new(
+                        &format!(
+                            "type error: cannot SWAP {} variable {:?} with {} variable {:?}",
+                            first_type, first, second_type, second
+                        ),
+                        start.line,
+                        start.col,
+                    )));
+                }
+
+                Stmt::Swap(first, second, self.span_from(start))
+            }
+
+            TokenType::Break | TokenType::Continue => {
+                let keyword = self.curtoken.kind;
+                if self.loop_depth == 0 {
+                    let name = if keyword == TokenType::Break {
+                        "break"
+                    } else {
+                        "continue"
+                    };
+                    return self.abort(&format!("{} outside of loop", name));
+                }
+                self.next_token()?;
+
+                match keyword {
+                    TokenType::Break => Stmt::Break(self.span_from(start)),
+                    _ => Stmt::Continue(self.span_from(start)),
+                }
+            }
+
+            TokenType::Let => {
+                self.match_token(TokenType::Let)?;
+                let name_pos = self.position();
+                let name = self.match_ident()?;
+                self.check_not_constant(&name, name_pos)?;
+                if compound_op(self.curtoken.kind).is_some() {
+                    return self.parse_compound_assign(start, name, name_pos);
+                }
+                let stmt = self.parse_let(start, name, name_pos)?;
+
+                while self.check_token(TokenType::Comma) {
+                    self.next_token()?;
+                    let name_pos = self.position();
+                    let name = self.match_ident()?;
+                    self.check_not_constant(&name, name_pos)?;
+                    let stmt = self.parse_let(name_pos, name, name_pos)?;
+                    self.chained.push(stmt);
+                }
+
+                stmt
+            }
+
+            TokenType::Int => {
+                self.match_token(TokenType::Int)?;
+
+                if self.symbols.contains(&self.curtoken.spelling) {
+                    return self.abort(&format!(
+                        "Variable already declared: {:?}",
+                        self.curtoken.spelling
+                    ));
+                }
+                let name_pos = self.position();
+                let name = self.match_ident()?;
+                let pos = self.position();
+                let value = self
+                    .match_token(TokenType::Eq)
+                    .and_then(|_| self.parse_value());
+                self.symbols
+                    .declare(&name, Symbol::new(Type::Int, name_pos));
+                self.stats.variables += 1;
+
+                let (value, ty) = value?;
+                self.check_assignable(&name, Type::Int, ty, pos)?;
+
+                Stmt::Int {
+                    name,
+                    value,
+                    span: self.span_from(start),
+                }
+            }
+
+            TokenType::Const => {
+                self.match_token(TokenType::Const)?;
+
+                if self.symbols.contains(&self.curtoken.spelling) {
+                    return self.abort(&format!(
+                        "Variable already declared: {:?}",
+                        self.curtoken.spelling
+                    ));
+                }
+                let name_pos = self.position();
+                let name = self.match_ident()?;
+                self.match_token(TokenType::Eq)?;
+                let pos = self.position();
+                let value = self.parse_expression();
+                self.symbols.declare(
+                    &name,
+                    Symbol {
+                        constant: true,
+                        ..Symbol::new(Type::Float, name_pos)
+                    },
+                );
+                self.stats.variables += 1;
+
+                // constants are declared ahead of all other code in their
+                // scope, where no variable has been assigned yet
+                let value = value?;
+                let is_variable =
+                    |name: &str| !self.symbols.get(name).is_some_and(|symbol| symbol.constant);
+                if let Some((var, _)) = find_variable(&value, &is_variable) {
+                    return Err(Box::new(CompileError::new(
+                        &format!("CONST value cannot use variable {:?}", var),
+                        pos.line,
+                        pos.col,
+                    )));
+                }
+
+                Stmt::Const {
+                    name,
+                    value,
+                    span: self.span_from(start),
+                }
+            }
+
+            TokenType::Ident => {
+                let next = self.peek_token()?.kind;
+                if next != TokenType::Eq && compound_op(next).is_none() {
+                    return self.abort(&format!("Invalid statement at {}", self.curtoken.kind));
+                }
+                if !self.symbols.contains(&self.curtoken.spelling) {
+                    return self.abort(&format!(
+                        "assignment to undeclared variable: {:?}",
+                        self.curtoken.spelling
+                    ));
+                }
+                let name_pos = self.position();
+                let name = self.match_ident()?;
+                self.check_not_constant(&name, name_pos)?;
+                if compound_op(next).is_some() {
+                    return self.parse_compound_assign(start, name, name_pos);
+                }
+                let pos = self.position();
+                self.match_token(TokenType::Eq)?;
+
+                let (value, ty) = self.parse_value()?;
+                let declared = self.symbols.type_of(&name).unwrap();
+                self.check_assignable(&name, declared, ty, pos)?;
+
+                Stmt::Assign {
+                    name,
+                    value,
+                    span: self.span_from(start),
+                }
+            }
+
+            TokenType::Input => {
+                self.match_token(TokenType::Input)?;
+
+                let mut prompt = None;
+                if self.check_token(TokenType::String) {
+                    prompt = Some(self.curtoken.spelling.clone());
+                    self.match_token(TokenType::String)?;
+                    self.match_token(TokenType::Comma)?;
+                }
+
+                // INPUT reads into a variable of the type it already has, or
+                // declares a float
+                let pos = self.position();
+                let name = self.match_ident()?;
+                self.check_not_constant(&name, pos)?;
+                if self.symbols.type_of(&name) == Some(Type::Str) {
+                    return Err(Box::new(CompileError::new(
+                        &format!("type error: cannot INPUT into string variable {:?}", name),
+                        pos.line,
+                        pos.col,
+                    )));
+                }
+                if !self.symbols.contains(&name) {
+                    self.stats.variables += 1;
+                }
+                self.symbols
+                    .get_or_declare(&name, Symbol::new(Type::Float, pos));
+
+                Stmt::Input {
+                    prompt,
+                    name,
+                    doc: None,
+                    span: self.span_from(start),
+                }
+            }
+
+            TokenType::Else => return self.abort("ELSE without a matching IF"),
+            TokenType::Elseif => return self.abort("ELSEIF without a matching IF"),
+
+            _ => return self.abort(&format!("Invalid statement at {}", self.curtoken.kind)),
+        };
+
+        Ok(stmt)
+    }
+
+    /// program ::= { statement }
+    fn parse_program(&mut self) -> GenResult<Vec<Stmt>> {
+        self.parse_block(&[TokenType::Eof])
+    }
+
+    /// The variables and constants declared outside of any block, with their
+    /// types and where they were declared. Only complete once `parse` has
+    /// succeeded.
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// The warnings found in the program, in source order. Only complete once
+    /// `parse` has succeeded.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// How many statements, variables, labels and `GOTO`s the program has.
+    /// Only complete once `parse` has succeeded.
+    pub fn stats(&self) -> CompileStats {
+        self.stats
+    }
+
+    pub fn parse(&mut self) -> GenResult<Vec<Stmt>> {
+        self.skip_blank_lines()?;
+        let program = self.parse_program()?;
+
+        let mut undefined = Vec::new();
+        for (label, positions) in &self.gotoed_labels {
+            if !self.declared_labels.contains_key(label) {
+                undefined.extend(positions.iter().map(|&position| (position, label)));
+            }
+        }
+        undefined.sort();
+        for ((line, col), label) in undefined {
+            self.errors.push(CompileError::new(
+                &format!("Goto's label is undefined: {:?}", label),
+                line,
+                col,
+            ));
+        }
+
+        if self.errors.len() > self.max_errors {
+            self.errors.truncate(self.max_errors);
+            return Err(Box::new(TooManyErrors(self.errors.clone())));
+        }
+        if !self.errors.is_empty() {
+            return Err(Box::new(CompileErrors(self.errors.clone())));
+        }
+
+        let mut unused = self
+            .declared_labels
+            .iter()
+            .filter(|(label, _)| !self.gotoed_labels.contains_key(*label))
+            .map(|(label, pos)| ((pos.line, pos.col), label))
+            .collect::<Vec<_>>();
+        unused.sort();
+        let unused = unused
+            .into_iter()
+            .map(|((line, col), label)| {
+                Warning::new(&format!("label {:?} is never used", label), line, col)
+            })
+            .collect::<Vec<_>>();
+        self.warnings.extend(unused);
+        self.warnings
+            .sort_by_key(|warning| (warning.line, warning.col));
+
+        Ok(program)
+    }
 }
 
-impl<'a> Parser<'a> {
-    pub fn new(mut lexer: Lexer, emitter: &'a mut Emitter) -> Self {
-        let curtoken = lexer.get_token();
+#[cfg(test)]
+mod test {
+    use crate::ast::{clear_positions, BinaryOp, Builtin, Expr, Pos, Span, Stmt, Type, UnaryOp};
+    use crate::error::{CompileErrors, TooManyErrors, Warning};
+    use crate::lexer::Lexer;
+    use crate::parser::{CompileStats, Parser, DEFAULT_MAX_ERRORS, DEFAULT_MAX_NESTING};
+    use crate::typecheck::typecheck;
+    use crate::GenResult;
+
+    fn read_source(infile: &str) -> String {
+        use std::fs::File;
+        use std::io::{BufReader, Read};
+
+        let mut reader = BufReader::new(File::open(infile).unwrap());
+        let mut buffer = String::new();
+        reader.read_to_string(&mut buffer).unwrap();
+        buffer
+    }
+
+    fn parse_with_positions(source: &str) -> GenResult<Vec<Stmt>> {
+        Parser::new(Lexer::new(source))?.parse()
+    }
+
+    /// The parsed program, without positions, so that it compares equal to
+    /// one built with default spans.
+    fn parse(source: &str) -> GenResult<Vec<Stmt>> {
+        let mut program = parse_with_positions(source)?;
+        clear_positions(&mut program);
+        Ok(program)
+    }
+
+    fn num(value: &str) -> Expr {
+        Expr::Number(value.to_string(), Span::default())
+    }
+
+    fn var(name: &str) -> Expr {
+        Expr::Var(name.to_string(), Span::default())
+    }
+
+    fn bin(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::Binary {
+            op,
+            pos: Pos::default(),
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+            span: Span::default(),
+        }
+    }
+
+    #[test]
+    fn test_parse_label_loop() {
+        let input = "LABEL loop\nPRINT \"hello, world\"\nGOTO loop";
+        assert_eq!(
+            parse(input).unwrap(),
+            vec![
+                Stmt::Label("loop".to_string(), Span::default()),
+                Stmt::Print(
+                    vec![Expr::Str("hello, world".to_string(), Span::default())],
+                    Span::default()
+                ),
+                Stmt::Goto("loop".to_string(), Span::default()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_unused_labels() {
+        let mut parser = Parser::new(Lexer::new(
+            "LABEL b\nLABEL used\nIF 1 > 0 THEN\n    LABEL a\nENDIF\nGOTO used\n",
+        ))
+        .unwrap();
+        parser.parse().unwrap();
+        assert_eq!(
+            parser.warnings(),
+            [
+                Warning::new("label \"b\" is never used", 1, 1),
+                Warning::new("label \"a\" is never used", 4, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_large_float_literal() {
+        let mut parser =
+            Parser::new(Lexer::new("LET x = 1e39\nLABEL a\nLET y = 3.4e38\n")).unwrap();
+        parser.parse().unwrap();
+        assert_eq!(
+            parser.warnings(),
+            [
+                Warning::new(
+                    "float literal is too large for a float, so it becomes infinity",
+                    1,
+                    9
+                ),
+                Warning::new("label \"a\" is never used", 2, 1),
+            ]
+        );
+
+        let mut parser = Parser::new(Lexer::new("LET x = 1e39\nLET y = 1e309\n")).unwrap();
+        parser.double = true;
+        parser.parse().unwrap();
+        assert_eq!(
+            parser.warnings(),
+            [Warning::new(
+                "float literal is too large for a double, so it becomes infinity",
+                2,
+                9
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_ast_shape() {
+        let input = "INPUT n\nLET x = -n + 2 * (n - 1)\nWHILE x > 0 AND NOT x == 5 REPEAT\nLET x = x - 1\nENDWHILE";
+        assert_eq!(
+            parse(input).unwrap(),
+            vec![
+                Stmt::Input {
+                    prompt: None,
+                    name: "n".to_string(),
+                    doc: None,
+                    span: Span::default(),
+                },
+                Stmt::Let {
+                    name: "x".to_string(),
+                    value: bin(
+                        BinaryOp::Add,
+                        Expr::Unary {
+                            op: UnaryOp::Minus,
+                            pos: Pos::default(),
+                            expr: Box::new(var("n")),
+                            span: Span::default(),
+                        },
+                        bin(
+                            BinaryOp::Mul,
+                            num("2"),
+                            bin(BinaryOp::Sub, var("n"), num("1"))
+                        )
+                    ),
+                    doc: None,
+                    span: Span::default(),
+                },
+                Stmt::While {
+                    cond: bin(
+                        BinaryOp::And,
+                        bin(BinaryOp::Gt, var("x"), num("0")),
+                        Expr::Unary {
+                            op: UnaryOp::Not,
+                            pos: Pos::default(),
+                            expr: Box::new(bin(BinaryOp::Eq, var("x"), num("5"))),
+                            span: Span::default(),
+                        }
+                    ),
+                    body: vec![Stmt::Let {
+                        name: "x".to_string(),
+                        value: bin(BinaryOp::Sub, var("x"), num("1")),
+                        doc: None,
+                        span: Span::default(),
+                    }],
+                    span: Span::default(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_from_source() {
+        let program = Parser::from_source("LET a = 1\nPRINT a\n")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(
+            program,
+            parse_with_positions("LET a = 1\nPRINT a\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_stats() {
+        let stats = |source: &str| {
+            let mut parser = Parser::from_source(source).unwrap();
+            parser.parse().unwrap();
+            parser.stats()
+        };
+
+        assert_eq!(
+            stats(&read_source("samples/fib.teeny")),
+            CompileStats {
+                statements: 11,
+                variables: 4,
+                labels: 0,
+                gotos: 0,
+            }
+        );
+        assert_eq!(
+            stats("LABEL top\nLET a = 1, b = 2\nIF a < b THEN LET a = b, c = 3\nGOTO top\n"),
+            CompileStats {
+                statements: 7,
+                variables: 3,
+                labels: 1,
+                gotos: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_emit() {
+        let source = "EMIT \"puts(\\\"hi\\\");\"\n";
+        assert_eq!(
+            errors(source),
+            vec![(
+                1,
+                "EMIT needs raw C to be allowed, with --allow-raw".to_string()
+            )]
+        );
+
+        let mut parser = Parser::from_source(source).unwrap();
+        parser.allow_raw = true;
+        let mut program = parser.parse().unwrap();
+        clear_positions(&mut program);
+        assert_eq!(
+            program,
+            vec![Stmt::Emit("puts(\"hi\");".to_string(), Span::default())]
+        );
+
+        let mut parser = Parser::from_source("EMIT x\n").unwrap();
+        parser.allow_raw = true;
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_let() {
+        assert!(parse("LET foo = bar * 3 + 2").is_err());
+    }
+
+    #[test]
+    fn test_parse_let_if() {
+        let input = "LET foo = bar * 3 + 2\nIF foo > 0 THEN\nPRINT \"yes!\"\nENDIF\n";
+        assert!(parse(input).is_err());
+    }
+
+    #[test]
+    fn test_parse_nested_if() {
+        let input = "LET foo = bar * 3 + 2\nIF foo > 0 THEN\nIF 10 * 10 < 100 THEN\nPRINT bar\nENDIF\nENDIF";
+        assert!(parse(input).is_err());
+    }
+
+    #[test]
+    fn test_parse_unterminated_blocks() {
+        for (input, messages) in [
+            (
+                "LET a = 1\nIF a > 0 THEN\nPRINT a\n",
+                vec![(2, "unterminated IF block started at line 2")],
+            ),
+            (
+                "LET a = 1\nIF a > 0 THEN\nPRINT a\nELSEIF a < 0 THEN\nPRINT 0\nELSE\n",
+                vec![(2, "unterminated IF block started at line 2")],
+            ),
+            (
+                "LET a = 1\n\n  WHILE a > 0 REPEAT\nPRINT a\n",
+                vec![(3, "unterminated WHILE block started at line 3")],
+            ),
+            (
+                "LET a = 1\nWHILE a > 0 REPEAT\nIF a > 1 THEN\nPRINT a\n",
+                vec![
+                    (3, "unterminated IF block started at line 3"),
+                    (2, "unterminated WHILE block started at line 2"),
+                ],
+            ),
+            // the source can also end partway through a line, or with
+            // nothing at all after the block's header
+            (
+                "IF 1 > 0 THEN\nELSE\nPRINT 1",
+                vec![(1, "unterminated IF block started at line 1")],
+            ),
+            (
+                "IF 1 > 0 THEN\nELSEIF 2 > 1 THEN\n",
+                vec![(1, "unterminated IF block started at line 1")],
+            ),
+            (
+                "DO\nPRINT 1",
+                vec![(1, "unterminated DO block started at line 1")],
+            ),
+            (
+                "DO\nWHILE 1 > 0 REPEAT\n",
+                vec![(2, "unterminated WHILE block started at line 2")],
+            ),
+            (
+                "WHILE 1 > 0 REPEAT",
+                vec![(1, "unterminated WHILE block started at line 1")],
+            ),
+            (
+                "IF 1 > 0 THEN IF 2 > 1 THEN",
+                vec![(1, "unterminated IF block started at line 1")],
+            ),
+        ] {
+            let messages = messages
+                .into_iter()
+                .map(|(line, message)| (line, message.to_string()))
+                .collect::<Vec<_>>();
+            assert_eq!(errors(input), messages, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_assert() {
+        assert_eq!(
+            parse("LET a = 1\nASSERT a > 0\n").unwrap()[1],
+            Stmt::Assert(bin(BinaryOp::Gt, var("a"), num("0")), Span::default())
+        );
+        assert!(parse("LET a = 1\nASSERT a\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_chained_comparison() {
+        for input in [
+            "LET a = 1\nIF 0 < a < 2 THEN\nPRINT a\nENDIF\n",
+            "LET a = 1\nWHILE a == a != 0 REPEAT\nENDWHILE\n",
+            "LET a = 1\nASSERT NOT 0 < a <= 2\n",
+        ] {
+            let errs = errors(input);
+            assert_eq!(errs.len(), 1, "{}", input);
+            assert_eq!(errs[0].0, 2);
+            assert!(
+                errs[0].1.contains("chained comparison; use AND"),
+                "{}",
+                input
+            );
+        }
+
+        let input = "LET a = 1\nIF 0 < a AND a < 2 THEN\nPRINT a\nENDIF\n";
+        assert!(parse(input).is_ok());
 
-        Parser {
-            lexer: lexer,
-            emitter: emitter,
-            curtoken: curtoken,
-            symbols: HashSet::new(),
-            declared_labels: HashSet::new(),
-            gotoed_labels: HashSet::new(),
-        }
+        // parentheses make a comparison a value on either side
+        let program = parse("LET x = 1\nPRINT (x > 0) == 1, 1 == (x > 0)\n").unwrap();
+        assert_eq!(
+            program[1],
+            Stmt::Print(
+                vec![
+                    bin(
+                        BinaryOp::Eq,
+                        bin(BinaryOp::Gt, var("x"), num("0")),
+                        num("1")
+                    ),
+                    bin(
+                        BinaryOp::Eq,
+                        num("1"),
+                        bin(BinaryOp::Gt, var("x"), num("0"))
+                    ),
+                ],
+                Span::default()
+            )
+        );
+        assert!(parse("LET x = 1\nPRINT (x > 0) == 1 < 2\n").is_err());
     }
 
-    fn check_token(&self, kind: TokenType) -> bool {
-        self.curtoken.kind == kind
+    #[test]
+    fn test_parse_compound_assignment() {
+        let program = parse("LET x = 1\nx += 1\nx -= 2\nLET x *= 3\nLET x /= x + 1\n").unwrap();
+        let compound = |op, value| Stmt::CompoundAssign {
+            name: "x".to_string(),
+            op,
+            pos: Pos::default(),
+            value,
+            span: Span::default(),
+        };
+        assert_eq!(
+            program[1..],
+            [
+                compound(BinaryOp::Add, num("1")),
+                compound(BinaryOp::Sub, num("2")),
+                compound(BinaryOp::Mul, num("3")),
+                compound(BinaryOp::Div, bin(BinaryOp::Add, var("x"), num("1"))),
+            ]
+        );
+
+        for (input, message) in [
+            (
+                "y += 1\n",
+                "error at 1:1: assignment to undeclared variable: \"y\"",
+            ),
+            (
+                "LET y -= 1\n",
+                "error at 1:5: assignment to undeclared variable: \"y\"",
+            ),
+            (
+                "CONST C = 1\nC *= 2\n",
+                "error at 2:1: cannot assign to constant \"C\"",
+            ),
+        ] {
+            assert_eq!(parse(input).unwrap_err().to_string(), message);
+        }
     }
 
-    fn next_token(&mut self) {
-        self.curtoken = self.lexer.get_token();
+    #[test]
+    fn test_parse_swap() {
+        assert_eq!(
+            parse("LET a = 1\nLET b = 2\nSWAP a, b\n").unwrap()[2],
+            Stmt::Swap("a".to_string(), "b".to_string(), Span::default())
+        );
+
+        for (input, message) in [
+            ("LET a = 1\nSWAP a, b\n", "error at 2:9: Undeclared variable: \"b\""),
+            ("LET a = 1\nSWAP c, a\n", "error at 2:6: Undeclared variable: \"c\""),
+            (
+                "LET a = 1\nCONST B = 2\nSWAP a, B\n",
+                "error at 3:9: cannot assign to constant \"B\"",
+            ),
+            (
+                "LET a = 1\nLET s = \"s\"\nSWAP a, s\n",
+                "error at 3:9: type error: cannot SWAP string variable \"s\"",
+            ),
+            (
+                "LET a = 1\nINT i = 2\nSWAP a, i\n",
+                "error at 3:1: type error: cannot SWAP float variable \"a\" with int variable \"i\"",
+            ),
+        ] {
+            assert_eq!(parse(input).unwrap_err().to_string(), message);
+        }
     }
 
-    fn match_token(&mut self, kind: TokenType) {
-        if !self.check_token(kind) {
-            self.abort(&format!(
-                "expected token of kind {:?}, but found token of kind {:?}",
-                kind, self.curtoken.kind
-            ));
+    #[test]
+    fn test_parse_random() {
+        assert_eq!(
+            parse("LET a = 1\nLET b = RANDOM(a, 6) + 1\n").unwrap()[1],
+            Stmt::Let {
+                name: "b".to_string(),
+                value: bin(
+                    BinaryOp::Add,
+                    Expr::Call {
+                        func: Builtin::Random,
+                        args: vec![var("a"), num("6")],
+                        span: Span::default(),
+                    },
+                    num("1")
+                ),
+                doc: None,
+                span: Span::default(),
+            }
+        );
+
+        for (input, message) in [
+            (
+                "PRINT RANDOM(1)\n",
+                "error at 1:7: RANDOM takes 2 arguments, but got 1",
+            ),
+            (
+                "PRINT RANDOM(1, 2, 3)\n",
+                "error at 1:7: RANDOM takes 2 arguments, but got 3",
+            ),
+            (
+                "PRINT RANDOM(1, 2\n",
+                "error at 1:18: missing ')' to close the call to RANDOM at 1:7",
+            ),
+            (
+                "PRINT RANDOM\n",
+                "error at 1:7: Undeclared variable: \"RANDOM\"",
+            ),
+            (
+                "PRINT LEN(\"a\", \"b\")\n",
+                "error at 1:7: LEN takes 1 argument, but got 2",
+            ),
+            (
+                "PRINT ORD(\"a\", 1)\n",
+                "error at 1:7: ORD takes 1 argument, but got 2",
+            ),
+            (
+                "PRINT CHR(65, 66)\n",
+                "error at 1:7: CHR takes 1 argument, but got 2",
+            ),
+            (
+                "PRINT SQRT(1, 2)\n",
+                "error at 1:7: SQRT takes 1 argument, but got 2",
+            ),
+            ("PRINT FOO(1)\n", "error at 1:7: unknown function: \"FOO\""),
+        ] {
+            assert_eq!(parse(input).unwrap_err().to_string(), message);
         }
-        self.next_token();
     }
 
-    fn abort(&self, message: &str) {
-        panic!("Parser error: {}", message);
+    #[test]
+    fn test_parse_return() {
+        assert_eq!(
+            parse("LET a = 1\nRETURN a + 1\n").unwrap()[1],
+            Stmt::Return(bin(BinaryOp::Add, var("a"), num("1")), Span::default())
+        );
+        assert!(parse("RETURN\n").is_err());
     }
 
-    /// NL ::= "\n"+
-    fn parse_newline(&mut self) {
-        self.match_token(TokenType::Newline);
-        while self.check_token(TokenType::Newline) {
-            self.next_token();
-        }
+    #[test]
+    fn test_parse_nesting_limit() {
+        let exceeded = format!("maximum nesting depth of {} exceeded", DEFAULT_MAX_NESTING);
+
+        let dos = |n: usize| format!("{}BREAK\n{}", "DO\n".repeat(n), "WHILE 1 > 0\n".repeat(n));
+        assert!(parse(&dos(DEFAULT_MAX_NESTING - 4)).is_ok());
+        assert_eq!(
+            errors(&dos(DEFAULT_MAX_NESTING + 40)),
+            vec![(DEFAULT_MAX_NESTING + 1, exceeded.clone())]
+        );
+
+        let ifs = |n: usize| {
+            format!(
+                "{}PRINT 1\n{}",
+                "IF 1 > 0 THEN\n".repeat(n),
+                "ENDIF\n".repeat(n)
+            )
+        };
+        assert!(parse(&ifs(DEFAULT_MAX_NESTING - 4)).is_ok());
+        // the condition of the deepest `IF` nests inside it
+        assert_eq!(
+            errors(&ifs(DEFAULT_MAX_NESTING + 40)),
+            vec![(DEFAULT_MAX_NESTING - 1, exceeded.clone())]
+        );
+
+        let parens = |n: usize| format!("PRINT {}1{}\n", "(".repeat(n), ")".repeat(n));
+        assert!(parse(&parens(DEFAULT_MAX_NESTING - 4)).is_ok());
+        assert_eq!(errors(&parens(10_000)), vec![(1, exceeded.clone())]);
+        assert_eq!(
+            errors(&format!("PRINT 1{}\n", " ^ 1".repeat(10_000))),
+            vec![(1, exceeded)]
+        );
+        assert!(parse(&format!("PRINT 1{}\n", " + 1 * 2".repeat(10_000))).is_ok());
+
+        let mut parser = Parser::new(Lexer::new(&parens(10))).unwrap();
+        parser.max_nesting = 8;
+        let err = parser.parse().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "error at 1:14: maximum nesting depth of 8 exceeded"
+        );
     }
 
-    /// primary ::= number | ident
-    fn parse_primary(&mut self) {
-        if self.check_token(TokenType::Number) {
-            self.emitter.emit(&self.curtoken.spelling);
-            self.next_token();
-        } else if self.check_token(TokenType::Ident) {
-            if !self.symbols.contains(&self.curtoken.spelling) {
-                self.abort(&format!(
-                    "Undeclared variable: {:?}",
-                    self.curtoken.spelling
-                ));
+    #[test]
+    fn test_parse_comparison_values() {
+        let mut parser = Parser::new(Lexer::new(
+            "LET x = 1\nLET flag = x > 0 AND x < 2\nLET y = (x == 1) * 2\nPRINT NOT x > 0\n",
+        ))
+        .unwrap();
+        let mut program = parser.parse().unwrap();
+        clear_positions(&mut program);
+        assert_eq!(
+            program[1],
+            Stmt::Let {
+                name: "flag".to_string(),
+                value: bin(
+                    BinaryOp::And,
+                    bin(BinaryOp::Gt, var("x"), num("0")),
+                    bin(BinaryOp::Lt, var("x"), num("2"))
+                ),
+                doc: None,
+                span: Span::default(),
             }
+        );
+        assert_eq!(
+            program[2],
+            Stmt::Let {
+                name: "y".to_string(),
+                value: bin(
+                    BinaryOp::Mul,
+                    bin(BinaryOp::Eq, var("x"), num("1")),
+                    num("2")
+                ),
+                doc: None,
+                span: Span::default(),
+            }
+        );
+        assert_eq!(parser.symbols().type_of("flag"), Some(Type::Int));
+        assert_eq!(parser.symbols().type_of("y"), Some(Type::Float));
 
-            self.emitter.emit(&self.curtoken.spelling);
-            self.next_token();
-        } else {
-            self.abort(&format!("Unexpected token: {:?}", self.curtoken.spelling));
-        }
+        // comparison values compare like any others
+        let mut parser = Parser::new(Lexer::new(
+            "LET x = 1\nLET flag = x > 0\nLET same = (x > 0) == flag\nIF (x < 2) != (x > 0) THEN\nPRINT same\nENDIF\n",
+        ))
+        .unwrap();
+        let mut program = parser.parse().unwrap();
+        clear_positions(&mut program);
+        assert_eq!(
+            program[2],
+            Stmt::Let {
+                name: "same".to_string(),
+                value: bin(
+                    BinaryOp::Eq,
+                    bin(BinaryOp::Gt, var("x"), num("0")),
+                    var("flag")
+                ),
+                doc: None,
+                span: Span::default(),
+            }
+        );
+        assert_eq!(parser.symbols().type_of("same"), Some(Type::Int));
+        assert!(typecheck(&program).is_ok());
+
+        // without parentheses, a comparison is still not an operand
+        assert!(parse("LET x = 1\nLET y = x > 0 * 2 > 1\n").is_err());
     }
 
-    /// unary ::= ["+" | "-"] primary
-    fn parse_unary(&mut self) {
-        if self.check_token(TokenType::Plus) || self.check_token(TokenType::Minus) {
-            self.emitter.emit(&self.curtoken.spelling);
-            self.next_token();
+    #[test]
+    fn test_parse_exit() {
+        let program = parse("LET a = 1\nEXIT a + 1\nEXIT\n").unwrap();
+        assert_eq!(
+            program[1],
+            Stmt::Exit(
+                Some(bin(BinaryOp::Add, var("a"), num("1"))),
+                Span::default()
+            )
+        );
+        assert_eq!(program[2], Stmt::Exit(None, Span::default()));
+        assert!(parse("EXIT \"no\"\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_do_while() {
+        let input = "INT i = 0\nDO\n    WHILE i < 2 REPEAT\n        i = i + 1\n    ENDWHILE\n\n    BREAK\nWHILE i < 5\nPRINT i\n";
+        let program = parse(input).unwrap();
+        assert_eq!(program.len(), 3);
+        match &program[1] {
+            Stmt::DoWhile { body, cond, .. } => {
+                assert!(matches!(body[..], [Stmt::While { .. }, Stmt::Break(_)]));
+                assert_eq!(*cond, bin(BinaryOp::Lt, var("i"), num("5")));
+            }
+            stmt => panic!("unexpected statement: {:?}", stmt),
         }
-        self.parse_primary();
+        let program = parse_with_positions(input).unwrap();
+        assert_eq!(span_of(program[1].span()), (2, 1, 8, 12));
+
+        let err = parse("INT i = 0\nDO\n    i = i + 1\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "error at 2:1: unterminated DO block started at line 2"
+        );
     }
 
-    /// term ::= unary { ("*" | "/") unary }
-    fn parse_term(&mut self) {
-        self.parse_unary();
+    #[test]
+    fn test_parse_inline_if() {
+        let block = parse("LET a = 1\nIF a > 0 THEN\n    a = 0\nENDIF\nPRINT a\n").unwrap();
+        let inline = parse("LET a = 1\nIF a > 0 THEN a = 0\nPRINT a\n").unwrap();
+        assert_eq!(inline, block);
+        let inline = parse_with_positions("LET a = 1\nIF a > 0 THEN a = 0\nPRINT a\n").unwrap();
+        assert_eq!(span_of(inline[1].span()), (2, 1, 2, 20));
+
+        let nested = parse("LET a = 1\nIF a > 0 THEN IF a < 2 THEN PRINT a\n").unwrap();
+        assert_eq!(nested.len(), 2);
 
-        while self.check_token(TokenType::Asterisk) || self.check_token(TokenType::Slash) {
-            self.emitter.emit(&self.curtoken.spelling);
-            self.next_token();
-            self.parse_unary();
+        for input in [
+            "LET a = 1\nIF a > 0 THEN PRINT a\nELSE\nPRINT 0\nENDIF\n",
+            "LET a = 1\nIF a > 0 THEN PRINT a\nENDIF\n",
+            "LET a = 1\nIF a > 0 THEN\nPRINT a\nELSEIF a < 0 THEN PRINT 0\nENDIF\n",
+        ] {
+            assert!(parse(input).is_err(), "{}", input);
         }
     }
 
-    /// expression ::= term { ("+" | "-) term }
-    fn parse_expression(&mut self) {
-        self.parse_term();
+    #[test]
+    fn test_invalid_variable_and_label() {
+        assert!(parse("PRINT index\nGOTO main\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_let_self_reference() {
+        let err = parse("LET x = x + 1").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("error at 1:9: Undeclared variable: \"x\""));
+
+        let err = parse("INT i = i * 2").unwrap_err();
+        assert!(err.to_string().contains("Undeclared variable: \"i\""));
+
+        assert!(parse("LET x = 1\nLET x = x + 1").is_ok());
+        assert!(parse("INPUT x\nLET x = x + 1").is_ok());
+    }
+
+    #[test]
+    fn test_parse_chained_let() {
+        let let_ = |name: &str, value| Stmt::Let {
+            name: name.to_string(),
+            value,
+            doc: None,
+            span: Span::default(),
+        };
+        assert_eq!(
+            parse("LET a = 1, b = a + 1, c = \"x\"\nPRINT c\n").unwrap()[..3],
+            [
+                let_("a", num("1")),
+                let_("b", bin(BinaryOp::Add, var("a"), num("1"))),
+                let_("c", Expr::Str("x".to_string(), Span::default())),
+            ]
+        );
+        assert_eq!(
+            parse("LET a = 1\nIF a > 0 THEN LET b = 2, c = b\n").unwrap()[1],
+            Stmt::If {
+                branches: vec![(
+                    bin(BinaryOp::Gt, var("a"), num("0")),
+                    vec![let_("b", num("2")), let_("c", var("b"))]
+                )],
+                else_body: None,
+                span: Span::default(),
+            }
+        );
+
+        assert_eq!(
+            errors("LET a = 1, b = 2,\n"),
+            vec![(1, "expected identifier but found newline".to_string())]
+        );
+        assert_eq!(
+            errors("LET a = 1, a += 2\n"),
+            vec![(1, "expected = but found +=".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_parse_power() {
+        assert_eq!(
+            parse("LET x = 2 ^ 3 ^ 2 * 4").unwrap(),
+            vec![Stmt::Let {
+                name: "x".to_string(),
+                value: bin(
+                    BinaryOp::Mul,
+                    bin(
+                        BinaryOp::Pow,
+                        num("2"),
+                        bin(BinaryOp::Pow, num("3"), num("2"))
+                    ),
+                    num("4")
+                ),
+                doc: None,
+                span: Span::default(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_print_items() {
+        assert_eq!(
+            parse("LET x = 1\nPRINT x, \" \", x + 1").unwrap()[1],
+            Stmt::Print(
+                vec![
+                    var("x"),
+                    Expr::Str(" ".to_string(), Span::default()),
+                    bin(BinaryOp::Add, var("x"), num("1")),
+                ],
+                Span::default()
+            )
+        );
+        assert!(parse("PRINT \"a\",").is_err());
+        assert!(parse("PRINT \"a\" \"b\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_write() {
+        assert_eq!(
+            parse("WRITE \"a\", 1").unwrap(),
+            vec![Stmt::Write(
+                vec![Expr::Str("a".to_string(), Span::default()), num("1")],
+                Span::default()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_string_variables() {
+        assert_eq!(
+            parse("LET s = \"hello\"\ns = \"bye\"\nLET t = s\nPRINT s, t").unwrap(),
+            vec![
+                Stmt::Let {
+                    name: "s".to_string(),
+                    value: Expr::Str("hello".to_string(), Span::default()),
+                    doc: None,
+                    span: Span::default(),
+                },
+                Stmt::Assign {
+                    name: "s".to_string(),
+                    value: Expr::Str("bye".to_string(), Span::default()),
+                    span: Span::default(),
+                },
+                Stmt::Let {
+                    name: "t".to_string(),
+                    value: var("s"),
+                    doc: None,
+                    span: Span::default(),
+                },
+                Stmt::Print(vec![var("s"), var("t")], Span::default()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_const() {
+        assert_eq!(
+            parse("CONST PI = 3.14159\nCONST TAU = 2 * PI\nLET r = 2\nPRINT PI * r").unwrap(),
+            vec![
+                Stmt::Const {
+                    name: "PI".to_string(),
+                    value: num("3.14159"),
+                    span: Span::default(),
+                },
+                Stmt::Const {
+                    name: "TAU".to_string(),
+                    value: bin(BinaryOp::Mul, num("2"), var("PI")),
+                    span: Span::default(),
+                },
+                Stmt::Let {
+                    name: "r".to_string(),
+                    value: num("2"),
+                    doc: None,
+                    span: Span::default(),
+                },
+                Stmt::Print(
+                    vec![bin(BinaryOp::Mul, var("PI"), var("r"))],
+                    Span::default()
+                ),
+            ]
+        );
+    }
 
-        while self.check_token(TokenType::Plus) || self.check_token(TokenType::Minus) {
-            self.emitter.emit(&self.curtoken.spelling);
-            self.next_token();
-            self.parse_term();
+    #[test]
+    fn test_parse_block_scope() {
+        for input in [
+            "LET a = 1\nIF a > 0 THEN\nLET b = 2\nENDIF\nPRINT b\n",
+            "LET a = 1\nIF a > 0 THEN\nPRINT a\nELSE\nINPUT b\nENDIF\nPRINT b\n",
+            "LET a = 1\nWHILE a > 0 REPEAT\nINT b = 2\nENDWHILE\nPRINT b\n",
+            "LET a = 1\nDO\nLET b = 2\nWHILE a > 0\nPRINT b\n",
+            "LET a = 1\nIF a > 0 THEN LET b = 2\nPRINT b\n",
+        ] {
+            let lines = input.lines().count();
+            assert_eq!(
+                errors(input),
+                vec![(lines, "Undeclared variable: \"b\"".to_string())],
+                "{}",
+                input
+            );
         }
+
+        assert_eq!(
+            errors("LET a = 1\nDO\nLET b = a\nWHILE b > 0\n"),
+            vec![(4, "\"b\" is out of scope in the DO condition".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_parser_symbols() {
+        let mut parser = Parser::new(Lexer::new("LET x = 1\nLET y = 2")).unwrap();
+        parser.parse().unwrap();
+
+        let mut symbols = parser
+            .symbols()
+            .iter()
+            .map(|(name, symbol)| (name, symbol.ty, symbol.pos.line, symbol.pos.col))
+            .collect::<Vec<_>>();
+        symbols.sort_by_key(|&(name, ..)| name);
+        assert_eq!(
+            symbols,
+            vec![("x", Type::Float, 1, 5), ("y", Type::Float, 2, 5)]
+        );
     }
 
-    fn is_comparison_operator(&self, kind: TokenType) -> bool {
-        match kind {
-            TokenType::EqEq
-            | TokenType::NotEq
-            | TokenType::Lt
-            | TokenType::Lte
-            | TokenType::Gt
-            | TokenType::Gte => true,
-            _ => false,
+    #[test]
+    fn test_parse_no_shadowing() {
+        // LET in a block assigns to the variable outside it
+        let program = parse("LET a = 1\nIF a > 0 THEN\nLET a = \"s\"\nENDIF\n");
+        assert!(program
+            .unwrap_err()
+            .to_string()
+            .contains("cannot assign a string to numeric variable \"a\""));
+
+        assert_eq!(
+            errors("INT i = 0\nWHILE i < 3 REPEAT\nINT i = 1\nENDWHILE\n"),
+            vec![(3, "Variable already declared: \"i\"".to_string())]
+        );
+
+        // but the names declared in a block can be declared again after it
+        let input = "LET a = 1
+IF a > 0 THEN
+LET s = \"s\"
+PRINT s
+ENDIF
+WHILE a > 0 REPEAT
+INT s = 2
+a = a - s
+ENDWHILE
+CONST s = 3
+PRINT s
+";
+        assert!(parse(input).is_ok());
+    }
+
+    #[test]
+    fn test_parse_break_continue() {
+        let input = "INT i = 0\nWHILE i < 5 REPEAT\n    IF i == 3 THEN\n        BREAK\n    ENDIF\n    CONTINUE\nENDWHILE\n";
+        match &parse(input).unwrap()[1] {
+            Stmt::While { body, .. } => {
+                assert_eq!(body[1], Stmt::Continue(Span::default()));
+                match &body[0] {
+                    Stmt::If { branches, .. } => {
+                        assert_eq!(branches[0].1, vec![Stmt::Break(Span::default())])
+                    }
+                    stmt => panic!("unexpected statement: {:?}", stmt),
+                }
+            }
+            stmt => panic!("unexpected statement: {:?}", stmt),
+        }
+
+        for (input, message) in [
+            ("BREAK\n", "error at 1:1: break outside of loop"),
+            (
+                "INT i = 0\nIF i == 0 THEN\n    CONTINUE\nENDIF\n",
+                "error at 3:5: continue outside of loop",
+            ),
+            (
+                "INT i = 0\nWHILE i < 1 REPEAT\n    i = 1\nENDWHILE\nBREAK\n",
+                "error at 5:1: break outside of loop",
+            ),
+        ] {
+            let err = parse(input).unwrap_err();
+            assert!(err.to_string().contains(message), "{}: {}", input, err);
         }
     }
 
-    /// comparison ::= expression ( ("==" | "!=" | "<" | "<=" | ">" | ">=") expression)+
-    fn parse_comparison(&mut self) {
-        self.parse_expression();
-        if self.is_comparison_operator(self.curtoken.kind) {
-            self.emitter.emit(&self.curtoken.spelling);
-            self.next_token();
-            self.parse_expression();
-        } else {
-            self.abort(&format!(
-                "Expected comparison operator, but got {:?}",
-                self.curtoken.kind
-            ));
+    #[test]
+    fn test_parse_const_errors() {
+        for (input, message) in [
+            (
+                "CONST PI = 3\nLET PI = 4",
+                "error at 2:5: cannot assign to constant \"PI\"",
+            ),
+            (
+                "CONST PI = 3\nPI = 4",
+                "error at 2:1: cannot assign to constant \"PI\"",
+            ),
+            (
+                "CONST PI = 3\nINPUT PI",
+                "error at 2:7: cannot assign to constant \"PI\"",
+            ),
+            (
+                "LET x = 1\nCONST PI = x",
+                "error at 2:12: CONST value cannot use variable \"x\"",
+            ),
+            ("LET x = 1\nCONST x = 2", "Variable already declared: \"x\""),
+        ] {
+            let err = parse(input).unwrap_err();
+            assert!(err.to_string().contains(message), "{}: {}", input, err);
         }
+    }
 
-        while self.is_comparison_operator(self.curtoken.kind) {
-            self.emitter.emit(&self.curtoken.spelling);
-            self.next_token();
-            self.parse_expression();
+    #[test]
+    fn test_parse_string_type_errors() {
+        for (input, message) in [
+            (
+                "LET s = \"a\"\nLET s = 1",
+                "error at 2:7: type error: cannot assign a number to string variable \"s\"",
+            ),
+            (
+                "LET x = 1\nx = \"a\"",
+                "type error: cannot assign a string to numeric variable \"x\"",
+            ),
+            (
+                "INT i = \"a\"",
+                "type error: cannot assign a string to numeric variable \"i\"",
+            ),
+            (
+                "LET s = \"a\"\nINPUT s",
+                "type error: cannot INPUT into string variable \"s\"",
+            ),
+        ] {
+            let err = parse(input).unwrap_err();
+            assert!(err.to_string().contains(message), "{}: {}", input, err);
         }
     }
 
-    /// statement ::= "PRINT" (expression | string) NL
-    ///             | "IF" comparison "THEN" NL { statement } "ENDIF" NL
-    ///             | "WHILE" comparison "REPEAT" NL { statement } "ENDWHILE" NL
-    ///             | "LABEL" ident NL
-    ///             | "GOTO" ident NL
-    ///             | "LET" ident "=" expression NL
-    ///             | "INPUT" ident NL
-    fn parse_statement(&mut self) {
-        match self.curtoken.kind {
-            TokenType::Print => {
-                self.match_token(TokenType::Print);
+    #[test]
+    fn test_parse_int() {
+        assert_eq!(
+            parse("INT i = 0\nLET i = i + 1").unwrap(),
+            vec![
+                Stmt::Int {
+                    name: "i".to_string(),
+                    value: num("0"),
+                    span: Span::default(),
+                },
+                Stmt::Let {
+                    name: "i".to_string(),
+                    value: bin(BinaryOp::Add, var("i"), num("1")),
+                    doc: None,
+                    span: Span::default(),
+                },
+            ]
+        );
+    }
 
-                if self.check_token(TokenType::String) {
-                    self.emitter
-                        .emit_line(&format!("printf(\"{}\\n\");", self.curtoken.spelling));
-                    self.match_token(TokenType::String);
-                } else {
-                    self.emitter
-                        .emit(&format!("printf(\"{}\\n\", (float)(", "%.2f"));
-                    self.parse_expression();
-                    self.emitter.emit_line("));");
-                }
-            }
+    #[test]
+    fn test_parse_int_redeclared() {
+        let err = parse("LET i = 0\nINT i = 1").unwrap_err();
+        assert!(err.to_string().contains("Variable already declared: \"i\""));
+    }
 
-            TokenType::If => {
-                self.match_token(TokenType::If);
-                self.emitter.emit("if (");
-                self.parse_comparison();
-                self.match_token(TokenType::Then);
-                self.parse_newline();
-                self.emitter.emit_line(") {");
+    #[test]
+    fn test_parse_assignment() {
+        assert_eq!(
+            parse("LET x = 1\nx = 2").unwrap(),
+            vec![
+                Stmt::Let {
+                    name: "x".to_string(),
+                    value: num("1"),
+                    doc: None,
+                    span: Span::default(),
+                },
+                Stmt::Assign {
+                    name: "x".to_string(),
+                    value: num("2"),
+                    span: Span::default(),
+                },
+            ]
+        );
+    }
 
-                while !self.check_token(TokenType::Endif) {
-                    self.parse_statement();
-                }
-                self.match_token(TokenType::Endif);
-                self.emitter.emit_line("}");
-            }
+    #[test]
+    fn test_parse_assignment_to_undeclared() {
+        let err = parse("LET x = 1\ny = 2").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("error at 2:1: assignment to undeclared variable: \"y\""));
+    }
 
-            TokenType::While => {
-                self.match_token(TokenType::While);
-                self.emitter.emit("while (");
-                self.parse_comparison();
-                self.match_token(TokenType::Repeat);
-                self.parse_newline();
-                self.emitter.emit_line(") {");
+    #[test]
+    fn test_parse_bare_expression_statement() {
+        let err = parse("LET x = 1\nx + 1").unwrap_err();
+        assert!(err.to_string().contains("error at 2:1: Invalid statement"));
 
-                while !self.check_token(TokenType::Endwhile) {
-                    self.parse_statement();
-                }
-                self.match_token(TokenType::Endwhile);
-                self.emitter.emit_line("}");
-            }
+        let err = parse("LET x = 1\ny").unwrap_err();
+        assert!(err.to_string().contains("error at 2:1: Invalid statement"));
+    }
 
-            TokenType::Label => {
-                self.match_token(TokenType::Label);
+    #[test]
+    fn test_parse_lookahead_keeps_tokens() {
+        let program = parse("LET x = 1\nx = x + 1 # bump\nPRINT x").unwrap();
+        assert_eq!(
+            program[1..],
+            [
+                Stmt::Assign {
+                    name: "x".to_string(),
+                    value: bin(BinaryOp::Add, var("x"), num("1")),
+                    span: Span::default(),
+                },
+                Stmt::Comment("bump".to_string(), Span::default()),
+                Stmt::Print(vec![var("x")], Span::default()),
+            ]
+        );
+    }
 
-                if self.declared_labels.contains(&self.curtoken.spelling) {
-                    self.abort(&format!("Duplicate label: {:?}", &self.curtoken.spelling));
-                }
-                self.declared_labels.insert(self.curtoken.spelling.clone());
-                self.emitter
-                    .emit_line(&format!("{}:", self.curtoken.spelling));
-                self.match_token(TokenType::Ident);
+    #[test]
+    fn test_parse_input_prompt() {
+        let input = "INPUT \"Enter your age: \", age\nPRINT age";
+        assert_eq!(
+            parse(input).unwrap()[0],
+            Stmt::Input {
+                prompt: Some("Enter your age: ".to_string()),
+                name: "age".to_string(),
+                doc: None,
+                span: Span::default(),
             }
+        );
 
-            TokenType::Goto => {
-                self.match_token(TokenType::Goto);
-                self.gotoed_labels.insert(self.curtoken.spelling.clone());
-                self.emitter
-                    .emit_line(&format!("goto {};", self.curtoken.spelling));
-                self.match_token(TokenType::Ident);
+        assert_eq!(
+            parse("INPUT age").unwrap()[0],
+            Stmt::Input {
+                prompt: None,
+                name: "age".to_string(),
+                doc: None,
+                span: Span::default(),
             }
+        );
+    }
 
-            TokenType::Let => {
-                self.match_token(TokenType::Let);
+    #[test]
+    fn test_parse_input_prompt_missing_comma() {
+        assert!(parse("INPUT \"Enter your age: \" age").is_err());
+    }
 
-                if !self.symbols.contains(&self.curtoken.spelling) {
-                    self.symbols.insert(self.curtoken.spelling.clone());
-                    self.emitter
-                        .header_line(&format!("float {};", self.curtoken.spelling));
-                }
+    #[test]
+    fn test_parse_input_needs_identifier() {
+        assert_eq!(
+            errors("INPUT 5\nLET s = \"a\"\nINPUT s\n"),
+            vec![
+                (1, "expected identifier but found number".to_string()),
+                (
+                    3,
+                    "type error: cannot INPUT into string variable \"s\"".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        let err = parse("LET a = 1\nLET b = c + a").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("error at 2:9: Undeclared variable"));
+    }
 
-                self.emitter.emit(&format!("{} = ", self.curtoken.spelling));
-                self.match_token(TokenType::Ident);
-                self.match_token(TokenType::Eq);
-                self.parse_expression();
-                self.emitter.emit_line(";");
+    #[test]
+    fn test_parse_if_elseif_else() {
+        let input =
+            "LET a = 1\nIF a > 1 THEN\nPRINT 1\nELSEIF a > 0 THEN\nPRINT 2\nELSE\nPRINT 3\nENDIF\n";
+        assert_eq!(
+            parse(input).unwrap()[1],
+            Stmt::If {
+                branches: vec![
+                    (
+                        bin(BinaryOp::Gt, var("a"), num("1")),
+                        vec![Stmt::Print(vec![num("1")], Span::default())]
+                    ),
+                    (
+                        bin(BinaryOp::Gt, var("a"), num("0")),
+                        vec![Stmt::Print(vec![num("2")], Span::default())]
+                    ),
+                ],
+                else_body: Some(vec![Stmt::Print(vec![num("3")], Span::default())]),
+                span: Span::default(),
             }
+        );
+    }
 
-            TokenType::Input => {
-                self.match_token(TokenType::Input);
+    #[test]
+    fn test_parse_elseif_after_else() {
+        let input =
+            "LET a = 1\nIF a > 1 THEN\nPRINT 1\nELSE\nPRINT 2\nELSEIF a > 0 THEN\nPRINT 3\nENDIF\n";
+        let err = parse(input).unwrap_err();
+        assert!(err.to_string().contains("ELSEIF cannot follow ELSE"));
+    }
 
-                if !self.symbols.contains(&self.curtoken.spelling) {
-                    self.symbols.insert(self.curtoken.spelling.clone());
-                    self.emitter
-                        .header_line(&format!("float {};", self.curtoken.spelling));
+    #[test]
+    fn test_parse_else_without_if() {
+        let err = parse("LET a = 1\nELSE\nPRINT a\n").unwrap_err();
+        assert!(err.to_string().contains("ELSE without a matching IF"));
+    }
+
+    #[test]
+    fn test_parse_missing_rparen() {
+        let err = parse("LET a = 1\nLET x = (a * (a + 1)").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("missing ')' to close the '(' at 2:9"));
+    }
+
+    #[test]
+    fn test_parse_unmatched_rparen() {
+        let err = parse("LET a = 1\nLET x = a + 1)").unwrap_err();
+        assert!(err.to_string().contains("unmatched ')'"));
+    }
+
+    #[test]
+    fn test_parse_logical_operator_missing_comparison() {
+        assert!(parse("LET a = 1\nIF a > 0 AND a THEN\nPRINT a\nENDIF\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_comments() {
+        let input = "# leading\n\nLET a = 1 # trailing\nIF a > 0 THEN\n    PRINT a\n    # before ENDIF\nENDIF\n# at end of file";
+        let program = parse(input).unwrap();
+        assert_eq!(
+            program[0],
+            Stmt::Comment("leading".to_string(), Span::default())
+        );
+        assert_eq!(
+            program[2],
+            Stmt::Comment("trailing".to_string(), Span::default())
+        );
+        assert_eq!(
+            program[3],
+            Stmt::If {
+                branches: vec![(
+                    bin(BinaryOp::Gt, var("a"), num("0")),
+                    vec![
+                        Stmt::Print(vec![var("a")], Span::default()),
+                        Stmt::Comment("before ENDIF".to_string(), Span::default())
+                    ]
+                )],
+                else_body: None,
+                span: Span::default(),
+            }
+        );
+        assert_eq!(
+            program[4],
+            Stmt::Comment("at end of file".to_string(), Span::default())
+        );
+    }
+
+    #[test]
+    fn test_parse_trailing_comments() {
+        let input = "\
+LET a = 1 # let
+PRINT a # print
+WRITE a # write
+a = a + 1 # assign
+a += 1 # compound
+IF a > 0 THEN PRINT a # inline
+WHILE a < 5 REPEAT # loop
+    BREAK # break
+ENDWHILE # endwhile
+DO # do
+    a = a + 1
+WHILE a < 3 # until
+LABEL top # label
+INPUT b # input
+SWAP a, b # swap
+ASSERT a > 0 # assert
+GOTO top # goto
+RETURN 0 # return";
+        let program = parse(input).unwrap();
+
+        // every statement is followed by its comment
+        let mut comments = Vec::new();
+        for pair in program.chunks(2) {
+            match pair {
+                [stmt, Stmt::Comment(text, _)] if !matches!(stmt, Stmt::Comment(..)) => {
+                    comments.push(text.as_str())
                 }
-                self.emitter.emit_line(&format!(
-                    "if (0 == scanf(\"{}\", &{})) {{",
-                    "%f", self.curtoken.spelling
-                ));
-                self.emitter
-                    .emit_line(&format!("{} = 0;", self.curtoken.spelling));
-                self.emitter.emit("scanf(\"%");
-                self.emitter.emit_line("*s\");");
-                self.emitter.emit_line("}");
-                self.match_token(TokenType::Ident);
+                pair => panic!("unexpected statements: {:?}", pair),
             }
+        }
+        assert_eq!(
+            comments,
+            vec![
+                "let", "print", "write", "assign", "compound", "inline", "endwhile", "until",
+                "label", "input", "swap", "assert", "goto", "return"
+            ]
+        );
 
-            _ => self.abort(&format!("Invalid statement at {:?}", self.curtoken)),
+        match &program[12] {
+            Stmt::While { body, .. } => assert_eq!(
+                body,
+                &vec![
+                    Stmt::Comment("loop".to_string(), Span::default()),
+                    Stmt::Break(Span::default()),
+                    Stmt::Comment("break".to_string(), Span::default()),
+                ]
+            ),
+            stmt => panic!("unexpected statement: {:?}", stmt),
         }
+    }
 
-        self.parse_newline();
+    /// A span as `(start line, start col, end line, end col)`, since spans
+    /// themselves always compare equal.
+    fn span_of(span: Span) -> (usize, usize, usize, usize) {
+        (span.start.line, span.start.col, span.end.line, span.end.col)
     }
 
-    /// program ::= { statement }
-    fn parse_program(&mut self) {
-        self.emitter.header_line("#include <stdio.h>");
-        self.emitter
-            .header_line("int main(int argc, char *argv[]) {");
+    #[test]
+    fn test_binary_span_covers_operands() {
+        let program = parse_with_positions("LET a = 1\nLET x = (a + 2) * -a - 10").unwrap();
+        let value = match &program[1] {
+            Stmt::Let { value, .. } => value,
+            stmt => panic!("unexpected statement: {:?}", stmt),
+        };
+        assert_eq!(span_of(value.span()), (2, 9, 2, 26));
 
-        while !self.check_token(TokenType::Eof) {
-            self.parse_statement();
+        let (lhs, rhs) = match value {
+            Expr::Binary { lhs, rhs, .. } => (lhs, rhs),
+            expr => panic!("unexpected expression: {:?}", expr),
+        };
+        assert_eq!(span_of(rhs.span()), (2, 24, 2, 26));
+        assert_eq!(span_of(lhs.span()), (2, 9, 2, 21));
+        match &**lhs {
+            Expr::Binary { lhs, rhs, .. } => {
+                // the parenthesized operand includes its parentheses
+                assert_eq!(span_of(lhs.span()), (2, 9, 2, 16));
+                assert_eq!(span_of(rhs.span()), (2, 19, 2, 21));
+            }
+            expr => panic!("unexpected expression: {:?}", expr),
         }
-
-        self.emitter.emit_line("return 0;");
-        self.emitter.emit_line("}");
     }
 
-    pub fn parse(&mut self) {
-        while self.check_token(TokenType::Newline) {
-            self.next_token();
-        }
-        self.parse_program();
+    #[test]
+    fn test_statement_spans() {
+        let input = "# note\n\nLET a = 1\nWHILE a < 3 REPEAT\n    PRINT \"a\", a\nENDWHILE\n";
+        let program = parse_with_positions(input).unwrap();
+        let spans = program
+            .iter()
+            .map(|stmt| span_of(stmt.span()))
+            .collect::<Vec<_>>();
+        assert_eq!(spans, vec![(1, 1, 1, 7), (3, 1, 3, 10), (4, 1, 6, 9),]);
 
-        for label in &self.gotoed_labels {
-            if !self.declared_labels.contains(label) {
-                self.abort(&format!("Goto's label is undefined: {:?}", label));
+        match &program[2] {
+            Stmt::While { body, .. } => {
+                assert_eq!(span_of(body[0].span()), (5, 5, 5, 17));
             }
+            stmt => panic!("unexpected statement: {:?}", stmt),
         }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use crate::emitter::Emitter;
-    use crate::lexer::Lexer;
-    use crate::parser::Parser;
+    #[test]
+    fn test_span_merge() {
+        let pos = |line, col| Pos { line, col };
+        let a = Span::new(pos(1, 5), pos(1, 8));
+        let b = Span::new(pos(2, 1), pos(2, 3));
+        assert_eq!(span_of(a.merge(b)), (1, 5, 2, 3));
+        assert_eq!(span_of(b.merge(a)), (1, 5, 2, 3));
+    }
 
-    fn read_source(infile: &str) -> String {
-        use std::fs::File;
-        use std::io::{BufReader, Read};
+    #[test]
+    fn test_clear_positions() {
+        assert_ne!(Pos { line: 1, col: 5 }, Pos { line: 1, col: 6 });
+        assert_ne!(
+            Span {
+                start: Pos { line: 1, col: 1 },
+                end: Pos { line: 1, col: 4 },
+            },
+            Span::default()
+        );
 
-        let mut reader = BufReader::new(File::open(infile).unwrap());
-        let mut buffer = String::new();
-        reader.read_to_string(&mut buffer).unwrap();
-        buffer
+        let spaced = parse_with_positions("LET a = 1 + 2\nPRINT -a\n").unwrap();
+        let packed = parse_with_positions("LET a=1+2\nPRINT  -a\n").unwrap();
+        assert_ne!(spaced, packed);
+        assert_eq!(
+            parse("LET a = 1 + 2\nPRINT -a\n").unwrap(),
+            parse("LET a=1+2\nPRINT  -a\n").unwrap()
+        );
     }
 
     #[test]
-    fn test_parse_label_loop() {
-        let input = "LABEL loop\nPRINT \"hello, world\"\nGOTO loop";
-        let emitter = Emitter::new("dummy.c");
-        let mut parser = Parser::new(Lexer::new(input), &emitter);
-        parser.parse();
+    fn test_parse_doc_comments() {
+        let input = "# the count\n# of things\nLET a = 1 # trailing\nINPUT b\n# apart\n\nINPUT c\n# reused\na = 2\n";
+        let program = parse(input).unwrap();
+        let docs = program
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::Let { doc, .. } | Stmt::Input { doc, .. } => doc.as_deref(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            docs,
+            vec![
+                Some("the count\nof things"),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None
+            ]
+        );
+        assert_eq!(
+            program[1],
+            Stmt::Comment("trailing".to_string(), Span::default())
+        );
+        assert_eq!(
+            program[3],
+            Stmt::Comment("apart".to_string(), Span::default())
+        );
     }
 
     #[test]
-    #[should_panic]
-    fn test_parse_let() {
-        let input = "LET foo = bar * 3 + 2";
-        let emitter = Emitter::new("dummy.c");
-        let mut parser = Parser::new(Lexer::new(input), &emitter);
-        parser.parse();
+    fn test_parse_blank_and_comment_lines() {
+        let input = "\n  \n# one\nLET a = 1\r\n\r\n   \t\n  # two\n\nWHILE a < 3 REPEAT\n\n  # three\n   \n  a = a + 1   \n\nENDWHILE\n\n\n";
+        assert_eq!(
+            parse(input).unwrap(),
+            vec![
+                Stmt::Let {
+                    name: "a".to_string(),
+                    value: num("1"),
+                    doc: Some("one".to_string()),
+                    span: Span::default(),
+                },
+                Stmt::Comment("two".to_string(), Span::default()),
+                Stmt::While {
+                    cond: bin(BinaryOp::Lt, var("a"), num("3")),
+                    body: vec![
+                        Stmt::Comment("three".to_string(), Span::default()),
+                        Stmt::Assign {
+                            name: "a".to_string(),
+                            value: bin(BinaryOp::Add, var("a"), num("1")),
+                            span: Span::default(),
+                        },
+                    ],
+                    span: Span::default(),
+                },
+            ]
+        );
+        assert_eq!(parse("\n\n# nothing else\n\n").unwrap().len(), 1);
+    }
+
+    fn errors(source: &str) -> Vec<(usize, String)> {
+        let err = parse(source).unwrap_err();
+        let errs = match err.downcast_ref::<CompileErrors>() {
+            Some(errs) => &errs.0,
+            None => &err.downcast_ref::<TooManyErrors>().unwrap().0,
+        };
+        errs.iter()
+            .map(|err| (err.line, err.message.clone()))
+            .collect()
     }
 
     #[test]
-    #[should_panic]
-    fn test_parse_let_if() {
-        let input = "LET foo = bar * 3 + 2\nIF foo > 0 THEN\nPRINT \"yes!\"\nENDIF\n";
-        let emitter = Emitter::new("dummy.c");
-        let mut parser = Parser::new(Lexer::new(input), &emitter);
-        parser.parse();
+    fn test_parse_reports_multiple_errors() {
+        let input = "LET a = 1
+LET b = a +
+PRINT c
+LET d = a ! 2
+PRINT a
+";
+        assert_eq!(
+            errors(input),
+            vec![
+                (2, "Unexpected token: newline".to_string()),
+                (3, "Undeclared variable: \"c\"".to_string()),
+                (4, "! must be followed by =".to_string()),
+            ]
+        );
     }
 
     #[test]
-    #[should_panic]
-    fn test_parse_nested_if() {
-        let input = "LET foo = bar * 3 + 2\nIF foo > 0 THEN\nIF 10 * 10 < 100 THEN\nPRINT bar\nENDIF\nENDIF";
-        let emitter = Emitter::new("dummy.c");
-        let mut parser = Parser::new(Lexer::new(input), &emitter);
-        parser.parse();
+    fn test_parse_nesting_error_ends_parse() {
+        let deep = DEFAULT_MAX_NESTING + 40;
+        let input = format!(
+            "PRINT c\n{}PRINT 1\n{}PRINT d\n",
+            "IF 1 > 0 THEN\n".repeat(deep),
+            "ENDIF\n".repeat(deep)
+        );
+        assert_eq!(
+            errors(&input),
+            vec![
+                (1, "Undeclared variable: \"c\"".to_string()),
+                (
+                    DEFAULT_MAX_NESTING,
+                    format!("maximum nesting depth of {} exceeded", DEFAULT_MAX_NESTING)
+                ),
+            ]
+        );
     }
 
     #[test]
-    #[should_panic]
-    fn test_invalid_variable_and_label() {
-        let input = "PRINT index\nGOTO main\n";
-        let emitter = Emitter::new("dummy.c");
-        let mut parser = Parser::new(Lexer::new(input), &emitter);
-        parser.parse();
+    fn test_parse_max_errors() {
+        let parse_with = |source: &str, max_errors| {
+            let mut parser = Parser::new(Lexer::new(source)).unwrap();
+            parser.max_errors = max_errors;
+            parser.parse().unwrap_err()
+        };
+
+        let err = parse_with(&"LET = 1\n".repeat(50), DEFAULT_MAX_ERRORS);
+        let errs = err.downcast_ref::<TooManyErrors>().unwrap();
+        assert_eq!(errs.0.len(), DEFAULT_MAX_ERRORS);
+        assert_eq!(errs.0.last().unwrap().line, DEFAULT_MAX_ERRORS);
+
+        let err = parse_with(&"LET = 1\n".repeat(3), 3);
+        assert_eq!(err.downcast_ref::<CompileErrors>().unwrap().0.len(), 3);
+
+        // undefined labels are only found at the end
+        let err = parse_with("GOTO a\nGOTO b\nGOTO c\n", 2);
+        assert_eq!(err.downcast_ref::<TooManyErrors>().unwrap().0.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_errors_do_not_cascade() {
+        // a bad initializer still declares the variable
+        assert_eq!(errors("LET x = )\nPRINT x\nLET y = x\n").len(), 1);
+        // an undeclared variable is reported once
+        assert_eq!(errors("PRINT x\nPRINT x + 1\n").len(), 1);
+        // a bad condition doesn't orphan the rest of the block
+        let input = "LET a = 1
+WHILE a > REPEAT
+    IF a THEN
+        PRINT a
+    ELSE
+        PRINT 0
+    ENDIF
+ENDWHILE
+";
+        assert_eq!(
+            errors(input).iter().map(|e| e.0).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+    }
+
+    #[test]
+    fn test_parse_undefined_goto_positions() {
+        let input = "LABEL top
+GOTO top
+GOTO nowhere
+PRINT 1
+  GOTO elsewhere
+GOTO nowhere
+";
+        let err = parse(input).unwrap_err();
+        let errs = err.downcast_ref::<CompileErrors>().unwrap();
+        assert_eq!(
+            errs.0
+                .iter()
+                .map(|err| (err.line, err.col, err.message.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (3, 1, "Goto's label is undefined: \"nowhere\""),
+                (5, 3, "Goto's label is undefined: \"elsewhere\""),
+                (6, 1, "Goto's label is undefined: \"nowhere\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_unterminated_block_after_error() {
+        let errs = errors("LET a = 1\nIF a > 0 THEN\nPRINT b\n");
+        assert_eq!(errs.len(), 2);
+        assert_eq!(
+            errs[1],
+            (2, "unterminated IF block started at line 2".to_string())
+        );
     }
 
     #[test]
     fn test_parse_average() {
-        let emitter = Emitter::new("dummy.c");
-        let mut parser = Parser::new(Lexer::new(&read_source("samples/average.teeny")), &emitter);
-        parser.parse();
+        parse(&read_source("samples/average.teeny")).unwrap();
     }
 
     #[test]
     fn test_parse_factorial() {
-        let emitter = Emitter::new("dummy.c");
-        let mut parser = Parser::new(
-            Lexer::new(&read_source("samples/factorial.teeny")),
-            &emitter,
-        );
-        parser.parse();
+        parse(&read_source("samples/factorial.teeny")).unwrap();
     }
 
     #[test]
     fn test_parse_hello() {
-        let emitter = Emitter::new("dummy.c");
-        let mut parser = Parser::new(Lexer::new(&read_source("samples/hello.teeny")), &emitter);
-        parser.parse();
+        parse(&read_source("samples/hello.teeny")).unwrap();
     }
 
     #[test]
     fn test_parse_statements() {
-        let emitter = Emitter::new("dummy.c");
-        let mut parser = Parser::new(
-            Lexer::new(&read_source("samples/statements.teeny")),
-            &emitter,
-        );
-        parser.parse();
+        parse(&read_source("samples/statements.teeny")).unwrap();
     }
 
     #[test]
     fn test_parse_expressions() {
-        let emitter = Emitter::new("dummy.c");
-        let mut parser = Parser::new(
-            Lexer::new(&read_source("samples/expression.teeny")),
-            &emitter,
-        );
-        parser.parse();
+        parse(&read_source("samples/expression.teeny")).unwrap();
     }
 
     #[test]
     fn test_parse_fib() {
-        let emitter = Emitter::new("dummy.c");
-        let mut parser = Parser::new(Lexer::new(&read_source("samples/fib.teeny")), &emitter);
-        parser.parse();
+        parse(&read_source("samples/fib.teeny")).unwrap();
     }
 
     #[test]
     fn test_parse_minmax() {
-        let emitter = Emitter::new("dummy.c");
-        let mut parser = Parser::new(Lexer::new(&read_source("samples/minmax.teeny")), &emitter);
-        parser.parse();
+        parse(&read_source("samples/minmax.teeny")).unwrap();
     }
 
     #[test]
     fn test_parse_vector() {
-        let emitter = Emitter::new("dummy.c");
-        let mut parser = Parser::new(Lexer::new(&read_source("samples/vector.teeny")), &emitter);
-        parser.parse();
+        parse(&read_source("samples/vector.teeny")).unwrap();
     }
 }
@@ -0,0 +1,151 @@
+//! The symbol table module
+
+use crate::ast::{Pos, Type};
+use std::collections::HashMap;
+
+/// A declared variable or constant.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub ty: Type,
+    /// Where the name was declared.
+    pub pos: Pos,
+    /// Whether it was declared with `CONST`, and so can't be reassigned.
+    pub constant: bool,
+}
+
+impl Symbol {
+    pub fn new(ty: Type, pos: Pos) -> Self {
+        Symbol {
+            ty,
+            pos,
+            constant: false,
+        }
+    }
+}
+
+/// The names declared so far, in a stack of scopes: the program as a whole
+/// at the bottom, and one more for each `IF`, `ELSE`, `WHILE` or `DO` body the
+/// current statement is in. A name declared in a body goes out of scope at
+/// the end of it.
+///
+/// A name is visible in the scope it's declared in and every scope nested in
+/// it, and can't be declared again while it is, so there is no shadowing.
+#[derive(Clone, Debug)]
+pub struct SymbolTable {
+    scopes: Vec<HashMap<String, Symbol>>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        SymbolTable::new()
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable {
+            scopes: vec![HashMap::new()],
+        }
+    }
+
+    /// Enters a block.
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// Leaves a block, forgetting the names declared in it.
+    pub fn pop_scope(&mut self) {
+        assert!(self.scopes.len() > 1, "the global scope is never popped");
+        self.scopes.pop();
+    }
+
+    /// Whether the current statement is outside of any block.
+    pub fn is_global(&self) -> bool {
+        self.scopes.len() == 1
+    }
+
+    /// Declares a name in the innermost scope.
+    pub fn declare(&mut self, name: &str, symbol: Symbol) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), symbol);
+    }
+
+    /// The visible symbol with the given name, if any.
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// The type of a visible name, if any.
+    pub fn type_of(&self, name: &str) -> Option<Type> {
+        self.get(name).map(|symbol| symbol.ty)
+    }
+
+    /// Every visible name and its symbol, in no particular order. After a
+    /// program has been parsed, these are the names it declares outside of
+    /// any block.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Symbol)> {
+        self.scopes
+            .iter()
+            .flat_map(|scope| scope.iter().map(|(name, symbol)| (name.as_str(), symbol)))
+    }
+
+    /// The visible symbol with the given name, declaring it in the innermost
+    /// scope first if there isn't one, as `LET` does.
+    pub fn get_or_declare(&mut self, name: &str, symbol: Symbol) -> Symbol {
+        if let Some(&existing) = self.get(name) {
+            return existing;
+        }
+        self.declare(name, symbol);
+        symbol
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::{Pos, Type};
+    use crate::symbols::{Symbol, SymbolTable};
+
+    fn symbol(ty: Type) -> Symbol {
+        Symbol::new(ty, Pos::default())
+    }
+
+    #[test]
+    fn test_inner_scope_is_forgotten() {
+        let mut symbols = SymbolTable::new();
+        symbols.declare("x", symbol(Type::Float));
+        assert!(symbols.is_global());
+
+        symbols.push_scope();
+        symbols.declare("y", symbol(Type::Str));
+        assert!(!symbols.is_global());
+        assert_eq!(symbols.type_of("x"), Some(Type::Float));
+        assert_eq!(symbols.type_of("y"), Some(Type::Str));
+
+        symbols.pop_scope();
+        assert_eq!(symbols.type_of("x"), Some(Type::Float));
+        assert_eq!(symbols.type_of("y"), None);
+    }
+
+    #[test]
+    fn test_get_or_declare_finds_outer_name() {
+        let mut symbols = SymbolTable::new();
+        symbols.declare("s", symbol(Type::Str));
+
+        symbols.push_scope();
+        assert_eq!(
+            symbols.get_or_declare("s", symbol(Type::Float)).ty,
+            Type::Str
+        );
+        assert_eq!(symbols.get_or_declare("n", symbol(Type::Int)).ty, Type::Int);
+        symbols.pop_scope();
+
+        assert_eq!(symbols.type_of("s"), Some(Type::Str));
+        assert!(!symbols.contains("n"));
+    }
+}
@@ -0,0 +1,367 @@
+//! The type checker module
+
+use crate::ast::{BinaryOp, Expr, Pos, Stmt, Type, UnaryOp};
+use crate::error::{CompileError, CompileErrors};
+use crate::lexer::is_integer_literal;
+use crate::symbols::{Symbol, SymbolTable};
+use crate::GenResult;
+
+/// The type a `LET` declares its variable with, given the value it's first
+/// assigned: a string for a string, an `int` for a condition, whose value is
+/// 0 or 1, and a float for any other number.
+pub fn let_type(value: &Expr, vars: &SymbolTable) -> Type {
+    match infer(value, vars) {
+        Ok(Type::Str) => Type::Str,
+        _ if value.is_condition() => Type::Int,
+        _ => Type::Float,
+    }
+}
+
+/// Works out the type of an expression, given the variables in scope.
+/// Arithmetic on integers stays an integer, except for `%`
+/// and `^`, and comparisons and logical operators give integers, as in C.
+pub fn infer(expr: &Expr, vars: &SymbolTable) -> Result<Type, CompileError> {
+    match expr {
+        Expr::Number(value, _) if is_integer_literal(value) => Ok(Type::Int),
+        Expr::Number(..) => Ok(Type::Float),
+        Expr::Str(..) => Ok(Type::Str),
+        Expr::Var(name, _) => Ok(vars.type_of(name).unwrap_or(Type::Float)),
+
+        Expr::Unary { op, pos, expr, .. } => match (op, infer(expr, vars)?) {
+            (_, Type::Str) => Err(CompileError::new(
+                &format!("type error: cannot apply {:?} to a string", op.spelling()),
+                pos.line,
+                pos.col,
+            )),
+            (UnaryOp::Not, _) => Ok(Type::Int),
+            (_, ty) => Ok(ty),
+        },
+
+        Expr::Binary { .. } => {
+            let (first, links) = expr.left_chain();
+            let mut ty = infer(first, vars)?;
+            for link in links {
+                ty = binary_type(link.op, link.pos, ty, infer(link.rhs, vars)?)?;
+            }
+            Ok(ty)
+        }
+
+        Expr::Call { func, args, .. } => {
+            for arg in args {
+                let is_string = infer(arg, vars)? == Type::Str;
+                if is_string != func.takes_string() {
+                    let pos = arg.span().start;
+                    let message = if is_string {
+                        format!("type error: cannot pass a string to {}", func.name())
+                    } else {
+                        format!("type error: {} takes a string", func.name())
+                    };
+                    return Err(CompileError::new(&message, pos.line, pos.col));
+                }
+            }
+
+            Ok(func.result_type())
+        }
+    }
+}
+
+/// The type of a binary operation on operands of the given types, found at
+/// `pos`.
+pub fn binary_type(op: BinaryOp, pos: Pos, lhs: Type, rhs: Type) -> Result<Type, CompileError> {
+    if lhs == Type::Str || rhs == Type::Str {
+        return Err(CompileError::new(
+            &format!(
+                "type error: cannot apply {:?} to {} and {}",
+                op.spelling(),
+                lhs,
+                rhs
+            ),
+            pos.line,
+            pos.col,
+        ));
+    }
+
+    match op {
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div
+            if lhs == Type::Int && rhs == Type::Int =>
+        {
+            Ok(Type::Int)
+        }
+        BinaryOp::Add
+        | BinaryOp::Sub
+        | BinaryOp::Mul
+        | BinaryOp::Div
+        | BinaryOp::Mod
+        | BinaryOp::Pow => Ok(Type::Float),
+        _ => Ok(Type::Int),
+    }
+}
+
+/// Checks that every expression in the program is well typed, reporting all
+/// of the type errors found.
+pub fn typecheck(program: &[Stmt]) -> GenResult<()> {
+    let mut checker = TypeChecker {
+        vars: SymbolTable::new(),
+        errors: Vec::new(),
+    };
+    checker.check_block(program);
+
+    if !checker.errors.is_empty() {
+        return Err(Box::new(CompileErrors(checker.errors)));
+    }
+
+    Ok(())
+}
+
+struct TypeChecker {
+    vars: SymbolTable,
+    errors: Vec<CompileError>,
+}
+
+impl TypeChecker {
+    fn check_block(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.check_statement(stmt);
+        }
+    }
+
+    /// Checks the body of an `IF`, `ELSE` or loop, in a scope of its own.
+    fn check_body(&mut self, stmts: &[Stmt]) {
+        self.vars.push_scope();
+        self.check_block(stmts);
+        self.vars.pop_scope();
+    }
+
+    /// Infers the type of an expression, recording the error if it is ill
+    /// typed. Such an expression is taken to be a float from then on.
+    fn check(&mut self, expr: &Expr) -> Type {
+        infer(expr, &self.vars).unwrap_or_else(|err| {
+            self.errors.push(err);
+            Type::Float
+        })
+    }
+
+    fn check_statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Print(items, _) | Stmt::Write(items, _) => {
+                for item in items {
+                    self.check(item);
+                }
+            }
+
+            Stmt::If {
+                branches,
+                else_body,
+                ..
+            } => {
+                for (cond, body) in branches {
+                    self.check(cond);
+                    self.check_body(body);
+                }
+                if let Some(body) = else_body {
+                    self.check_body(body);
+                }
+            }
+
+            Stmt::While { cond, body, .. } => {
+                self.check(cond);
+                self.check_body(body);
+            }
+
+            Stmt::DoWhile { body, cond, .. } => {
+                self.check_body(body);
+                self.check(cond);
+            }
+
+            Stmt::Assert(cond, _) => {
+                self.check(cond);
+            }
+
+            Stmt::Return(value, _) => {
+                if self.check(value) == Type::Str {
+                    let pos = value.span().start;
+                    self.errors.push(CompileError::new(
+                        "type error: cannot RETURN a string",
+                        pos.line,
+                        pos.col,
+                    ));
+                }
+            }
+
+            Stmt::Exit(Some(value), _) => {
+                if self.check(value) == Type::Str {
+                    let pos = value.span().start;
+                    self.errors.push(CompileError::new(
+                        "type error: cannot EXIT with a string",
+                        pos.line,
+                        pos.col,
+                    ));
+                }
+            }
+
+            Stmt::Let {
+                name, value, span, ..
+            } => {
+                self.check(value);
+                let ty = let_type(value, &self.vars);
+                self.vars.get_or_declare(name, Symbol::new(ty, span.start));
+            }
+
+            Stmt::Int {
+                name, value, span, ..
+            } => {
+                self.check(value);
+                self.vars.declare(name, Symbol::new(Type::Int, span.start));
+            }
+
+            Stmt::Const {
+                name, value, span, ..
+            } => {
+                self.check(value);
+                self.vars
+                    .declare(name, Symbol::new(Type::Float, span.start));
+            }
+
+            Stmt::Assign { value, .. } => {
+                self.check(value);
+            }
+
+            Stmt::CompoundAssign {
+                name,
+                op,
+                pos,
+                value,
+                ..
+            } => {
+                self.check(&Stmt::compound_value(name, *op, *pos, value));
+            }
+
+            Stmt::Input { name, span, .. } => {
+                self.vars
+                    .get_or_declare(name, Symbol::new(Type::Float, span.start));
+            }
+
+            Stmt::Exit(None, _)
+            | Stmt::Label(..)
+            | Stmt::Goto(..)
+            | Stmt::Emit(..)
+            | Stmt::Swap(..)
+            | Stmt::Break(..)
+            | Stmt::Continue(..)
+            | Stmt::Comment(..) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::{Stmt, Type};
+    use crate::error::CompileErrors;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::symbols::{Symbol, SymbolTable};
+    use crate::typecheck::{infer, typecheck};
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        Parser::new(Lexer::new(source)).unwrap().parse().unwrap()
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        let err = typecheck(&parse(source)).unwrap_err();
+        let errs = err.downcast_ref::<CompileErrors>().unwrap();
+        errs.0.iter().map(|err| err.to_string()).collect()
+    }
+
+    fn infer_value(source: &str, types: &[(&str, Type)]) -> Type {
+        let mut vars = SymbolTable::new();
+        for &(name, ty) in types {
+            vars.declare(name, Symbol::new(ty, Default::default()));
+        }
+        let declarations = types
+            .iter()
+            .map(|(name, _)| format!("LET {} = 0\n", name))
+            .collect::<String>();
+
+        match parse(&format!("{}LET result = {}", declarations, source)).pop() {
+            Some(Stmt::Let { value, .. }) => infer(&value, &vars).unwrap(),
+            stmt => panic!("unexpected statement: {:?}", stmt),
+        }
+    }
+
+    #[test]
+    fn test_infer_integer_expression() {
+        assert_eq!(infer_value("1 + 2 * 3 - -4 / 2", &[]), Type::Int);
+        assert_eq!(infer_value("0xFF + 0b1", &[]), Type::Int);
+        assert_eq!(
+            infer_value("i * (j + 1)", &[("i", Type::Int), ("j", Type::Int)]),
+            Type::Int
+        );
+    }
+
+    #[test]
+    fn test_infer_float_expression() {
+        assert_eq!(infer_value("1 + 2.5", &[]), Type::Float);
+        assert_eq!(infer_value("2e3", &[]), Type::Float);
+        assert_eq!(infer_value("7 % 2", &[]), Type::Float);
+        assert_eq!(infer_value("2 ^ 2", &[]), Type::Float);
+        assert_eq!(
+            infer_value("i + x", &[("i", Type::Int), ("x", Type::Float)]),
+            Type::Float
+        );
+    }
+
+    #[test]
+    fn test_typecheck_string_builtins() {
+        assert_eq!(infer_value("LEN(\"abc\") + ORD(\"a\")", &[]), Type::Int);
+        assert_eq!(infer_value("CHR(65)", &[]), Type::Str);
+        assert!(typecheck(&parse(
+            "LET c = CHR(65)\nc = \"b\"\nPRINT LEN(c), ORD(CHR(66))\n"
+        ))
+        .is_ok());
+
+        assert_eq!(
+            errors("LET x = 1\nPRINT LEN(x), ORD(2)\nLET s = \"a\"\nPRINT CHR(s), CHR(65) + 1\n"),
+            vec![
+                "error at 2:11: type error: LEN takes a string",
+                "error at 2:19: type error: ORD takes a string",
+                "error at 4:11: type error: cannot pass a string to CHR",
+                "error at 4:23: type error: cannot apply \"+\" to string and int",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_typecheck_ok() {
+        let input =
+            "INT i = 0\nLET s = \"hi\"\nWHILE i < 3 REPEAT\nPRINT s, i\ni = i + 1\nENDWHILE\n";
+        assert!(typecheck(&parse(input)).is_ok());
+    }
+
+    #[test]
+    fn test_typecheck_errors() {
+        let input = "LET s = \"a\"
+LET x = s + 1
+PRINT 2 * s
+IF s < 1 THEN
+    PRINT -s
+ENDIF
+";
+        assert_eq!(
+            errors(input),
+            vec![
+                "error at 2:11: type error: cannot apply \"+\" to string and int",
+                "error at 3:9: type error: cannot apply \"*\" to int and string",
+                "error at 4:6: type error: cannot apply \"<\" to string and int",
+                "error at 5:11: type error: cannot apply \"-\" to a string",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_typecheck_return_string() {
+        assert_eq!(
+            errors("LET s = \"a\"\nRETURN s\n"),
+            vec!["error at 2:8: type error: cannot RETURN a string"]
+        );
+    }
+}
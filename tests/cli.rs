@@ -0,0 +1,418 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+fn ttc() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_ttc-rs"))
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("ttc-rs-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn test_output_flag() {
+    for flag in ["-o", "--output"] {
+        let outfile = temp_path(&format!("hello{}.c", flag));
+
+        let status = ttc()
+            .args(["samples/hello.teeny", flag])
+            .arg(&outfile)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let code = std::fs::read_to_string(&outfile).unwrap();
+        assert!(code.contains("int main(int argc, char *argv[]) {"));
+        std::fs::remove_file(&outfile).unwrap();
+    }
+}
+
+#[test]
+fn test_usage_exits_with_failure() {
+    for args in [
+        &[][..],
+        &["-o"],
+        &["--bogus", "samples/hello.teeny"],
+        &["a", "b"],
+    ] {
+        let output = ttc().args(args).output().unwrap();
+        assert!(!output.status.success(), "{:?}", args);
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("Usage: ttc"));
+        assert!(stderr.contains("-o, --output <path>"));
+    }
+}
+
+#[test]
+fn test_emit_stdout() {
+    let outfile = temp_path("unused.c");
+    let output = ttc()
+        .args(["samples/hello.teeny", "--emit-stdout", "-o"])
+        .arg(&outfile)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("#include <stdio.h>\n"));
+    assert!(stdout.contains("int main(int argc, char *argv[]) {"));
+    assert!(!stdout.contains("Program compiled successfully"));
+    assert!(!outfile.exists());
+}
+
+#[test]
+fn test_emit_stdout_errors_go_to_stderr() {
+    let source = temp_path("bad.teeny");
+    std::fs::write(&source, "PRINT x\n").unwrap();
+
+    let output = ttc().arg(&source).arg("--emit-stdout").output().unwrap();
+    std::fs::remove_file(&source).unwrap();
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Undeclared variable: \"x\""));
+}
+
+#[test]
+fn test_reports_every_error() {
+    let source = temp_path("errors.teeny");
+    std::fs::write(&source, "PRINT x\nLET y = )\nGOTO nowhere\n").unwrap();
+
+    let output = ttc().arg(&source).arg("--emit-stdout").output().unwrap();
+    std::fs::remove_file(&source).unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let errors = stderr
+        .lines()
+        .filter(|line| line.starts_with(&format!("{}: error at ", source.display())))
+        .count();
+    assert_eq!(errors, 3);
+}
+
+#[test]
+fn test_max_errors() {
+    let source = temp_path("many-errors.teeny");
+    std::fs::write(&source, "PRINT )\n".repeat(30)).unwrap();
+
+    let output = ttc()
+        .arg(&source)
+        .args(["--emit-stdout", "--max-errors", "5"])
+        .output()
+        .unwrap();
+    std::fs::remove_file(&source).unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let errors = stderr
+        .lines()
+        .filter(|line| line.starts_with(&format!("{}: error at ", source.display())))
+        .count();
+    assert_eq!(errors, 5);
+    assert!(stderr.ends_with(&format!(
+        "{}: too many errors; aborting\n",
+        source.display()
+    )));
+}
+
+#[test]
+fn test_error_quotes_source_line() {
+    let source = temp_path("caret.teeny");
+    std::fs::write(&source, "LET a = 1\nPRINT a + b\n").unwrap();
+
+    let output = ttc().arg(&source).arg("--emit-stdout").output().unwrap();
+    std::fs::remove_file(&source).unwrap();
+
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "{}: error at 2:11: Undeclared variable: \"b\"\n  |\n2 | PRINT a + b\n  |           ^\n",
+            source.display()
+        )
+    );
+}
+
+#[test]
+fn test_warns_about_dead_code() {
+    let source = temp_path("dead.teeny");
+    std::fs::write(&source, "IF 0 > 1 THEN\nPRINT 1\nENDIF\n").unwrap();
+
+    let output = ttc().arg(&source).arg("--emit-stdout").output().unwrap();
+    std::fs::remove_file(&source).unwrap();
+
+    assert!(output.status.success());
+    assert!(!String::from_utf8(output.stdout).unwrap().contains("if"));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "{}: warning at 1:4: condition is always false, so its body is removed\n  |\n1 | IF 0 > 1 THEN\n  |    ^\n",
+            source.display()
+        )
+    );
+}
+
+#[test]
+fn test_werror() {
+    let source = temp_path("unused.teeny");
+    std::fs::write(&source, "LABEL unused\nPRINT 1\n").unwrap();
+
+    let output = ttc().arg(&source).arg("--emit-stdout").output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("warning at 1:1: label \"unused\" is never used"));
+
+    let output = ttc()
+        .arg(&source)
+        .args(["--emit-stdout", "--werror"])
+        .output()
+        .unwrap();
+    std::fs::remove_file(&source).unwrap();
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("warning at 1:1: label \"unused\" is never used"));
+}
+
+#[test]
+fn test_reports_type_errors() {
+    let source = temp_path("types.teeny");
+    std::fs::write(&source, "LET s = \"a\"\nPRINT s + 1\n").unwrap();
+
+    let output = ttc().arg(&source).arg("--emit-stdout").output().unwrap();
+    std::fs::remove_file(&source).unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("error at 2:9: type error: cannot apply \"+\" to string and int"));
+}
+
+#[test]
+fn test_js_backend() {
+    let output = ttc()
+        .args(["samples/hello.teeny", "--js", "--emit-stdout"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("console.log(\"Hello, world!\");\n"));
+
+    let source = temp_path("goto.teeny");
+    std::fs::write(&source, "LABEL top\nGOTO top\n").unwrap();
+
+    let output = ttc().arg(&source).arg("--js").output().unwrap();
+    std::fs::remove_file(&source).unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("LABEL top is unsupported in the JS backend"));
+}
+
+#[test]
+fn test_allow_raw() {
+    let source = temp_path("raw.teeny");
+    std::fs::write(&source, "EMIT \"puts(\\\"raw\\\");\"\n").unwrap();
+
+    let output = ttc().arg(&source).arg("--emit-stdout").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("error at 1:1: EMIT needs raw C to be allowed, with --allow-raw"));
+
+    let output = ttc()
+        .arg(&source)
+        .args(["--emit-stdout", "--allow-raw"])
+        .output()
+        .unwrap();
+    std::fs::remove_file(&source).unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("    puts(\"raw\");\n    return 0;\n"));
+}
+
+#[test]
+fn test_stats() {
+    let output = ttc()
+        .args(["samples/statements.teeny", "--emit-stdout", "--stats"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr, "statements: 3\nvariables: 0\nlabels: 1\ngotos: 1\n");
+}
+
+#[test]
+fn test_run() {
+    let mut child = ttc()
+        .args(["samples/factorial.teeny", "--run"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"4\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("Factorial of: \n4\n = \n24\n"));
+}
+
+#[test]
+fn test_float_precision() {
+    let source = temp_path("pi.teeny");
+    std::fs::write(&source, "PRINT 3.14159\n").unwrap();
+
+    let run = |args: &[&str]| {
+        let output = ttc().arg(&source).arg("--run").args(args).output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    // not cut short to a fixed number of decimal places by default
+    assert_eq!(run(&[]), "3.14159\n");
+    assert_eq!(run(&["--float-precision", "2"]), "3.14\n");
+    assert_eq!(run(&["--float-precision", "5"]), "3.14159\n");
+
+    let emit = |args: &[&str]| {
+        let output = ttc()
+            .arg(&source)
+            .arg("--emit-stdout")
+            .args(args)
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert!(emit(&[]).contains("printf(\"%g\\n\", 3.14159);"));
+    assert!(emit(&["--js"]).contains("console.log(String(3.14159));"));
+
+    assert!(
+        emit(&["--js", "--float-precision", "5"]).contains("console.log((3.14159).toFixed(5));")
+    );
+
+    let output = ttc()
+        .arg(&source)
+        .args(["--float-precision", "x"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    std::fs::remove_file(&source).unwrap();
+}
+
+#[test]
+fn test_func_name() {
+    let source = temp_path("func.teeny");
+    std::fs::write(&source, "PRINT 1\n").unwrap();
+
+    let output = ttc()
+        .arg(&source)
+        .args(["--emit-stdout", "--func-name", "run"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let code = String::from_utf8(output.stdout).unwrap();
+    assert!(code.contains("int run(void) {"));
+    assert!(!code.contains("main"));
+
+    let output = ttc()
+        .arg(&source)
+        .args(["--func-name", "2run"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    std::fs::remove_file(&source).unwrap();
+}
+
+#[test]
+fn test_dump_tokens() {
+    let source = temp_path("tokens.teeny");
+    std::fs::write(&source, "LET a = 1\nPRINT a\n").unwrap();
+
+    let output = ttc().arg(&source).arg("--dump-tokens").output().unwrap();
+    std::fs::remove_file(&source).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .collect::<Vec<_>>(),
+        vec![
+            "1:1 Let \"LET\"",
+            "1:5 Ident \"a\"",
+            "1:7 Eq \"=\"",
+            "1:9 Number \"1\"",
+            "1:10 Newline \"\\n\"",
+            "2:1 Print \"PRINT\"",
+            "2:7 Ident \"a\"",
+            "2:8 Newline \"\\n\"",
+            "3:1 Newline \"\\n\"",
+        ]
+    );
+}
+
+#[test]
+fn test_dump_tokens_json() {
+    let source = temp_path("tokens-json.teeny");
+    std::fs::write(&source, "PRINT \"a \\\"b\\\" \\\\ c\"\n").unwrap();
+
+    let output = ttc()
+        .arg(&source)
+        .arg("--dump-tokens=json")
+        .output()
+        .unwrap();
+    std::fs::remove_file(&source).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        r#"[
+  {"kind": "Print", "spelling": "PRINT", "line": 1, "col": 1},
+  {"kind": "String", "spelling": "a \"b\" \\ c", "line": 1, "col": 7},
+  {"kind": "Newline", "spelling": "\n", "line": 1, "col": 21},
+  {"kind": "Newline", "spelling": "\n", "line": 2, "col": 1}
+]
+"#
+    );
+}
+
+#[test]
+fn test_dump_ast() {
+    let source = temp_path("ast.teeny");
+    std::fs::write(&source, "LET x = 1 + 2 * 3\n").unwrap();
+
+    let output = ttc().arg(&source).arg("--dump-ast").output().unwrap();
+    std::fs::remove_file(&source).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "\
+Let x
+  Binary +
+    Number 1
+    Binary *
+      Number 2
+      Number 3
+"
+    );
+}
+
+#[test]
+fn test_fmt() {
+    let source = temp_path("messy.teeny");
+    std::fs::write(&source, "LET  a=1\nIF a>0 THEN\nPRINT a*(a+1)\nENDIF\n").unwrap();
+
+    let output = ttc().arg(&source).arg("--fmt").output().unwrap();
+    std::fs::remove_file(&source).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "LET a = 1\nIF a > 0 THEN\n    PRINT a * (a + 1)\nENDIF\n"
+    );
+}